@@ -43,6 +43,26 @@ layout(set = 1, binding = 0) uniform Locals {
     Material material;
 };
 
+// Per tile brightness, e.g. for fog of war.
+// The rect is the origin and size of the map in tiles.
+layout(set = 2, binding = 0) uniform Visibility {
+    vec4 u_VisibilityRect;
+};
+layout(set = 2, binding = 1) uniform texture2D t_Visibility;
+layout(set = 2, binding = 2) uniform sampler s_Visibility;
+
+float fVisibility(vec3 world_pos) {
+    // No map means everything is visible
+    if (u_VisibilityRect.z <= 0.0 || u_VisibilityRect.w <= 0.0) {
+        return 1.0;
+    }
+    vec2 tile = floor(world_pos.xy + 0.5) - u_VisibilityRect.xy;
+    if (any(lessThan(tile, vec2(0.0))) || any(greaterThanEqual(tile, u_VisibilityRect.zw))) {
+        return 0.0;
+    }
+    return textureLod(sampler2D(t_Visibility, s_Visibility), (tile + 0.5) / u_VisibilityRect.zw, 0.0).r;
+}
+
 
 // The following color space functions are from
 // http://www.chilliant.com/rgb2hsv.html
@@ -268,5 +288,5 @@ void main() {
     //color = HCYtoRGB(color);
 
     //o_Target = vec4(color.rgb, 1.0);
-    o_Target = diffuse;
+    o_Target = vec4(diffuse.rgb * fVisibility(v_FragPos.xyz), diffuse.a);
 }
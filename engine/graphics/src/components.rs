@@ -22,6 +22,9 @@ pub struct Target {
     pub entity: Entity,
 }
 
+/// Models of entities with this component are not drawn
+pub struct Hidden;
+
 #[derive(Clone)]
 pub struct DynamicModel {
     pub idx: ModelID,
@@ -38,6 +38,14 @@ pub struct GlobalUniforms {
     pub eye_position: [f32; 4],
 }
 
+/// Where the visibility map lies in the world, in tiles.
+/// A zero sized rectangle means that everything is visible.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Pod, Zeroable)]
+pub struct VisibilityUniforms {
+    pub rect: [f32; 4],
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Pod, Zeroable)]
 pub struct Material {
@@ -3,7 +3,7 @@ use itertools::Itertools;
 use wgpu::util::DeviceExt;
 
 use crate::components::{Camera, DynamicModel, StaticModel};
-use crate::data::{GlobalUniforms, LocalUniforms};
+use crate::data::{GlobalUniforms, LocalUniforms, VisibilityUniforms};
 use crate::{GraphicsContext, GraphicsResources, RenderContext, TextureID};
 
 // TODO: Have ass_man auto-load all Shaders
@@ -47,10 +47,198 @@ impl ModelQueue {
     }
 }
 
+/// A per tile brightness map that the fragment shader multiplies into the
+/// color of everything drawn on top of it. Used to hide and dim parts of the map.
+struct VisibilityMap {
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform_buf: wgpu::Buffer,
+    texture: wgpu::Texture,
+    texture_size: wgpu::Extent3d,
+    sampler: wgpu::Sampler,
+}
+
+impl VisibilityMap {
+    fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Visibility Bind Group Layout -- Models"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler {
+                        filtering: true,
+                        comparison: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let uniform_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Visibility Uniforms"),
+            contents: bytemuck::bytes_of(&VisibilityUniforms::default()),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: None,
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture_size = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth: 1,
+        };
+        let texture = Self::create_texture(device, texture_size);
+        let bind_group =
+            Self::create_bind_group(device, &bind_group_layout, &uniform_buf, &texture, &sampler);
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            uniform_buf,
+            texture,
+            texture_size,
+            sampler,
+        }
+    }
+
+    fn create_texture(device: &wgpu::Device, size: wgpu::Extent3d) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Visibility Map"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buf: &wgpu::Buffer,
+        texture: &wgpu::Texture,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: uniform_buf,
+                        offset: 0,
+                        size: None,
+                    },
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    fn set(
+        &mut self,
+        graphics_context: &GraphicsContext,
+        origin: [i32; 2],
+        size: [u32; 2],
+        visibility: &[u8],
+    ) {
+        assert_eq!(visibility.len(), (size[0] * size[1]) as usize);
+
+        let texture_size = wgpu::Extent3d {
+            width: size[0].max(1),
+            height: size[1].max(1),
+            depth: 1,
+        };
+        if texture_size != self.texture_size {
+            self.texture = Self::create_texture(&graphics_context.device, texture_size);
+            self.texture_size = texture_size;
+            self.bind_group = Self::create_bind_group(
+                &graphics_context.device,
+                &self.bind_group_layout,
+                &self.uniform_buf,
+                &self.texture,
+                &self.sampler,
+            );
+        }
+
+        if !visibility.is_empty() {
+            graphics_context.queue.write_texture(
+                wgpu::TextureCopyView {
+                    texture: &self.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                visibility,
+                wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: size[0],
+                    rows_per_image: size[1],
+                },
+                texture_size,
+            );
+        }
+
+        graphics_context.queue.write_buffer(
+            &self.uniform_buf,
+            0,
+            bytemuck::bytes_of(&VisibilityUniforms {
+                rect: [
+                    origin[0] as f32,
+                    origin[1] as f32,
+                    size[0] as f32,
+                    size[1] as f32,
+                ],
+            }),
+        );
+    }
+}
+
 pub struct ModelRenderPipeline {
     global_uniform_buf: wgpu::Buffer,
     global_bind_group: wgpu::BindGroup,
     pub(crate) local_bind_group_layout: wgpu::BindGroupLayout,
+    visibility_map: VisibilityMap,
     static_pipeline: wgpu::RenderPipeline,
     dynamic_pipeline: wgpu::RenderPipeline,
     _pipeline_layout: wgpu::PipelineLayout,
@@ -168,9 +356,15 @@ impl ModelRenderPipeline {
         let dynamic_vs_module = graphics_resources.shaders.get("forward.vert").unwrap();
         let fs_module = graphics_resources.shaders.get("forward.frag").unwrap();
 
+        let visibility_map = VisibilityMap::new(device);
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Model Render Pipeline Layout"),
-            bind_group_layouts: &[&global_bind_group_layout, &local_bind_group_layout],
+            bind_group_layouts: &[
+                &global_bind_group_layout,
+                &local_bind_group_layout,
+                &visibility_map.bind_group_layout,
+            ],
             push_constant_ranges: &[],
         });
 
@@ -184,6 +378,7 @@ impl ModelRenderPipeline {
             global_uniform_buf,
             global_bind_group,
             local_bind_group_layout,
+            visibility_map,
             static_pipeline,
             dynamic_pipeline,
             _pipeline_layout: pipeline_layout,
@@ -234,6 +429,7 @@ impl ModelRenderPipeline {
 
         render_pass.set_pipeline(&self.static_pipeline);
         render_pass.set_bind_group(0, &self.global_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.visibility_map.bind_group, &[]);
 
         // render static meshes
         for model in &model_queue.static_models {
@@ -289,6 +485,7 @@ impl ModelRenderPipeline {
 
         render_pass.set_pipeline(&self.dynamic_pipeline);
         render_pass.set_bind_group(0, &self.global_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.visibility_map.bind_group, &[]);
 
         // render dynamic meshes
         for (model, _) in model_queue.dynamic_models.iter() {
@@ -334,6 +531,26 @@ impl ModelRenderPipeline {
         );
     }
 
+    /// Sets how visible each tile of the map is, from 0 (hidden) to 255 (fully visible).
+    /// `visibility` is laid out row by row, starting at the tile at `origin`.
+    /// Anything outside of the map is hidden.
+    pub fn set_visibility_map(
+        &mut self,
+        graphics_context: &GraphicsContext,
+        origin: [i32; 2],
+        size: [u32; 2],
+        visibility: &[u8],
+    ) {
+        self.visibility_map
+            .set(graphics_context, origin, size, visibility);
+    }
+
+    /// Makes everything visible again
+    pub fn clear_visibility_map(&mut self, graphics_context: &GraphicsContext) {
+        self.visibility_map
+            .set(graphics_context, [0, 0], [0, 0], &[]);
+    }
+
     fn create_depth_view(
         device: &wgpu::Device,
         size: winit::dpi::PhysicalSize<u32>,
@@ -1,11 +1,11 @@
 use debug::DebugTimer;
 use legion::systems::Runnable;
-use legion::{IntoQuery, SystemBuilder};
+use legion::{component, IntoQuery, SystemBuilder};
 use transforms::{Position, Transform};
 use winit::window::Window;
 
 use crate::canvas::{CanvasQueue, CanvasRenderPipeline};
-use crate::components::{ActiveCamera, Camera, DynamicModel, StaticModel, Target};
+use crate::components::{ActiveCamera, Camera, DynamicModel, Hidden, StaticModel, Target};
 use crate::data::{LocalUniforms, Material};
 use crate::gui::GuiRenderPipeline;
 use crate::models::{ModelQueue, ModelRenderPipeline};
@@ -58,7 +58,7 @@ fn render_draw_models_system() -> impl Runnable {
         .read_component::<DynamicModel>()
         .read_component::<Transform>()
         .write_resource::<ModelQueue>()
-        .with_query(<(&DynamicModel, &Transform)>::query())
+        .with_query(<(&DynamicModel, &Transform)>::query().filter(!component::<Hidden>()))
         .build(move |_, world, model_queue, query| {
             query.for_each_mut(world, |(model, transform)| {
                 draw_model(model, transform, model_queue);
@@ -231,6 +231,15 @@ pub enum Command {
 
     PlayerClickToMove,
     PlayerOrbitCamera,
+    PlayerAttack,
+    PlayerShoot,
+    PlayerUseItem,
+    PlayerEquipItem,
+    PlayerDropItem,
+    PlayerMoveItem,
+    PlayerSplitItem,
+    PlayerUnequipItem,
+    PlayerInteract,
 }
 
 pub type KeyBinding = dyn Fn(&InputState, bool) -> bool + Send + Sync;
@@ -318,6 +327,15 @@ impl CommandManager {
             MouseButton::Right,
             ButtonStatus::Down,
         );
+        ret.simple_key_bind(Command::PlayerAttack, Key::Space, ButtonStatus::Pressed);
+        ret.simple_key_bind(Command::PlayerShoot, Key::A, ButtonStatus::Pressed);
+        ret.simple_key_bind(Command::PlayerUseItem, Key::Q, ButtonStatus::Pressed);
+        ret.simple_key_bind(Command::PlayerEquipItem, Key::W, ButtonStatus::Pressed);
+        ret.simple_key_bind(Command::PlayerDropItem, Key::G, ButtonStatus::Pressed);
+        ret.simple_key_bind(Command::PlayerMoveItem, Key::Z, ButtonStatus::Pressed);
+        ret.simple_key_bind(Command::PlayerSplitItem, Key::X, ButtonStatus::Pressed);
+        ret.simple_key_bind(Command::PlayerUnequipItem, Key::C, ButtonStatus::Pressed);
+        ret.simple_key_bind(Command::PlayerInteract, Key::R, ButtonStatus::Pressed);
 
         ret.key_toggle(
             Command::DebugToggleSnake,
@@ -45,6 +45,33 @@ impl PhysicsBuilderExtender for Builder {
     }
 }
 
+/// Takes the bodies and colliders of the entities out of the physics world.
+/// The entities keep their `PhysicsBody` and `Collider`, so they get new ones
+/// if they are ever put back into play.
+pub fn release_physics(world: &mut World, resources: &mut Resources, entities: &[Entity]) {
+    let mut physics = resources
+        .get_mut::<PhysicsResource>()
+        .expect("The physics systems have not been added");
+    for &entity in entities {
+        let mut entry = match world.entry(entity) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let collider = entry
+            .get_component::<ColliderHandle>()
+            .map(|handle| handle.0);
+        if let Ok(handle) = collider {
+            physics.colliders.remove(handle);
+            entry.remove_component::<ColliderHandle>();
+        }
+        let body = entry.get_component::<BodyHandle>().map(|handle| handle.0);
+        if let Ok(handle) = body {
+            physics.bodies.remove(handle);
+            entry.remove_component::<BodyHandle>();
+        }
+    }
+}
+
 struct PhysicsResource {
     mechanical_world: DefaultMechanicalWorld<f32>,
     geometrical_world: DefaultGeometricalWorld<f32>,
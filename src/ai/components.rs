@@ -0,0 +1,134 @@
+use cgmath::{InnerSpace, Vector2};
+use legion::Entity;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AIState {
+    Idle,
+    Wander,
+    Chase,
+    Attack,
+    Flee,
+}
+
+/// How an agent regards whatever it has perceived
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Disposition {
+    Hostile,
+    Neutral,
+    Allied,
+}
+
+/// What an agent knows about its target this frame
+#[derive(Copy, Clone, Debug)]
+pub struct Sighting {
+    pub target: Entity,
+    // From the agent to the target
+    pub offset: Vector2<f32>,
+}
+
+impl Sighting {
+    pub fn distance(&self) -> f32 { self.offset.magnitude() }
+}
+
+pub struct AIBrain {
+    pub state: AIState,
+    // Whoever the agent is currently paying attention to
+    pub target: Option<Entity>,
+    // Where the agent wanders around when it has nothing better to do
+    pub home: Vector2<f32>,
+    // Distance at which a hostile target is noticed
+    pub aggro_range: f32,
+    // Distance at which an engaged agent gives up on its target
+    pub leash_range: f32,
+    pub attack_range: f32,
+    // Fraction of max health below which the agent runs away
+    pub flee_threshold: f32,
+    pub wander_radius: f32,
+    // Seconds left until the agent reconsiders idling or wandering
+    pub timer: f32,
+}
+
+impl AIBrain {
+    pub fn monster(home: Vector2<f32>) -> Self {
+        Self {
+            state: AIState::Idle,
+            target: None,
+            home,
+            aggro_range: 6.0,
+            leash_range: 10.0,
+            attack_range: 0.9,
+            flee_threshold: 0.25,
+            wander_radius: 2.0,
+            timer: 0.0,
+        }
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        matches!(self.state, AIState::Chase | AIState::Attack | AIState::Flee)
+    }
+
+    /// Distance at which targets are noticed, or kept track of once engaged
+    pub fn notice_range(&self) -> f32 {
+        if self.is_engaged() {
+            self.leash_range
+        } else {
+            self.aggro_range
+        }
+    }
+
+    /// Scores every state by how useful it is right now and returns the best one.
+    /// `health` is the fraction of max hit points the agent has left.
+    pub fn decide(
+        &self,
+        sighting: Option<&Sighting>,
+        disposition: Disposition,
+        health: f32,
+    ) -> AIState {
+        let distance = sighting.map_or(f32::INFINITY, |sighting| sighting.distance());
+        let notice_range = self.notice_range();
+
+        // Wandering goes on until its timer runs out, and starts again once it has
+        let keep_wandering = self.state == AIState::Wander && self.timer > 0.0;
+        let start_wandering = self.state != AIState::Wander && self.timer <= 0.0;
+
+        let utility = |state: AIState| -> f32 {
+            match (state, disposition) {
+                (AIState::Flee, Disposition::Hostile)
+                    if health < self.flee_threshold && distance < self.leash_range =>
+                {
+                    1.0
+                }
+                (AIState::Attack, Disposition::Hostile) if distance <= self.attack_range => 0.9,
+                (AIState::Chase, Disposition::Hostile) if distance <= notice_range => 0.7,
+                // Allies tag along once they lag behind
+                (AIState::Chase, Disposition::Allied)
+                    if distance > 2.0 * self.attack_range && distance <= self.leash_range =>
+                {
+                    0.7
+                }
+                (AIState::Wander, _) if keep_wandering || start_wandering => 0.2,
+                (AIState::Idle, _) => 0.1,
+                _ => 0.0,
+            }
+        };
+
+        [
+            AIState::Flee,
+            AIState::Attack,
+            AIState::Chase,
+            AIState::Wander,
+            AIState::Idle,
+        ]
+        .iter()
+        .copied()
+        .fold((AIState::Idle, 0.0), |best, state| {
+            let score = utility(state);
+            if score > best.1 {
+                (state, score)
+            } else {
+                best
+            }
+        })
+        .0
+    }
+}
@@ -0,0 +1,210 @@
+use application::UnitStage;
+use cgmath::{InnerSpace, Vector2};
+use entity_smith::FrameTime;
+use legion::systems::{Builder, CommandBuffer, ParallelRunnable};
+use legion::world::SubWorld;
+use legion::{component, Entity, EntityStore, IntoQuery, SystemBuilder};
+use rand::prelude::*;
+use transforms::Position;
+
+use crate::ai::components::{AIBrain, AIState, Disposition, Sighting};
+use crate::combat::components::{AttackCommand, Corpse};
+use crate::components::{AIFollow, Destination, HitPoints, Player};
+use crate::factions::{Faction, FactionRegistry};
+use crate::perception::fov::can_see;
+use crate::systems::ai_follow_system;
+use crate::world_gen::components::FloorTiles;
+
+pub struct AIUnit;
+
+impl application::Unit for AIUnit {
+    fn add_systems(&self, stage: UnitStage, builder: &mut Builder) {
+        if let UnitStage::Logic = stage {
+            builder
+                .add_system(ai_decision_system())
+                .add_system(ai_follow_system());
+        }
+    }
+}
+
+fn ai_decision_system() -> impl ParallelRunnable {
+    SystemBuilder::new("ai_decision")
+        .read_component::<Position>()
+        .read_component::<Faction>()
+        .read_component::<HitPoints>()
+        .read_component::<Corpse>()
+        .write_component::<AIBrain>()
+        .read_resource::<Player>()
+        .read_resource::<FrameTime>()
+        .read_resource::<FloorTiles>()
+        .read_resource::<FactionRegistry>()
+        .build(
+            move |cmd, world, (player, frame_time, floor_tiles, factions), _| {
+                ai_decision(world, cmd, player, frame_time, floor_tiles, factions);
+            },
+        )
+}
+
+/// Someone an agent might take an interest in
+struct Candidate {
+    entity: Entity,
+    position: Vector2<f32>,
+    faction: Option<Faction>,
+}
+
+pub fn ai_decision(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    player: &Player,
+    frame_time: &FrameTime,
+    floor_tiles: &FloorTiles,
+    factions: &FactionRegistry,
+) {
+    let mut rng = thread_rng();
+
+    let (mut brain_world, world) = world.split::<&mut AIBrain>();
+
+    let candidates = <(Entity, &Position, &HitPoints, Option<&Faction>)>::query()
+        .filter(!component::<Corpse>())
+        .iter(&world)
+        .filter(|(_, _, hp, _)| hp.health > 0.0)
+        .map(|(entity, position, _, faction)| Candidate {
+            entity: *entity,
+            position: position.0.truncate(),
+            faction: faction.copied(),
+        })
+        .collect::<Vec<_>>();
+    let player_candidate = candidates
+        .iter()
+        .find(|candidate| candidate.entity == player.player);
+
+    for (entity, brain) in <(Entity, &mut AIBrain)>::query().iter_mut(&mut brain_world) {
+        let entry = match world.entry_ref(*entity) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let position = match entry.get_component::<Position>() {
+            Ok(pos) => pos.0.truncate(),
+            Err(_) => continue,
+        };
+        let health = entry
+            .get_component::<HitPoints>()
+            .map_or(1.0, |hp| hp.health / hp.max);
+
+        // The dead don't think
+        if health <= 0.0 {
+            continue;
+        }
+
+        let faction = entry.get_component::<Faction>().ok().copied();
+        // Noticing someone takes line of sight, but once engaged
+        // the agent keeps track of its target until it is out of leash range.
+        let is_noticed = |sighting: &Sighting| {
+            sighting.distance() <= brain.notice_range()
+                && ((brain.is_engaged() && brain.target == Some(sighting.target))
+                    || can_see(floor_tiles, position, position + sighting.offset))
+        };
+        let regards =
+            |theirs: Option<Faction>, relation: fn(&FactionRegistry, Faction, Faction) -> bool| {
+                match (faction, theirs) {
+                    (Some(ours), Some(theirs)) => relation(factions, ours, theirs),
+                    _ => false,
+                }
+            };
+        let sighting_of = |candidate: &Candidate| Sighting {
+            target: candidate.entity,
+            offset: candidate.position - position,
+        };
+
+        let nearest_enemy = candidates
+            .iter()
+            .filter(|candidate| candidate.entity != *entity)
+            .filter(|candidate| regards(candidate.faction, FactionRegistry::is_hostile))
+            .map(sighting_of)
+            .filter(is_noticed)
+            .min_by(|a, b| a.distance().partial_cmp(&b.distance()).unwrap());
+
+        // Without enemies around, allies of the player tag along with them
+        let (sighting, disposition) = match (nearest_enemy, player_candidate) {
+            (Some(enemy), _) => (Some(enemy), Disposition::Hostile),
+            (None, Some(player_candidate))
+                if player_candidate.entity != *entity
+                    && regards(player_candidate.faction, FactionRegistry::is_allied) =>
+            {
+                (
+                    Some(sighting_of(player_candidate)).filter(is_noticed),
+                    Disposition::Allied,
+                )
+            }
+            _ => (None, Disposition::Neutral),
+        };
+
+        brain.timer -= frame_time.0;
+
+        let next_state = brain.decide(sighting.as_ref(), disposition, health);
+        let next_target = sighting.map(|sighting| sighting.target);
+        let retargeted = next_state == AIState::Chase && next_target != brain.target;
+        brain.target = next_target;
+
+        if next_state != brain.state || retargeted {
+            enter_state(
+                commands, &mut rng, *entity, brain, next_state, position, sighting,
+            );
+        }
+
+        // Fleeing needs a fresh heading every frame
+        if let (AIState::Flee, Some(sighting)) = (brain.state, sighting) {
+            let away = -sighting.offset.normalize_to(2.0 * brain.wander_radius);
+            commands.add_component(*entity, Destination::simple(position + away));
+        }
+
+        // Keep swinging for as long as the target is in reach
+        if let (AIState::Attack, Some(sighting)) = (brain.state, sighting) {
+            commands.add_component(*entity, AttackCommand::towards(sighting.offset));
+        }
+    }
+}
+
+fn enter_state(
+    commands: &mut CommandBuffer,
+    rng: &mut ThreadRng,
+    entity: Entity,
+    brain: &mut AIBrain,
+    state: AIState,
+    position: Vector2<f32>,
+    sighting: Option<Sighting>,
+) {
+    brain.state = state;
+
+    if state != AIState::Chase {
+        commands.remove_component::<AIFollow>(entity);
+    }
+
+    match state {
+        AIState::Idle | AIState::Attack => {
+            // Stopping is done by heading for where we already are
+            commands.add_component(entity, Destination::simple(position));
+            brain.timer = rng.gen_range(1.0..4.0);
+        }
+        AIState::Wander => {
+            let offset = Vector2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            commands.add_component(
+                entity,
+                Destination::simple(brain.home + offset * brain.wander_radius),
+            );
+            brain.timer = rng.gen_range(2.0..5.0);
+        }
+        AIState::Chase => {
+            if let Some(sighting) = sighting {
+                commands.add_component(
+                    entity,
+                    AIFollow {
+                        target: sighting.target,
+                        minimum_distance: 0.8 * brain.attack_range,
+                    },
+                );
+            }
+        }
+        AIState::Flee => {}
+    }
+}
@@ -0,0 +1,210 @@
+use cgmath::{InnerSpace, Vector2};
+use legion::Entity;
+
+use crate::factions::Faction;
+use crate::stats::components::{EffectKind, StatusEffect};
+
+/// Swings at everything within `reach` in a cone in front of the wielder
+pub struct MeleeAttack {
+    pub damage: f32,
+    pub reach: f32,
+    // Cosine of half the cone's angle, targets further off to the side are missed
+    pub arc: f32,
+    // Speed the target is sent flying away with
+    pub knockback: f32,
+    // Seconds between swings
+    pub cooldown: f32,
+    pub ready_in: f32,
+    pub inflicts: Option<StatusEffect>,
+}
+
+impl MeleeAttack {
+    pub fn new(damage: f32, reach: f32, knockback: f32, cooldown: f32) -> Self {
+        Self {
+            damage,
+            reach,
+            arc: 0.5,
+            knockback,
+            cooldown,
+            ready_in: 0.0,
+            inflicts: None,
+        }
+    }
+
+    pub fn inflicting(self, effect: StatusEffect) -> Self {
+        Self {
+            inflicts: Some(effect),
+            ..self
+        }
+    }
+
+    pub fn is_ready(&self) -> bool { self.ready_in <= 0.0 }
+}
+
+/// Orders an entity to swing its weapon this frame
+pub struct AttackCommand {
+    pub direction: Vector2<f32>,
+}
+
+impl AttackCommand {
+    pub fn towards(offset: Vector2<f32>) -> Self {
+        Self {
+            direction: if offset.magnitude2() > 0.0 {
+                offset.normalize()
+            } else {
+                Vector2::unit_x()
+            },
+        }
+    }
+}
+
+/// A hit waiting to be dealt to `target`
+pub struct Damage {
+    pub target: Entity,
+    pub source: Option<Entity>,
+    // The side the attack came from, who the target's faction will hold a grudge against
+    pub attacker: Option<Faction>,
+    pub amount: f32,
+    pub knockback: Vector2<f32>,
+    pub effect: Option<StatusEffect>,
+}
+
+/// Every hit landed this frame, drained when damage is applied
+#[derive(Default)]
+pub struct DamageQueue(pub Vec<Damage>);
+
+impl DamageQueue {
+    pub fn push(&mut self, damage: Damage) { self.0.push(damage); }
+}
+
+/// Seconds left until the entity can be hurt again
+pub struct Invulnerable(pub f32);
+
+/// Whoever landed the latest hit, and gets the credit if it was the last one
+pub struct LastHitBy(pub Entity);
+
+/// What becomes of an entity once its hit points run out.
+/// Entities without it are despawned.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub enum OnDeath {
+    Corpse,
+}
+
+/// The remains of something that died and no longer takes part in the fight
+pub struct Corpse;
+
+/// Fires projectiles in the direction of a `ShootCommand`
+pub struct RangedAttack {
+    pub projectile: ProjectileKind,
+    // Seconds between shots
+    pub cooldown: f32,
+    pub ready_in: f32,
+}
+
+impl RangedAttack {
+    pub fn new(projectile: ProjectileKind, cooldown: f32) -> Self {
+        Self {
+            projectile,
+            cooldown,
+            ready_in: 0.0,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool { self.ready_in <= 0.0 }
+}
+
+/// Orders an entity to fire its ranged weapon this frame
+pub struct ShootCommand {
+    pub direction: Vector2<f32>,
+}
+
+impl ShootCommand {
+    pub fn towards(offset: Vector2<f32>) -> Self {
+        Self {
+            direction: AttackCommand::towards(offset).direction,
+        }
+    }
+}
+
+/// What a projectile does when it runs into things
+#[derive(Clone)]
+pub struct ProjectileBehaviour {
+    // Number of targets it passes through before stopping
+    pub pierce: u32,
+    // Number of times it bounces off walls before stopping
+    pub bounces: u32,
+    // Everything within this distance of where it stops gets hurt as well
+    pub blast_radius: f32,
+    pub knockback: f32,
+}
+
+/// Everything needed to launch a projectile
+#[derive(Clone)]
+pub struct ProjectileKind {
+    pub damage: f32,
+    pub speed: f32,
+    // Seconds before it fizzles out
+    pub lifetime: f32,
+    pub radius: f32,
+    pub model: String,
+    pub behaviour: ProjectileBehaviour,
+    pub inflicts: Option<StatusEffect>,
+}
+
+impl ProjectileKind {
+    /// A quick, numbing bolt that passes through one target and ricochets off walls
+    pub fn bolt() -> Self {
+        Self {
+            damage: 1.5,
+            speed: 12.0,
+            lifetime: 1.5,
+            radius: 0.1,
+            model: "sphere.obj".to_string(),
+            behaviour: ProjectileBehaviour {
+                pierce: 1,
+                bounces: 2,
+                blast_radius: 0.0,
+                knockback: 1.0,
+            },
+            inflicts: Some(StatusEffect::new(EffectKind::Slow, 0.5, 2.0)),
+        }
+    }
+}
+
+pub struct Projectile {
+    pub owner: Entity,
+    // Projectiles never hurt anyone on their owner's side
+    pub faction: Option<Faction>,
+    pub damage: f32,
+    pub radius: f32,
+    pub lifetime: f32,
+    pub behaviour: ProjectileBehaviour,
+    pub inflicts: Option<StatusEffect>,
+    // Where it was last frame, hits are looked for along the path from there
+    pub(crate) previous: Vector2<f32>,
+    pub(crate) already_hit: Vec<Entity>,
+}
+
+impl Projectile {
+    pub fn new(
+        kind: &ProjectileKind,
+        owner: Entity,
+        faction: Option<Faction>,
+        origin: Vector2<f32>,
+    ) -> Self {
+        Self {
+            owner,
+            faction,
+            damage: kind.damage,
+            radius: kind.radius,
+            lifetime: kind.lifetime,
+            behaviour: kind.behaviour.clone(),
+            inflicts: kind.inflicts,
+            previous: origin,
+            already_hit: Vec::new(),
+        }
+    }
+}
+
+/// A projectile that has stopped and is waiting for its physics body to be released
+pub struct Spent;
@@ -0,0 +1,43 @@
+use assman::components::DynamicModelRequest;
+use cgmath::Vector2;
+use entity_smith::EntitySmith;
+use legion::Entity;
+use physics::PhysicsEntitySmith;
+use transforms::{Scale, TransformEntitySmith};
+
+use crate::combat::components::{Projectile, ProjectileKind};
+use crate::factions::Faction;
+use crate::perception::components::ObscuredByFog;
+
+pub trait ProjectileEntitySmith {
+    fn projectile(
+        &mut self,
+        kind: &ProjectileKind,
+        owner: Entity,
+        faction: Option<Faction>,
+        origin: Vector2<f32>,
+        direction: Vector2<f32>,
+    ) -> &mut Self;
+}
+
+impl<'a> ProjectileEntitySmith for EntitySmith<'a> {
+    fn projectile(
+        &mut self,
+        kind: &ProjectileKind,
+        owner: Entity,
+        faction: Option<Faction>,
+        origin: Vector2<f32>,
+        direction: Vector2<f32>,
+    ) -> &mut Self {
+        // The body has no collider, so the physics world moves it without it shoving anyone.
+        // Hits are found by tracing its path instead.
+        self.pos(origin)
+            .orientation(0.0)
+            .velocity(direction * kind.speed)
+            .dynamic_body(0.1)
+            .any(Projectile::new(kind, owner, faction, origin))
+            .any(DynamicModelRequest::new(&kind.model))
+            .any(Scale(kind.radius))
+            .any(ObscuredByFog)
+    }
+}
@@ -0,0 +1,682 @@
+use std::collections::HashSet;
+
+use application::UnitStage;
+use cgmath::num_traits::clamp;
+use cgmath::{InnerSpace, Vector2};
+use entity_smith::{FrameTime, Smith};
+use legion::systems::{Builder, CommandBuffer, ParallelRunnable};
+use legion::world::SubWorld;
+use legion::{component, Entity, EntityStore, IntoQuery, Resources, SystemBuilder, World};
+use physics::{BodyHandle, Collider, PhysicsBody, Velocity};
+use transforms::Position;
+
+use crate::ai::components::AIBrain;
+use crate::combat::components::{
+    AttackCommand, Corpse, Damage, DamageQueue, Invulnerable, LastHitBy, MeleeAttack, OnDeath,
+    Projectile, RangedAttack, ShootCommand, Spent,
+};
+use crate::combat::ProjectileEntitySmith;
+use crate::components::{AIFollow, Destination, HitPoints, Player};
+use crate::factions::{Faction, FactionRegistry};
+use crate::items::inventory::{Equipment, Inventory};
+use crate::perception::components::FogOfWar;
+use crate::perception::fov::tile_at;
+use crate::stats::components::{Attributes, DerivedStats, ExperienceReward, Level, StatusEffects};
+use crate::stats::config::StatsConfig;
+use crate::world_gen::components::{FloorTiles, MapTransition};
+use crate::world_gen::seed::RunSeed;
+
+// Seconds an entity is left alone after being hit
+const INVULNERABILITY_TIME: f32 = 0.4;
+// Roughly how long a knocked back entity slides before catching itself
+const KNOCKBACK_TIME: f32 = 0.2;
+
+pub struct CombatUnit;
+
+impl application::Unit for CombatUnit {
+    fn load_resources(&self, _: &mut World, resources: &mut Resources) {
+        resources.insert(DamageQueue::default());
+    }
+    fn add_systems(&self, stage: UnitStage, builder: &mut Builder) {
+        if let UnitStage::Logic = stage {
+            builder
+                .add_system(melee_attack_system())
+                .add_system(ranged_attack_system())
+                .add_system(projectile_system())
+                .add_system(apply_damage_system())
+                // Death needs to know who landed the final blow
+                .flush()
+                .add_system(invulnerability_system())
+                .add_system(death_system());
+        }
+    }
+}
+
+fn melee_attack_system() -> impl ParallelRunnable {
+    SystemBuilder::new("melee_attack")
+        .read_component::<AttackCommand>()
+        .read_component::<Position>()
+        .read_component::<Faction>()
+        .read_component::<Collider>()
+        .read_component::<HitPoints>()
+        .read_component::<Corpse>()
+        .read_component::<DerivedStats>()
+        .write_component::<MeleeAttack>()
+        .read_resource::<FrameTime>()
+        .read_resource::<FactionRegistry>()
+        .write_resource::<DamageQueue>()
+        .build(move |cmd, world, (frame_time, factions, damage_queue), _| {
+            melee_attack(world, cmd, frame_time, factions, damage_queue);
+        })
+}
+
+pub fn melee_attack(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    frame_time: &FrameTime,
+    factions: &FactionRegistry,
+    damage_queue: &mut DamageQueue,
+) {
+    let (mut weapon_world, world) = world.split::<&mut MeleeAttack>();
+
+    for (entity, weapon) in <(Entity, &mut MeleeAttack)>::query().iter_mut(&mut weapon_world) {
+        weapon.ready_in = (weapon.ready_in - frame_time.0).max(0.0);
+
+        let entry = match world.entry_ref(*entity) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let direction = match entry.get_component::<AttackCommand>() {
+            Ok(attack) => attack.direction,
+            Err(_) => continue,
+        };
+        commands.remove_component::<AttackCommand>(*entity);
+
+        let position = match entry.get_component::<Position>() {
+            Ok(pos) => pos.0.truncate(),
+            Err(_) => continue,
+        };
+        let stunned = matches!(entry.get_component::<DerivedStats>(), Ok(stats) if stats.stunned);
+        if !weapon.is_ready() || stunned {
+            continue;
+        }
+        weapon.ready_in = weapon.cooldown;
+
+        let faction = entry.get_component::<Faction>().ok().copied();
+
+        for (target, target_position, target_faction, collider) in
+            <(Entity, &Position, Option<&Faction>, Option<&Collider>)>::query()
+                .filter(component::<HitPoints>() & !component::<Corpse>())
+                .iter(&world)
+        {
+            // No friendly fire
+            if target == entity || factions.spares(faction, target_faction.copied()) {
+                continue;
+            }
+
+            let offset: Vector2<f32> = target_position.0.truncate() - position;
+            let distance = offset.magnitude();
+            let radius = match collider {
+                Some(Collider::Circle { radius }) => *radius,
+                _ => 0.0,
+            };
+            let in_front =
+                distance <= f32::EPSILON || offset.dot(direction) / distance >= weapon.arc;
+
+            if distance - radius <= weapon.reach && in_front {
+                damage_queue.push(Damage {
+                    target: *target,
+                    source: Some(*entity),
+                    attacker: faction,
+                    amount: weapon.damage,
+                    effect: weapon.inflicts,
+                    knockback: if distance > f32::EPSILON {
+                        offset.normalize_to(weapon.knockback)
+                    } else {
+                        direction * weapon.knockback
+                    },
+                });
+            }
+        }
+    }
+}
+
+fn ranged_attack_system() -> impl ParallelRunnable {
+    SystemBuilder::new("ranged_attack")
+        .read_component::<ShootCommand>()
+        .read_component::<Position>()
+        .read_component::<Faction>()
+        .read_component::<DerivedStats>()
+        .write_component::<RangedAttack>()
+        .read_resource::<FrameTime>()
+        .build(move |cmd, world, frame_time, _| {
+            ranged_attack(world, cmd, frame_time);
+        })
+}
+
+pub fn ranged_attack(world: &mut SubWorld, commands: &mut CommandBuffer, frame_time: &FrameTime) {
+    let (mut weapon_world, world) = world.split::<&mut RangedAttack>();
+
+    for (entity, weapon) in <(Entity, &mut RangedAttack)>::query().iter_mut(&mut weapon_world) {
+        weapon.ready_in = (weapon.ready_in - frame_time.0).max(0.0);
+
+        let entry = match world.entry_ref(*entity) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let direction = match entry.get_component::<ShootCommand>() {
+            Ok(shot) => shot.direction,
+            Err(_) => continue,
+        };
+        commands.remove_component::<ShootCommand>(*entity);
+
+        let position = match entry.get_component::<Position>() {
+            Ok(pos) => pos.0.truncate(),
+            Err(_) => continue,
+        };
+        let stunned = matches!(entry.get_component::<DerivedStats>(), Ok(stats) if stats.stunned);
+        if !weapon.is_ready() || stunned {
+            continue;
+        }
+        weapon.ready_in = weapon.cooldown;
+
+        let faction = entry.get_component::<Faction>().ok().copied();
+        commands
+            .smith()
+            .projectile(&weapon.projectile, *entity, faction, position, direction);
+    }
+}
+
+/// Something a projectile could hit
+struct Target {
+    entity: Entity,
+    position: Vector2<f32>,
+    radius: f32,
+    faction: Option<Faction>,
+}
+
+impl Target {
+    fn is_spared_by(&self, projectile: &Projectile, factions: &FactionRegistry) -> bool {
+        self.entity == projectile.owner || factions.spares(projectile.faction, self.faction)
+    }
+}
+
+fn projectile_system() -> impl ParallelRunnable {
+    SystemBuilder::new("projectile")
+        .read_component::<Faction>()
+        .read_component::<Collider>()
+        .read_component::<HitPoints>()
+        .read_component::<Corpse>()
+        .read_component::<BodyHandle>()
+        .read_component::<Spent>()
+        .write_component::<Projectile>()
+        .write_component::<Position>()
+        .write_component::<Velocity>()
+        .read_resource::<FrameTime>()
+        .read_resource::<FloorTiles>()
+        .read_resource::<FactionRegistry>()
+        .write_resource::<DamageQueue>()
+        .build(
+            move |cmd, world, (frame_time, floor_tiles, factions, damage_queue), _| {
+                projectile(world, cmd, frame_time, floor_tiles, factions, damage_queue);
+            },
+        )
+}
+
+pub fn projectile(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    frame_time: &FrameTime,
+    floor_tiles: &FloorTiles,
+    factions: &FactionRegistry,
+    damage_queue: &mut DamageQueue,
+) {
+    // Spent projectiles are removed once the physics world has let go of their bodies
+    for (entity, _) in <(Entity, &Spent)>::query()
+        .filter(!component::<BodyHandle>())
+        .iter(world)
+    {
+        commands.remove(*entity);
+    }
+
+    let targets = <(Entity, &Position, &Collider, Option<&Faction>)>::query()
+        .filter(component::<HitPoints>() & !component::<Corpse>())
+        .iter(world)
+        .map(|(entity, position, collider, faction)| Target {
+            entity: *entity,
+            position: position.0.truncate(),
+            radius: match collider {
+                Collider::Circle { radius } => *radius,
+                Collider::Square { side_length } => side_length / 2.0,
+            },
+            faction: faction.copied(),
+        })
+        .collect::<Vec<_>>();
+
+    for (entity, projectile, position, velocity) in
+        <(Entity, &mut Projectile, &mut Position, &mut Velocity)>::query()
+            .filter(!component::<Spent>())
+            .iter_mut(world)
+    {
+        let from = projectile.previous;
+        let to = position.0.truncate();
+
+        let wall = trace_wall(floor_tiles, from, to);
+        let path_end = wall.map_or(to, |(free, _)| free);
+
+        // Everything along the path, nearest first
+        let mut hits = targets
+            .iter()
+            .filter(|target| {
+                !target.is_spared_by(projectile, factions)
+                    && !projectile.already_hit.contains(&target.entity)
+            })
+            .filter_map(|target| {
+                let (along, distance) = closest_approach(from, path_end, target.position);
+                if distance <= target.radius + projectile.radius {
+                    Some((along, target))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut impact = None;
+        for (along, target) in hits {
+            damage_queue.push(Damage {
+                target: target.entity,
+                source: Some(projectile.owner),
+                attacker: projectile.faction,
+                amount: projectile.damage,
+                effect: projectile.inflicts,
+                knockback: if velocity.0.magnitude2() > 0.0 {
+                    velocity.0.normalize_to(projectile.behaviour.knockback)
+                } else {
+                    Vector2::new(0.0, 0.0)
+                },
+            });
+            projectile.already_hit.push(target.entity);
+
+            if projectile.behaviour.pierce == 0 {
+                impact = Some(from + (path_end - from) * along);
+                break;
+            }
+            projectile.behaviour.pierce -= 1;
+        }
+
+        projectile.lifetime -= frame_time.0;
+
+        if impact.is_none() {
+            if let Some((free, blocked)) = wall {
+                if projectile.behaviour.bounces > 0 {
+                    projectile.behaviour.bounces -= 1;
+                    velocity.0 = bounce(floor_tiles, free, blocked, velocity.0);
+                    position.0 = free.extend(position.0.z);
+                } else {
+                    impact = Some(free);
+                }
+            } else if projectile.lifetime <= 0.0 {
+                impact = Some(to);
+            }
+        }
+
+        match impact {
+            Some(point) => {
+                if projectile.behaviour.blast_radius > 0.0 {
+                    blast(projectile, point, &targets, factions, damage_queue);
+                }
+                commands.remove_component::<PhysicsBody>(*entity);
+                commands.add_component(*entity, Spent);
+            }
+            None => projectile.previous = position.0.truncate(),
+        }
+    }
+}
+
+/// Hurts everything around the impact, pushing it away from the center
+fn blast(
+    projectile: &Projectile,
+    point: Vector2<f32>,
+    targets: &[Target],
+    factions: &FactionRegistry,
+    damage_queue: &mut DamageQueue,
+) {
+    for target in targets {
+        let offset = target.position - point;
+        if !target.is_spared_by(projectile, factions)
+            && offset.magnitude() <= projectile.behaviour.blast_radius + target.radius
+        {
+            damage_queue.push(Damage {
+                target: target.entity,
+                source: Some(projectile.owner),
+                attacker: projectile.faction,
+                amount: projectile.damage,
+                effect: projectile.inflicts,
+                knockback: if offset.magnitude2() > 0.0 {
+                    offset.normalize_to(projectile.behaviour.knockback)
+                } else {
+                    Vector2::new(0.0, 0.0)
+                },
+            });
+        }
+    }
+}
+
+/// Walks from `from` to `to` and returns the last open point before the path
+/// runs into a wall, along with the first point inside it
+fn trace_wall(
+    floor_tiles: &FloorTiles,
+    from: Vector2<f32>,
+    to: Vector2<f32>,
+) -> Option<(Vector2<f32>, Vector2<f32>)> {
+    const STEP: f32 = 0.05;
+
+    let steps = ((to - from).magnitude() / STEP).ceil().max(1.0) as usize;
+    let mut free = from;
+    for step in 1..=steps {
+        let point = from + (to - from) * (step as f32 / steps as f32);
+        if floor_tiles.blocks_sight(tile_at(point)) {
+            return Some((free, point));
+        }
+        free = point;
+    }
+    None
+}
+
+/// Reflects the velocity off whichever side of the wall was hit
+fn bounce(
+    floor_tiles: &FloorTiles,
+    free: Vector2<f32>,
+    blocked: Vector2<f32>,
+    velocity: Vector2<f32>,
+) -> Vector2<f32> {
+    let (free_x, free_y) = tile_at(free);
+    let (blocked_x, blocked_y) = tile_at(blocked);
+    let flip_x = blocked_x != free_x && floor_tiles.blocks_sight((blocked_x, free_y));
+    let flip_y = blocked_y != free_y && floor_tiles.blocks_sight((free_x, blocked_y));
+
+    match (flip_x, flip_y) {
+        (true, false) => Vector2::new(-velocity.x, velocity.y),
+        (false, true) => Vector2::new(velocity.x, -velocity.y),
+        // Straight into a corner
+        _ => -velocity,
+    }
+}
+
+/// How far along the segment the point is closest to it, and how close it gets
+fn closest_approach(from: Vector2<f32>, to: Vector2<f32>, point: Vector2<f32>) -> (f32, f32) {
+    let path = to - from;
+    let along = if path.magnitude2() > 0.0 {
+        clamp((point - from).dot(path) / path.magnitude2(), 0.0, 1.0)
+    } else {
+        0.0
+    };
+    (along, (from + path * along - point).magnitude())
+}
+
+fn apply_damage_system() -> impl ParallelRunnable {
+    SystemBuilder::new("apply_damage")
+        .read_component::<Invulnerable>()
+        .read_component::<Position>()
+        .read_component::<Faction>()
+        .write_component::<HitPoints>()
+        .write_component::<Velocity>()
+        .write_component::<StatusEffects>()
+        .write_resource::<FactionRegistry>()
+        .write_resource::<DamageQueue>()
+        .build(move |cmd, world, (factions, damage_queue), _| {
+            apply_damage(world, cmd, factions, damage_queue);
+        })
+}
+
+pub fn apply_damage(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    factions: &mut FactionRegistry,
+    damage_queue: &mut DamageQueue,
+) {
+    // Invulnerability is only added once the command buffer is flushed,
+    // so hits on the same target later in the queue are tracked here.
+    let mut hurt = HashSet::new();
+
+    for damage in damage_queue.0.drain(..) {
+        let mut entry = match world.entry_mut(damage.target) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        if entry.get_component::<Invulnerable>().is_ok() || !hurt.insert(damage.target) {
+            continue;
+        }
+
+        match entry.get_component_mut::<HitPoints>() {
+            Ok(hp) if hp.health > 0.0 => hp.health = (hp.health - damage.amount).max(0.0),
+            _ => continue,
+        }
+
+        if let (Ok(&victim), Some(attacker)) = (entry.get_component::<Faction>(), damage.attacker) {
+            if factions.provoke(victim, attacker, damage.amount) {
+                println!(
+                    "The {} have turned on the {}",
+                    factions.name(victim),
+                    factions.name(attacker)
+                );
+            }
+        }
+
+        if let Some(effect) = damage.effect {
+            match entry.get_component_mut::<StatusEffects>() {
+                Ok(effects) => effects.apply(effect),
+                Err(_) => {
+                    let mut effects = StatusEffects::default();
+                    effects.apply(effect);
+                    commands.add_component(damage.target, effects);
+                }
+            }
+        }
+
+        if let Ok(velocity) = entry.get_component_mut::<Velocity>() {
+            velocity.0 += damage.knockback;
+        }
+        // Being sent flying interrupts whatever the target was walking towards
+        if let Ok(position) = entry.get_component::<Position>() {
+            commands.add_component(
+                damage.target,
+                Destination::simple(position.0.truncate() + damage.knockback * KNOCKBACK_TIME),
+            );
+        }
+
+        commands.add_component(damage.target, Invulnerable(INVULNERABILITY_TIME));
+        if let Some(source) = damage.source {
+            commands.add_component(damage.target, LastHitBy(source));
+        }
+    }
+}
+
+fn invulnerability_system() -> impl ParallelRunnable {
+    SystemBuilder::new("invulnerability")
+        .read_resource::<FrameTime>()
+        .with_query(<(Entity, &mut Invulnerable)>::query())
+        .build(move |cmd, world, frame_time, query| {
+            query.for_each_mut(world, |(entity, invulnerable)| {
+                invulnerable.0 -= frame_time.0;
+                if invulnerable.0 <= 0.0 {
+                    cmd.remove_component::<Invulnerable>(*entity);
+                }
+            });
+        })
+}
+
+fn death_system() -> impl ParallelRunnable {
+    SystemBuilder::new("death")
+        .read_resource::<Player>()
+        .write_resource::<MapTransition>()
+        .write_resource::<RunSeed>()
+        .write_resource::<FogOfWar>()
+        .write_component::<Level>()
+        .with_query(
+            <(
+                Entity,
+                &mut HitPoints,
+                Option<&OnDeath>,
+                Option<&LastHitBy>,
+                Option<&ExperienceReward>,
+            )>::query()
+            .filter(!component::<Corpse>()),
+        )
+        .build(move |cmd, world, (player, transition, seed, fog), query| {
+            let mut kills = Vec::new();
+
+            query.for_each_mut(world, |(entity, hp, on_death, last_hit_by, reward)| {
+                if hp.health > 0.0 {
+                    return;
+                }
+                if let (Some(LastHitBy(killer)), Some(ExperienceReward(experience))) =
+                    (last_hit_by, reward)
+                {
+                    kills.push((*killer, *experience));
+                }
+                if *entity == player.player {
+                    println!("You have died");
+                    hp.health = hp.max;
+                    // Start the run over from the top
+                    **transition = MapTransition::Restart;
+                    **seed = RunSeed::random();
+                    **fog = FogOfWar::default();
+                    let player = player.player;
+                    cmd.exec_mut(move |world, resources| start_over(world, resources, player));
+                    return;
+                }
+                match on_death {
+                    Some(OnDeath::Corpse) => leave_corpse(cmd, *entity),
+                    None => cmd.remove(*entity),
+                }
+            });
+
+            for (killer, experience) in kills {
+                if let Ok(level) = <&mut Level>::query().get_mut(world, killer) {
+                    level.experience += experience;
+                }
+            }
+        })
+}
+
+/// Nothing is carried over into the new run, the player starts out as they first did
+fn start_over(world: &mut World, resources: &mut Resources, player: Entity) {
+    resources
+        .get_mut::<FactionRegistry>()
+        .unwrap()
+        .forget_grudges();
+    let attributes = resources.get::<StatsConfig>().unwrap().player;
+    if let Some(mut entry) = world.entry(player) {
+        if let Ok(level) = entry.get_component_mut::<Level>() {
+            *level = Level::new(1);
+        }
+        if let Ok(base) = entry.get_component_mut::<Attributes>() {
+            *base = attributes;
+        }
+        if let Ok(inventory) = entry.get_component_mut::<Inventory>() {
+            *inventory = Inventory::new(inventory.slots().len(), inventory.max_weight);
+        }
+        if let Ok(equipment) = entry.get_component_mut::<Equipment>() {
+            *equipment = Equipment::default();
+        }
+    }
+}
+
+/// Strips everything that lets the entity act or get in the way
+fn leave_corpse(commands: &mut CommandBuffer, entity: Entity) {
+    commands.remove_component::<AIBrain>(entity);
+    commands.remove_component::<AIFollow>(entity);
+    commands.remove_component::<Destination>(entity);
+    commands.remove_component::<MeleeAttack>(entity);
+    commands.remove_component::<AttackCommand>(entity);
+    commands.remove_component::<PhysicsBody>(entity);
+    commands.remove_component::<Collider>(entity);
+    commands.add_component(entity, Corpse);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::world_gen::components::TileType;
+
+    /// A 5x5 room around the origin, with nothing but rock around it
+    fn room() -> FloorTiles {
+        let mut floor_tiles = FloorTiles::default();
+        floor_tiles.replace(
+            (-2..=2)
+                .flat_map(|x| (-2..=2).map(move |y| ((x, y), TileType::Floor)))
+                .collect::<HashMap<_, _>>(),
+        );
+        floor_tiles
+    }
+
+    #[test]
+    fn paths_stop_at_walls() {
+        let floor_tiles = room();
+        assert!(trace_wall(&floor_tiles, Vector2::new(0.0, 0.0), Vector2::new(1.0, 1.0)).is_none());
+
+        let (free, blocked) =
+            trace_wall(&floor_tiles, Vector2::new(0.0, 0.0), Vector2::new(4.0, 0.0)).unwrap();
+        assert_eq!(tile_at(free), (2, 0));
+        assert_eq!(tile_at(blocked), (3, 0));
+    }
+
+    #[test]
+    fn bounces_flip_only_what_ran_into_the_wall() {
+        let floor_tiles = room();
+        let velocity = Vector2::new(1.0, 0.5);
+        assert_eq!(
+            bounce(
+                &floor_tiles,
+                Vector2::new(2.4, 0.0),
+                Vector2::new(2.6, 0.1),
+                velocity
+            ),
+            Vector2::new(-1.0, 0.5)
+        );
+        assert_eq!(
+            bounce(
+                &floor_tiles,
+                Vector2::new(0.0, 2.4),
+                Vector2::new(0.1, 2.6),
+                velocity
+            ),
+            Vector2::new(1.0, -0.5)
+        );
+        assert_eq!(
+            bounce(
+                &floor_tiles,
+                Vector2::new(2.4, 2.4),
+                Vector2::new(2.6, 2.6),
+                velocity
+            ),
+            -velocity
+        );
+    }
+
+    #[test]
+    fn targets_are_found_along_the_path() {
+        let from = Vector2::new(0.0, 0.0);
+        let to = Vector2::new(4.0, 0.0);
+        assert_eq!(
+            closest_approach(from, to, Vector2::new(1.0, 1.0)),
+            (0.25, 1.0)
+        );
+        assert_eq!(
+            closest_approach(from, to, Vector2::new(-2.0, 0.0)),
+            (0.0, 2.0)
+        );
+        assert_eq!(
+            closest_approach(from, to, Vector2::new(6.0, 0.0)),
+            (1.0, 2.0)
+        );
+        assert_eq!(
+            closest_approach(from, from, Vector2::new(0.0, 3.0)),
+            (0.0, 3.0)
+        );
+    }
+}
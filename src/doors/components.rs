@@ -0,0 +1,21 @@
+use legion::Entity;
+
+use crate::items::components::ItemId;
+use crate::world_gen::components::DoorState;
+
+// What doors look like while they are shut, open doors are not drawn at all
+pub const CLOSED_DOOR_MODEL: &str = "cube.obj";
+pub const LOCKED_DOOR_MODEL: &str = "graycube.obj";
+
+// The item that unlocks locked doors
+pub const DOOR_KEY: &str = "iron_key";
+
+pub struct Door {
+    pub location: (i32, i32),
+    pub state: DoorState,
+    // Consumed when unlocking the door
+    pub key: Option<ItemId>,
+}
+
+/// Someone wants to open, close or unlock a door
+pub struct DoorCommand(pub Entity);
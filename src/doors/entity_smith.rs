@@ -0,0 +1,35 @@
+use assman::components::DynamicModelRequest;
+use entity_smith::EntitySmith;
+use physics::{Collider, PhysicsEntitySmith};
+use transforms::TransformEntitySmith;
+
+use crate::doors::components::{Door, CLOSED_DOOR_MODEL, DOOR_KEY, LOCKED_DOOR_MODEL};
+use crate::items::components::ItemId;
+use crate::world_gen::components::DoorState;
+
+pub trait DoorEntitySmith {
+    fn door(&mut self, location: (i32, i32), state: DoorState) -> &mut Self;
+}
+
+impl<'a> DoorEntitySmith for EntitySmith<'a> {
+    /// Turns a door tile into a door, the tile's position and floor model are left to the caller
+    fn door(&mut self, location: (i32, i32), state: DoorState) -> &mut Self {
+        self.orientation(0.0).static_body().any(Door {
+            location,
+            state,
+            key: match state {
+                DoorState::Locked => Some(ItemId(DOOR_KEY.to_string())),
+                _ => None,
+            },
+        });
+        match state {
+            DoorState::Open => self,
+            DoorState::Closed => self
+                .any(Collider::Square { side_length: 1.0 })
+                .any(DynamicModelRequest::new(CLOSED_DOOR_MODEL)),
+            DoorState::Locked => self
+                .any(Collider::Square { side_length: 1.0 })
+                .any(DynamicModelRequest::new(LOCKED_DOOR_MODEL)),
+        }
+    }
+}
@@ -0,0 +1,6 @@
+pub mod components;
+pub mod entity_smith;
+pub mod systems;
+
+pub use entity_smith::DoorEntitySmith;
+pub use systems::DoorsUnit;
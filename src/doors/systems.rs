@@ -0,0 +1,311 @@
+use application::UnitStage;
+use assman::components::DynamicModelRequest;
+use cgmath::{MetricSpace, Vector2};
+use graphics::components::DynamicModel;
+use input::{Command, CommandManager};
+use legion::systems::{Builder, CommandBuffer, ParallelRunnable};
+use legion::world::SubWorld;
+use legion::{Entity, IntoQuery, SystemBuilder};
+use physics::Collider;
+use transforms::Position;
+
+use crate::components::Player;
+use crate::doors::components::{Door, DoorCommand, CLOSED_DOOR_MODEL};
+use crate::items::components::Container;
+use crate::items::inventory::Inventory;
+use crate::items::registry::ItemRegistry;
+use crate::items::systems::CONTAINER_REACH;
+use crate::perception::fov::tile_at;
+use crate::world_gen::components::{DoorState, FloorTiles, TileType};
+
+// How close an entity needs to be to a door to use it
+const DOOR_REACH: f32 = 1.2;
+
+pub struct DoorsUnit;
+
+impl application::Unit for DoorsUnit {
+    fn add_systems(&self, stage: UnitStage, builder: &mut Builder) {
+        if let UnitStage::Logic = stage {
+            builder
+                .add_system(player_door_system())
+                .add_system(door_system());
+        }
+    }
+}
+
+/// The player uses whichever door is closest when interacting,
+/// unless there is a container in reach to open instead
+fn player_door_system() -> impl ParallelRunnable {
+    SystemBuilder::new("player_doors")
+        .read_component::<Position>()
+        .read_component::<Door>()
+        .read_component::<Container>()
+        .read_resource::<CommandManager>()
+        .read_resource::<Player>()
+        .build(move |cmd, world, (command_manager, player), _| {
+            if !command_manager.get(Command::PlayerInteract) {
+                return;
+            }
+            let position = match <&Position>::query().get(world, player.player) {
+                Ok(position) => position.0.truncate(),
+                Err(_) => return,
+            };
+
+            // One press does one thing, and containers come first
+            let container_in_reach =
+                <(&Position, &Container)>::query()
+                    .iter(world)
+                    .any(|(container_position, _)| {
+                        container_position.0.truncate().distance(position) <= CONTAINER_REACH
+                    });
+            if container_in_reach {
+                return;
+            }
+
+            let nearest_door = <(Entity, &Position, &Door)>::query()
+                .iter(world)
+                .map(|(entity, door_position, _)| {
+                    (*entity, door_position.0.truncate().distance(position))
+                })
+                .filter(|(_, distance)| *distance <= DOOR_REACH)
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+            if let Some((door, _)) = nearest_door {
+                cmd.add_component(player.player, DoorCommand(door));
+            }
+        })
+}
+
+fn door_system() -> impl ParallelRunnable {
+    SystemBuilder::new("doors")
+        .read_component::<DoorCommand>()
+        .read_component::<Position>()
+        .write_component::<Door>()
+        .write_component::<Inventory>()
+        .read_resource::<ItemRegistry>()
+        .read_resource::<Player>()
+        .write_resource::<FloorTiles>()
+        .build(move |cmd, world, (registry, player, floor_tiles), _| {
+            use_doors(world, cmd, registry, player, floor_tiles);
+        })
+}
+
+pub fn use_doors(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    registry: &ItemRegistry,
+    player: &Player,
+    floor_tiles: &mut FloorTiles,
+) {
+    let (mut door_world, mut world) = world.split::<&mut Door>();
+
+    let door_commands = <(Entity, &DoorCommand, &Position)>::query()
+        .iter(&world)
+        .map(|(user, DoorCommand(door), position)| (*user, *door, position.0.truncate()))
+        .collect::<Vec<_>>();
+
+    for (user, door_entity, user_position) in door_commands {
+        commands.remove_component::<DoorCommand>(user);
+        let is_player = user == player.player;
+
+        let door = match <&mut Door>::query().get_mut(&mut door_world, door_entity) {
+            Ok(door) => door,
+            Err(_) => continue,
+        };
+        let door_position = Vector2::new(door.location.0 as f32, door.location.1 as f32);
+        if door_position.distance(user_position) > DOOR_REACH {
+            continue;
+        }
+
+        let next_state = match door.state {
+            DoorState::Open => {
+                // Doors can't be shut on anyone standing in the doorway
+                let blocked = <(Entity, &Position)>::query()
+                    .iter(&world)
+                    .filter(|(entity, _)| **entity != door_entity)
+                    .any(|(_, position)| tile_at(position.0.truncate()) == door.location);
+                if blocked {
+                    if is_player {
+                        println!("Something is in the way");
+                    }
+                    continue;
+                }
+                DoorState::Closed
+            }
+            DoorState::Closed => DoorState::Open,
+            DoorState::Locked => {
+                let unlocked = match (
+                    door.key.as_ref(),
+                    <&mut Inventory>::query().get_mut(&mut world, user),
+                ) {
+                    (Some(key), Ok(inventory)) => {
+                        let slot = (0..inventory.slots().len())
+                            .find(|&slot| matches!(inventory.get(slot), Some(stack) if stack.item == *key));
+                        slot.and_then(|slot| inventory.take(slot, 1).ok()).is_some()
+                    }
+                    _ => false,
+                };
+                if !unlocked {
+                    if is_player {
+                        println!("The door is locked");
+                    }
+                    continue;
+                }
+                if is_player {
+                    let key_name = door
+                        .key
+                        .as_ref()
+                        .and_then(|key| registry.get(key))
+                        .map_or("key", |definition| definition.name.as_str());
+                    println!("You unlock the door with your {}", key_name);
+                }
+                door.key = None;
+                DoorState::Open
+            }
+        };
+
+        door.state = next_state;
+        floor_tiles.set(door.location, TileType::Door(next_state));
+        commands.add_component(door_entity, TileType::Door(next_state));
+        match next_state {
+            DoorState::Open => {
+                commands.remove_component::<Collider>(door_entity);
+                commands.remove_component::<DynamicModel>(door_entity);
+            }
+            _ => {
+                commands.add_component(door_entity, Collider::Square { side_length: 1.0 });
+                commands.add_component(door_entity, DynamicModelRequest::new(CLOSED_DOOR_MODEL));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use cgmath::Vector3;
+    use legion::{Resources, Schedule, World};
+
+    use super::*;
+    use crate::doors::components::DOOR_KEY;
+    use crate::items::components::{ItemId, ItemStack};
+    use crate::items::registry::ITEMS_PATH;
+
+    /// The player standing next to a door, with however many keys to it
+    fn next_to_door(state: DoorState, keys: u32) -> (World, Resources, Entity, Entity) {
+        let registry = ItemRegistry::load(Path::new(ITEMS_PATH)).unwrap();
+        let key = ItemId(DOOR_KEY.to_string());
+        let mut inventory = Inventory::new(4, 100.0);
+        if keys > 0 {
+            assert!(inventory
+                .insert(ItemStack::new(key.clone(), keys), &registry)
+                .is_none());
+        }
+
+        let mut world = World::default();
+        let player = world.push((Position(Vector3::new(0.0, 0.0, 0.0)), inventory));
+        let door = world.push((Door {
+            location: (1, 0),
+            state,
+            key: Some(key),
+        },));
+        let mut floor_tiles = FloorTiles::default();
+        floor_tiles.set((1, 0), TileType::Door(state));
+
+        let mut resources = Resources::default();
+        resources.insert(registry);
+        resources.insert(Player {
+            model: player,
+            player,
+        });
+        resources.insert(floor_tiles);
+        (world, resources, player, door)
+    }
+
+    /// Has the player use the door, and returns what it is like afterwards
+    fn use_door(
+        world: &mut World,
+        resources: &mut Resources,
+        player: Entity,
+        door: Entity,
+    ) -> DoorState {
+        world
+            .entry(player)
+            .unwrap()
+            .add_component(DoorCommand(door));
+        Schedule::builder()
+            .add_system(door_system())
+            .build()
+            .execute(world, resources);
+        world
+            .entry(door)
+            .unwrap()
+            .get_component::<Door>()
+            .unwrap()
+            .state
+    }
+
+    fn revision(resources: &Resources) -> u64 { resources.get::<FloorTiles>().unwrap().revision() }
+
+    fn tile(resources: &Resources) -> Option<TileType> {
+        resources.get::<FloorTiles>().unwrap().get((1, 0))
+    }
+
+    fn keys(world: &mut World, player: Entity) -> u32 {
+        let entry = world.entry(player).unwrap();
+        let inventory = entry.get_component::<Inventory>().unwrap();
+        inventory
+            .slots()
+            .iter()
+            .flatten()
+            .map(|stack| stack.count)
+            .sum()
+    }
+
+    #[test]
+    fn doors_open_and_close() {
+        let (mut world, mut resources, player, door) = next_to_door(DoorState::Closed, 0);
+        let before = revision(&resources);
+
+        assert!(use_door(&mut world, &mut resources, player, door) == DoorState::Open);
+        assert!(tile(&resources) == Some(TileType::Door(DoorState::Open)));
+        assert_eq!(revision(&resources), before + 1);
+
+        assert!(use_door(&mut world, &mut resources, player, door) == DoorState::Closed);
+        assert!(tile(&resources) == Some(TileType::Door(DoorState::Closed)));
+        assert_eq!(revision(&resources), before + 2);
+    }
+
+    #[test]
+    fn doors_stay_open_while_someone_is_in_the_way() {
+        let (mut world, mut resources, player, door) = next_to_door(DoorState::Open, 0);
+        world.push((Position(Vector3::new(1.0, 0.0, 0.0)),));
+        let before = revision(&resources);
+
+        assert!(use_door(&mut world, &mut resources, player, door) == DoorState::Open);
+        assert_eq!(revision(&resources), before);
+    }
+
+    #[test]
+    fn unlocking_uses_up_a_key() {
+        let (mut world, mut resources, player, door) = next_to_door(DoorState::Locked, 2);
+        let before = revision(&resources);
+
+        assert!(use_door(&mut world, &mut resources, player, door) == DoorState::Open);
+        assert!(tile(&resources) == Some(TileType::Door(DoorState::Open)));
+        assert_eq!(revision(&resources), before + 1);
+        assert_eq!(keys(&mut world, player), 1);
+        let entry = world.entry(door).unwrap();
+        assert!(entry.get_component::<Door>().unwrap().key.is_none());
+    }
+
+    #[test]
+    fn locked_doors_stay_shut_without_a_key() {
+        let (mut world, mut resources, player, door) = next_to_door(DoorState::Locked, 0);
+        let before = revision(&resources);
+
+        assert!(use_door(&mut world, &mut resources, player, door) == DoorState::Locked);
+        assert!(tile(&resources) == Some(TileType::Door(DoorState::Locked)));
+        assert_eq!(revision(&resources), before);
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use cgmath::num_traits::clamp;
+use serde::Deserialize;
+
+pub const FACTIONS_PATH: &str = "assets/Data/factions.ron";
+
+/// Membership of a faction in the `FactionRegistry`
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Faction(usize);
+
+#[derive(Deserialize)]
+struct FactionData {
+    factions: Vec<String>,
+    standings: Vec<(String, String, f32)>,
+}
+
+/// Every faction and how they regard one another.
+/// Standings go from -1.0 (hostile) to 1.0 (allied),
+/// and sour as members of a faction are attacked.
+pub struct FactionRegistry {
+    names: Vec<String>,
+    // standings[a][b] is how faction `a` regards faction `b`
+    standings: Vec<Vec<f32>>,
+    // The standings before anyone held a grudge
+    initial: Vec<Vec<f32>>,
+}
+
+impl FactionRegistry {
+    const HOSTILE_BELOW: f32 = -0.3;
+    const ALLIED_ABOVE: f32 = 0.3;
+    // How much standing is lost for every hit point of damage dealt to a member
+    const GRUDGE_PER_DAMAGE: f32 = 0.1;
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        Self::from_ron(&data)
+    }
+
+    pub fn from_ron(data: &str) -> Result<Self, String> {
+        let data: FactionData = ron::de::from_str(data).map_err(|error| error.to_string())?;
+
+        let mut registry = Self {
+            standings: data
+                .factions
+                .iter()
+                .enumerate()
+                .map(|(a, _)| {
+                    (0..data.factions.len())
+                        .map(|b| if a == b { 1.0 } else { 0.0 })
+                        .collect()
+                })
+                .collect(),
+            names: data.factions,
+            initial: vec![],
+        };
+
+        for (a, b, standing) in data.standings {
+            let a = registry
+                .get(&a)
+                .ok_or_else(|| format!("Unknown faction: {}", a))?;
+            let b = registry
+                .get(&b)
+                .ok_or_else(|| format!("Unknown faction: {}", b))?;
+            registry.standings[a.0][b.0] = clamp(standing, -1.0, 1.0);
+        }
+        registry.initial = registry.standings.clone();
+
+        Ok(registry)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Faction> {
+        self.names.iter().position(|n| n == name).map(Faction)
+    }
+
+    pub fn name(&self, faction: Faction) -> &str { &self.names[faction.0] }
+
+    /// How `a` regards `b`
+    pub fn standing(&self, a: Faction, b: Faction) -> f32 { self.standings[a.0][b.0] }
+
+    pub fn is_hostile(&self, a: Faction, b: Faction) -> bool {
+        self.standing(a, b) < Self::HOSTILE_BELOW
+    }
+
+    pub fn is_allied(&self, a: Faction, b: Faction) -> bool {
+        self.standing(a, b) > Self::ALLIED_ABOVE
+    }
+
+    /// Whether attacks from `a` should pass `b` by.
+    /// Anything without a faction is fair game.
+    pub fn spares(&self, a: Option<Faction>, b: Option<Faction>) -> bool {
+        matches!((a, b), (Some(a), Some(b)) if self.is_allied(a, b))
+    }
+
+    /// `victim` holds a grudge against `attacker` for the damage dealt to one of its members.
+    /// Returns whether that was the last straw.
+    pub fn provoke(&mut self, victim: Faction, attacker: Faction, damage: f32) -> bool {
+        if victim == attacker {
+            return false;
+        }
+        let was_hostile = self.is_hostile(victim, attacker);
+        let standing = &mut self.standings[victim.0][attacker.0];
+        *standing = (*standing - damage * Self::GRUDGE_PER_DAMAGE).max(-1.0);
+        !was_hostile && self.is_hostile(victim, attacker)
+    }
+
+    /// Puts every standing back to how the data had it, for a fresh run
+    pub fn forget_grudges(&mut self) { self.standings = self.initial.clone(); }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> FactionRegistry {
+        FactionRegistry::from_ron(
+            r#"(
+                factions: ["Adventurers", "Monsters", "Vermin"],
+                standings: [
+                    ("Monsters", "Adventurers", -1.0),
+                    ("Adventurers", "Monsters", -1.0),
+                ],
+            )"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn standings_come_from_data() {
+        let registry = registry();
+        let adventurers = registry.get("Adventurers").unwrap();
+        let monsters = registry.get("Monsters").unwrap();
+        let vermin = registry.get("Vermin").unwrap();
+
+        assert!(registry.is_hostile(monsters, adventurers));
+        assert!(!registry.is_hostile(vermin, adventurers));
+        assert!(!registry.is_allied(vermin, adventurers));
+        assert!(registry.is_allied(vermin, vermin));
+        assert!(registry.spares(Some(monsters), Some(monsters)));
+        assert!(!registry.spares(Some(monsters), None));
+    }
+
+    #[test]
+    fn attacking_neutrals_makes_them_hostile() {
+        let mut registry = registry();
+        let adventurers = registry.get("Adventurers").unwrap();
+        let vermin = registry.get("Vermin").unwrap();
+
+        assert!(!registry.provoke(vermin, adventurers, 2.0));
+        assert!(!registry.is_hostile(vermin, adventurers));
+        assert!(registry.provoke(vermin, adventurers, 2.0));
+        assert!(registry.is_hostile(vermin, adventurers));
+        // Grudges are one sided
+        assert!(!registry.is_hostile(adventurers, vermin));
+
+        registry.forget_grudges();
+        assert!(!registry.is_hostile(vermin, adventurers));
+        assert_eq!(registry.standing(vermin, adventurers), 0.0);
+    }
+
+    #[test]
+    fn unknown_factions_are_rejected() {
+        assert!(
+            FactionRegistry::from_ron(r#"(factions: ["A"], standings: [("A", "B", 1.0)])"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn shipped_factions_load() {
+        assert!(FactionRegistry::load(Path::new(FACTIONS_PATH)).is_ok());
+    }
+}
@@ -0,0 +1,66 @@
+use legion::Entity;
+use serde::Deserialize;
+
+use crate::items::inventory::EquipmentSlot;
+
+/// Refers to an item definition in the `ItemRegistry`
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd, Deserialize)]
+#[serde(transparent)]
+pub struct ItemId(pub String);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ItemStack {
+    pub item: ItemId,
+    pub count: u32,
+}
+
+impl ItemStack {
+    pub fn new(item: ItemId, count: u32) -> Self { Self { item, count } }
+}
+
+/// An item lying on the ground, waiting to be picked up
+pub struct GroundItem(pub ItemStack);
+
+/// Left behind by an entity that dropped the item,
+/// which won't pick it up again by walking over it until it has stepped away
+pub struct DroppedBy(pub Entity);
+
+/// Picks up any item that comes within `radius`
+pub struct Collector {
+    pub radius: f32,
+}
+
+/// An item the entity has been told to pick up once it is close enough
+pub struct PickupTarget(pub Entity);
+
+/// Something was picked up, systems that care read these from `PickupEvents`
+pub struct PickupEvent {
+    pub picker: Entity,
+    pub stack: ItemStack,
+}
+
+/// Everything picked up this frame
+#[derive(Default)]
+pub struct PickupEvents(pub Vec<PickupEvent>);
+
+/// Holds items in its `Inventory` until someone opens it
+pub struct Container {
+    pub name: String,
+}
+
+/// Someone wants to empty a container into their own inventory
+pub struct OpenCommand(pub Entity);
+
+/// The loot table rolled for what an entity drops when it dies
+pub struct LootDrop(pub String);
+
+/// Something an entity wants done with its inventory this frame
+#[derive(Copy, Clone, Debug)]
+pub enum InventoryCommand {
+    Move { from: usize, to: usize },
+    Split { slot: usize, count: u32 },
+    Drop { slot: usize, count: u32 },
+    Use(usize),
+    Equip(usize),
+    Unequip(EquipmentSlot),
+}
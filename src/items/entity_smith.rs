@@ -0,0 +1,61 @@
+use assman::components::DynamicModelRequest;
+use cgmath::Vector2;
+use entity_smith::EntitySmith;
+use physics::PhysicsEntitySmith;
+use transforms::{Scale, TransformEntitySmith};
+
+use crate::items::components::{Container, GroundItem, ItemStack};
+use crate::items::inventory::Inventory;
+use crate::items::loot::ContainerDefinition;
+use crate::items::registry::ItemDefinition;
+use crate::perception::components::ObscuredByFog;
+
+pub trait ItemEntitySmith {
+    fn ground_item(
+        &mut self,
+        definition: &ItemDefinition,
+        stack: ItemStack,
+        position: Vector2<f32>,
+    ) -> &mut Self;
+
+    fn container(
+        &mut self,
+        definition: &ContainerDefinition,
+        contents: Inventory,
+        position: Vector2<f32>,
+    ) -> &mut Self;
+}
+
+impl<'a> ItemEntitySmith for EntitySmith<'a> {
+    fn ground_item(
+        &mut self,
+        definition: &ItemDefinition,
+        stack: ItemStack,
+        position: Vector2<f32>,
+    ) -> &mut Self {
+        self.pos(position)
+            .orientation(0.0)
+            .any(GroundItem(stack))
+            .any(DynamicModelRequest::new(&definition.model))
+            .any(Scale(definition.scale))
+            .any(ObscuredByFog)
+    }
+
+    fn container(
+        &mut self,
+        definition: &ContainerDefinition,
+        contents: Inventory,
+        position: Vector2<f32>,
+    ) -> &mut Self {
+        self.pos(position)
+            .orientation(0.0)
+            .static_square_body(definition.size)
+            .any(Container {
+                name: definition.name.clone(),
+            })
+            .any(contents)
+            .any(DynamicModelRequest::new(&definition.model))
+            .any(Scale(definition.scale))
+            .any(ObscuredByFog)
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Deserialize;
+
+use crate::items::components::{ItemId, ItemStack};
+use crate::items::registry::{ItemRegistry, UseEffect};
+use crate::stats::components::Attributes;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum InventoryError {
+    NoSuchSlot,
+    EmptySlot,
+    NotEnoughItems,
+    NoRoom,
+    TooHeavy,
+    NotEquippable,
+    NotUsable,
+    UnknownItem,
+}
+
+impl fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            InventoryError::NoSuchSlot => "there is no such slot",
+            InventoryError::EmptySlot => "that slot is empty",
+            InventoryError::NotEnoughItems => "there aren't that many",
+            InventoryError::NoRoom => "there is no room for it",
+            InventoryError::TooHeavy => "it is too heavy",
+            InventoryError::NotEquippable => "it can't be equipped",
+            InventoryError::NotUsable => "it can't be used",
+            InventoryError::UnknownItem => "nobody knows what that is",
+        };
+        f.write_str(message)
+    }
+}
+
+/// A fixed number of slots, each holding a single stack,
+/// and a limit on how much weight can be carried in total
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+    pub max_weight: f32,
+}
+
+impl Inventory {
+    pub fn new(slot_count: usize, max_weight: f32) -> Self {
+        Self {
+            slots: vec![None; slot_count],
+            max_weight,
+        }
+    }
+
+    pub fn slots(&self) -> &[Option<ItemStack>] { &self.slots }
+
+    pub fn get(&self, slot: usize) -> Option<&ItemStack> {
+        self.slots.get(slot).and_then(Option::as_ref)
+    }
+
+    pub fn weight(&self, registry: &ItemRegistry) -> f32 {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|stack| {
+                registry
+                    .get(&stack.item)
+                    .map_or(0.0, |definition| definition.weight * stack.count as f32)
+            })
+            .sum()
+    }
+
+    /// Puts as much of the stack as fits into the inventory,
+    /// topping up existing stacks before starting new ones.
+    /// Whatever didn't fit is handed back.
+    pub fn insert(&mut self, stack: ItemStack, registry: &ItemRegistry) -> Option<ItemStack> {
+        let definition = match registry.get(&stack.item) {
+            Some(definition) => definition,
+            None => return Some(stack),
+        };
+
+        let mut remaining = stack.count;
+        if definition.weight > 0.0 {
+            let spare_weight = (self.max_weight - self.weight(registry)).max(0.0);
+            let carriable = (spare_weight / definition.weight + 1e-4).floor() as u32;
+            remaining = remaining.min(carriable);
+        }
+        let refused = stack.count - remaining;
+
+        for existing in self.slots.iter_mut().flatten() {
+            if existing.item == stack.item {
+                let added = remaining.min(definition.max_stack.saturating_sub(existing.count));
+                existing.count += added;
+                remaining -= added;
+            }
+        }
+        for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+            if remaining == 0 {
+                break;
+            }
+            let added = remaining.min(definition.max_stack);
+            *slot = Some(ItemStack::new(stack.item.clone(), added));
+            remaining -= added;
+        }
+
+        Some(ItemStack::new(stack.item, remaining + refused)).filter(|leftover| leftover.count > 0)
+    }
+
+    /// Moves the stack in `from` onto `to`,
+    /// merging it with a stack of the same item or swapping places with anything else
+    pub fn move_stack(
+        &mut self,
+        from: usize,
+        to: usize,
+        registry: &ItemRegistry,
+    ) -> Result<(), InventoryError> {
+        if from >= self.slots.len() || to >= self.slots.len() {
+            return Err(InventoryError::NoSuchSlot);
+        }
+        let moving = self.slots[from].take().ok_or(InventoryError::EmptySlot)?;
+
+        match self.slots[to].as_mut() {
+            Some(target) if target.item == moving.item && from != to => {
+                let max_stack = registry
+                    .get(&moving.item)
+                    .map_or(1, |definition| definition.max_stack);
+                let added = moving.count.min(max_stack.saturating_sub(target.count));
+                target.count += added;
+                if added < moving.count {
+                    self.slots[from] = Some(ItemStack::new(moving.item, moving.count - added));
+                }
+            }
+            _ => {
+                self.slots[from] = self.slots[to].take();
+                self.slots[to] = Some(moving);
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves `count` items from a stack into an empty slot, and returns that slot
+    pub fn split(&mut self, slot: usize, count: u32) -> Result<usize, InventoryError> {
+        let stack = self
+            .slots
+            .get(slot)
+            .ok_or(InventoryError::NoSuchSlot)?
+            .as_ref()
+            .ok_or(InventoryError::EmptySlot)?;
+        if count == 0 || count >= stack.count {
+            return Err(InventoryError::NotEnoughItems);
+        }
+        let empty = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(InventoryError::NoRoom)?;
+
+        let split_off = self.take(slot, count)?;
+        self.slots[empty] = Some(split_off);
+        Ok(empty)
+    }
+
+    /// Takes `count` items out of a slot, emptying it if nothing is left
+    pub fn take(&mut self, slot: usize, count: u32) -> Result<ItemStack, InventoryError> {
+        let stack = self
+            .slots
+            .get_mut(slot)
+            .ok_or(InventoryError::NoSuchSlot)?
+            .as_mut()
+            .ok_or(InventoryError::EmptySlot)?;
+        if count > stack.count {
+            return Err(InventoryError::NotEnoughItems);
+        }
+
+        stack.count -= count;
+        let taken = ItemStack::new(stack.item.clone(), count);
+        if stack.count == 0 {
+            self.slots[slot] = None;
+        }
+        Ok(taken)
+    }
+
+    /// Consumes one of the items in a slot and returns what it does
+    pub fn use_item(
+        &mut self,
+        slot: usize,
+        registry: &ItemRegistry,
+    ) -> Result<UseEffect, InventoryError> {
+        let stack = self.get(slot).ok_or(InventoryError::EmptySlot)?;
+        let effect = registry
+            .get(&stack.item)
+            .ok_or(InventoryError::UnknownItem)?
+            .use_effect
+            .ok_or(InventoryError::NotUsable)?;
+
+        self.take(slot, 1)?;
+        Ok(effect)
+    }
+
+    /// Equips one of the items in a slot,
+    /// putting whatever was equipped before back into the inventory
+    pub fn equip(
+        &mut self,
+        slot: usize,
+        equipment: &mut Equipment,
+        registry: &ItemRegistry,
+    ) -> Result<(), InventoryError> {
+        let stack = self.get(slot).ok_or(InventoryError::EmptySlot)?;
+        let equipment_slot = registry
+            .get(&stack.item)
+            .ok_or(InventoryError::UnknownItem)?
+            .equipment
+            .ok_or(InventoryError::NotEquippable)?;
+
+        let equipping = self.take(slot, 1)?;
+        if let Some(previous) = equipment.0.remove(&equipment_slot) {
+            if let Some(leftover) = self.insert(ItemStack::new(previous, 1), registry) {
+                // Undo everything rather than lose the previous item
+                self.insert(equipping, registry);
+                equipment.0.insert(equipment_slot, leftover.item);
+                return Err(InventoryError::NoRoom);
+            }
+        }
+        equipment.0.insert(equipment_slot, equipping.item);
+        Ok(())
+    }
+
+    /// Moves whatever is equipped in `equipment_slot` back into the inventory
+    pub fn unequip(
+        &mut self,
+        equipment_slot: EquipmentSlot,
+        equipment: &mut Equipment,
+        registry: &ItemRegistry,
+    ) -> Result<(), InventoryError> {
+        let item = equipment
+            .0
+            .get(&equipment_slot)
+            .cloned()
+            .ok_or(InventoryError::EmptySlot)?;
+
+        match self.insert(ItemStack::new(item, 1), registry) {
+            None => {
+                equipment.0.remove(&equipment_slot);
+                Ok(())
+            }
+            Some(_) if self.slots.iter().all(Option::is_some) => Err(InventoryError::NoRoom),
+            Some(_) => Err(InventoryError::TooHeavy),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize)]
+pub enum EquipmentSlot {
+    Weapon,
+    Armour,
+    Trinket,
+}
+
+/// What an entity has equipped, at most one item per slot
+#[derive(Default)]
+pub struct Equipment(pub HashMap<EquipmentSlot, ItemId>);
+
+impl Equipment {
+    /// The attributes granted by everything equipped
+    pub fn bonus(&self, registry: &ItemRegistry) -> Attributes {
+        self.0
+            .values()
+            .filter_map(|item| registry.get(item))
+            .fold(Attributes::default(), |total, definition| {
+                total.plus(definition.bonus, 1.0)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::items::registry::ITEMS_PATH;
+
+    fn registry() -> ItemRegistry {
+        ItemRegistry::from_ron(
+            r#"(
+                items: {
+                    "gold": (name: "Gold", model: "cube.obj", scale: 0.1, max_stack: 100, weight: 0.01),
+                    "potion": (
+                        name: "Potion", model: "sphere.obj", scale: 0.1, max_stack: 5, weight: 0.5,
+                        use_effect: Some(Heal(3.0)),
+                    ),
+                    "sword": (
+                        name: "Sword", model: "cube.obj", scale: 0.1, max_stack: 1, weight: 4.0,
+                        equipment: Some(Weapon), bonus: (strength: 2.0),
+                    ),
+                    "axe": (
+                        name: "Axe", model: "cube.obj", scale: 0.1, max_stack: 1, weight: 6.0,
+                        equipment: Some(Weapon), bonus: (strength: 3.0),
+                    ),
+                },
+            )"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn shipped_items_load() {
+        let registry = ItemRegistry::load(Path::new(ITEMS_PATH)).unwrap();
+        assert!(registry
+            .ids()
+            .iter()
+            .all(|id| registry.get(id).unwrap().max_stack > 0));
+    }
+
+    fn stack(item: &str, count: u32) -> ItemStack {
+        ItemStack::new(ItemId(item.to_string()), count)
+    }
+
+    #[test]
+    fn stacks_fill_up_before_new_slots_are_used() {
+        let registry = registry();
+        let mut inventory = Inventory::new(3, 100.0);
+
+        assert_eq!(inventory.insert(stack("potion", 3), &registry), None);
+        assert_eq!(inventory.insert(stack("potion", 4), &registry), None);
+        assert_eq!(inventory.get(0), Some(&stack("potion", 5)));
+        assert_eq!(inventory.get(1), Some(&stack("potion", 2)));
+
+        // Only one slot is left, so only one more sword fits
+        assert_eq!(
+            inventory.insert(stack("sword", 2), &registry),
+            Some(stack("sword", 1))
+        );
+        assert_eq!(
+            inventory.insert(stack("gold", 1), &registry),
+            Some(stack("gold", 1))
+        );
+    }
+
+    #[test]
+    fn weight_limits_what_can_be_carried() {
+        let registry = registry();
+        let mut inventory = Inventory::new(10, 5.0);
+
+        assert_eq!(inventory.insert(stack("sword", 1), &registry), None);
+        assert_eq!(
+            inventory.insert(stack("potion", 5), &registry),
+            Some(stack("potion", 3))
+        );
+        assert_eq!(inventory.weight(&registry), 5.0);
+        assert_eq!(
+            inventory.insert(stack("axe", 1), &registry),
+            Some(stack("axe", 1))
+        );
+    }
+
+    #[test]
+    fn move_split_and_take() {
+        let registry = registry();
+        let mut inventory = Inventory::new(4, 100.0);
+        inventory.insert(stack("gold", 60), &registry);
+
+        assert_eq!(inventory.split(0, 50), Ok(1));
+        assert_eq!(inventory.get(0), Some(&stack("gold", 10)));
+        assert_eq!(inventory.get(1), Some(&stack("gold", 50)));
+        assert_eq!(inventory.split(0, 10), Err(InventoryError::NotEnoughItems));
+        assert_eq!(inventory.split(2, 1), Err(InventoryError::EmptySlot));
+        assert_eq!(inventory.split(9, 1), Err(InventoryError::NoSuchSlot));
+
+        // Moving onto the same item merges the stacks
+        inventory.move_stack(1, 0, &registry).unwrap();
+        assert_eq!(inventory.get(0), Some(&stack("gold", 60)));
+        assert_eq!(inventory.get(1), None);
+
+        // Moving onto anything else swaps them
+        inventory.insert(stack("sword", 1), &registry);
+        inventory.move_stack(0, 1, &registry).unwrap();
+        assert_eq!(inventory.get(0), Some(&stack("sword", 1)));
+        assert_eq!(inventory.get(1), Some(&stack("gold", 60)));
+
+        assert_eq!(inventory.take(1, 60), Ok(stack("gold", 60)));
+        assert_eq!(inventory.get(1), None);
+        assert_eq!(inventory.take(1, 1), Err(InventoryError::EmptySlot));
+        assert_eq!(inventory.take(9, 1), Err(InventoryError::NoSuchSlot));
+    }
+
+    #[test]
+    fn using_consumes_an_item() {
+        let registry = registry();
+        let mut inventory = Inventory::new(4, 100.0);
+        inventory.insert(stack("potion", 2), &registry);
+        inventory.insert(stack("gold", 2), &registry);
+
+        assert_eq!(inventory.use_item(0, &registry), Ok(UseEffect::Heal(3.0)));
+        assert_eq!(inventory.get(0), Some(&stack("potion", 1)));
+        assert_eq!(
+            inventory.use_item(1, &registry),
+            Err(InventoryError::NotUsable)
+        );
+        assert_eq!(inventory.get(1), Some(&stack("gold", 2)));
+    }
+
+    #[test]
+    fn equipment_swaps_and_grants_bonuses() {
+        let registry = registry();
+        let mut inventory = Inventory::new(4, 100.0);
+        let mut equipment = Equipment::default();
+        inventory.insert(stack("sword", 1), &registry);
+        inventory.insert(stack("axe", 1), &registry);
+
+        inventory.equip(0, &mut equipment, &registry).unwrap();
+        assert_eq!(equipment.bonus(&registry).strength, 2.0);
+        assert_eq!(inventory.get(0), None);
+
+        inventory.equip(1, &mut equipment, &registry).unwrap();
+        assert_eq!(equipment.bonus(&registry).strength, 3.0);
+        assert_eq!(inventory.get(0), Some(&stack("sword", 1)));
+
+        inventory
+            .unequip(EquipmentSlot::Weapon, &mut equipment, &registry)
+            .unwrap();
+        assert_eq!(equipment.bonus(&registry), Attributes::default());
+        assert_eq!(
+            inventory.unequip(EquipmentSlot::Weapon, &mut equipment, &registry),
+            Err(InventoryError::EmptySlot)
+        );
+    }
+}
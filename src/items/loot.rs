@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::items::components::{ItemId, ItemStack};
+use crate::items::registry::ItemRegistry;
+
+pub const LOOT_PATH: &str = "assets/Data/loot.ron";
+
+fn first_floor() -> u32 { 1 }
+
+fn single() -> (u32, u32) { (1, 1) }
+
+#[derive(Deserialize)]
+pub struct LootEntry {
+    pub item: ItemId,
+    pub weight: f32,
+    // Added to the weight for every floor past `min_floor`,
+    // so that the good stuff gets more common the deeper you go
+    #[serde(default)]
+    pub weight_per_floor: f32,
+    // The shallowest floor the entry can be rolled on
+    #[serde(default = "first_floor")]
+    pub min_floor: u32,
+    // The smallest and largest stack, inclusive
+    #[serde(default = "single")]
+    pub count: (u32, u32),
+}
+
+impl LootEntry {
+    pub fn weight_on(&self, floor: i32) -> f32 {
+        let depth = floor - self.min_floor as i32;
+        if depth < 0 {
+            0.0
+        } else {
+            (self.weight + self.weight_per_floor * depth as f32).max(0.0)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LootTable {
+    // How many entries are picked, inclusive
+    pub rolls: (u32, u32),
+    pub entries: Vec<LootEntry>,
+}
+
+impl LootTable {
+    /// Picks entries by weight, each roll independently of the others
+    pub fn roll<R: Rng>(&self, floor: i32, rng: &mut R) -> Vec<ItemStack> {
+        let total = self
+            .entries
+            .iter()
+            .map(|entry| entry.weight_on(floor))
+            .sum::<f32>();
+        if total <= 0.0 {
+            return vec![];
+        }
+
+        let rolls = rng.gen_range(self.rolls.0..=self.rolls.1);
+        (0..rolls)
+            .filter_map(|_| {
+                let mut choice = rng.gen_range(0.0..total);
+                let entry = self
+                    .entries
+                    .iter()
+                    .find(|entry| {
+                        choice -= entry.weight_on(floor);
+                        choice < 0.0
+                    })
+                    // Rounding can leave a little of the choice over past the last entry
+                    .or_else(|| {
+                        self.entries
+                            .iter()
+                            .rev()
+                            .find(|entry| entry.weight_on(floor) > 0.0)
+                    })?;
+                let count = rng.gen_range(entry.count.0..=entry.count.1);
+                Some(ItemStack::new(entry.item.clone(), count))
+            })
+            .collect()
+    }
+}
+
+/// Something that holds loot, placed by world generation
+#[derive(Deserialize)]
+pub struct ContainerDefinition {
+    pub name: String,
+    pub model: String,
+    pub scale: f32,
+    // Side of the square it takes up on the floor
+    pub size: f32,
+    pub table: String,
+    // How often this kind of container shows up, relative to the others
+    pub weight: f32,
+}
+
+#[derive(Deserialize)]
+pub struct LootTables {
+    tables: HashMap<String, LootTable>,
+    pub containers: Vec<ContainerDefinition>,
+}
+
+impl LootTables {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        Self::from_ron(&data)
+    }
+
+    pub fn from_ron(data: &str) -> Result<Self, String> {
+        let tables: Self = ron::de::from_str(data).map_err(|error| error.to_string())?;
+        if let Some(container) = tables
+            .containers
+            .iter()
+            .find(|container| tables.get(&container.table).is_none())
+        {
+            return Err(format!(
+                "The {} uses the unknown loot table {}",
+                container.name, container.table
+            ));
+        }
+        let mut names = tables.tables.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let table = &tables.tables[name];
+            if table.rolls.0 > table.rolls.1 {
+                return Err(format!(
+                    "The loot table {} rolls at least {} times but at most {}",
+                    name, table.rolls.0, table.rolls.1
+                ));
+            }
+            if let Some(entry) = table
+                .entries
+                .iter()
+                .find(|entry| entry.count.0 > entry.count.1)
+            {
+                return Err(format!(
+                    "The loot table {} drops at least {} {:?} but at most {}",
+                    name, entry.count.0, entry.item, entry.count.1
+                ));
+            }
+        }
+        Ok(tables)
+    }
+
+    pub fn get(&self, table: &str) -> Option<&LootTable> { self.tables.get(table) }
+
+    /// Rolls a table, nothing drops from tables that don't exist
+    pub fn roll<R: Rng>(&self, table: &str, floor: i32, rng: &mut R) -> Vec<ItemStack> {
+        self.get(table)
+            .map_or_else(Vec::new, |table| table.roll(floor, rng))
+    }
+
+    /// Makes sure every item the tables mention is in the registry
+    pub fn check_items(&self, registry: &ItemRegistry) -> Result<(), String> {
+        let mut unknown = self
+            .tables
+            .values()
+            .flat_map(|table| table.entries.iter())
+            .map(|entry| &entry.item)
+            .filter(|item| registry.get(item).is_none())
+            .collect::<Vec<_>>();
+        unknown.sort();
+        unknown.dedup();
+        match unknown.as_slice() {
+            [] => Ok(()),
+            unknown => Err(format!("Loot tables mention unknown items: {:?}", unknown)),
+        }
+    }
+
+    pub fn container(&self, name: &str) -> Option<&ContainerDefinition> {
+        self.containers
+            .iter()
+            .find(|container| container.name == name)
+    }
+
+    pub fn choose_container<R: Rng>(&self, rng: &mut R) -> Option<&ContainerDefinition> {
+        let total = self
+            .containers
+            .iter()
+            .map(|container| container.weight)
+            .sum::<f32>();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut choice = rng.gen_range(0.0..total);
+        self.containers
+            .iter()
+            .find(|container| {
+                choice -= container.weight;
+                choice < 0.0
+            })
+            .or_else(|| {
+                self.containers
+                    .iter()
+                    .rev()
+                    .find(|container| container.weight > 0.0)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::items::registry::ITEMS_PATH;
+
+    fn tables() -> LootTables {
+        LootTables::from_ron(
+            r#"(
+                tables: {
+                    "chest": (
+                        rolls: (3, 3),
+                        entries: [
+                            (item: "gold", weight: 1.0, count: (5, 10)),
+                            (item: "sword", weight: 0.0, weight_per_floor: 1.0, min_floor: 3),
+                        ],
+                    ),
+                },
+                containers: [
+                    (name: "Chest", model: "cube.obj", scale: 0.5, size: 0.5, table: "chest", weight: 1.0),
+                ],
+            )"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn shipped_tables_only_drop_known_items() {
+        let tables = LootTables::load(Path::new(LOOT_PATH)).unwrap();
+        let registry = ItemRegistry::load(Path::new(ITEMS_PATH)).unwrap();
+        assert_eq!(tables.check_items(&registry), Ok(()));
+    }
+
+    #[test]
+    fn deeper_floors_unlock_entries() {
+        let tables = tables();
+        let mut rng = StdRng::seed_from_u64(7);
+        let gold = ItemId("gold".to_string());
+
+        for _ in 0..20 {
+            let loot = tables.roll("chest", 1, &mut rng);
+            assert_eq!(loot.len(), 3);
+            assert!(loot.iter().all(|stack| stack.item == gold));
+            assert!(loot.iter().all(|stack| (5..=10).contains(&stack.count)));
+        }
+
+        let table = tables.get("chest").unwrap();
+        assert_eq!(table.entries[1].weight_on(3), 0.0);
+        assert_eq!(table.entries[1].weight_on(5), 2.0);
+        let deep_loot = (0..20)
+            .flat_map(|_| tables.roll("chest", 10, &mut rng))
+            .collect::<Vec<_>>();
+        assert!(deep_loot.iter().any(|stack| stack.item != gold));
+    }
+
+    #[test]
+    fn containers_need_a_table() {
+        let broken = r#"(
+            tables: {},
+            containers: [
+                (name: "Box", model: "cube.obj", scale: 0.5, size: 0.5, table: "box", weight: 1.0),
+            ],
+        )"#;
+        assert!(LootTables::from_ron(broken).is_err());
+        assert!(tables()
+            .roll("nothing", 1, &mut StdRng::seed_from_u64(0))
+            .is_empty());
+    }
+
+    #[test]
+    fn ranges_go_from_least_to_most() {
+        let backwards_rolls = r#"(
+            tables: { "chest": (rolls: (3, 1), entries: []) },
+            containers: [],
+        )"#;
+        assert!(LootTables::from_ron(backwards_rolls).is_err());
+        let backwards_count = r#"(
+            tables: { "chest": (rolls: (1, 1), entries: [(item: "gold", weight: 1.0, count: (5, 2))]) },
+            containers: [],
+        )"#;
+        assert!(LootTables::from_ron(backwards_count).is_err());
+    }
+}
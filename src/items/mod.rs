@@ -0,0 +1,9 @@
+pub mod components;
+pub mod entity_smith;
+pub mod inventory;
+pub mod loot;
+pub mod registry;
+pub mod systems;
+
+pub use entity_smith::ItemEntitySmith;
+pub use systems::ItemsUnit;
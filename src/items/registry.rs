@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::items::components::ItemId;
+use crate::items::inventory::EquipmentSlot;
+use crate::stats::components::{Attributes, EffectKind};
+
+pub const ITEMS_PATH: &str = "assets/Data/items.ron";
+
+/// What happens when an item is used up
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub enum UseEffect {
+    // Hit points restored on the spot
+    Heal(f32),
+    Apply {
+        kind: EffectKind,
+        magnitude: f32,
+        duration: f32,
+    },
+}
+
+#[derive(Deserialize)]
+pub struct ItemDefinition {
+    pub name: String,
+    // What it looks like lying on the ground
+    pub model: String,
+    // What it looks like in the inventory, nothing draws it until there is an inventory screen
+    #[serde(default)]
+    #[allow(dead_code)]
+    pub icon: Option<String>,
+    pub scale: f32,
+    pub max_stack: u32,
+    // Per item, not per stack
+    #[serde(default)]
+    pub weight: f32,
+    // Where it goes when equipped, if it can be equipped at all
+    #[serde(default)]
+    pub equipment: Option<EquipmentSlot>,
+    // Added to the wearer's attributes while equipped
+    #[serde(default)]
+    pub bonus: Attributes,
+    #[serde(default)]
+    pub use_effect: Option<UseEffect>,
+}
+
+#[derive(Deserialize)]
+pub struct ItemRegistry {
+    items: HashMap<ItemId, ItemDefinition>,
+}
+
+impl ItemRegistry {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        Self::from_ron(&data)
+    }
+
+    pub fn from_ron(data: &str) -> Result<Self, String> {
+        ron::de::from_str(data).map_err(|error| error.to_string())
+    }
+
+    pub fn get(&self, item: &ItemId) -> Option<&ItemDefinition> { self.items.get(item) }
+
+    /// Every known item, in a stable order
+    pub fn ids(&self) -> Vec<&ItemId> {
+        let mut ids = self.items.keys().collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+}
@@ -0,0 +1,451 @@
+use std::collections::HashSet;
+
+use application::UnitStage;
+use cgmath::{MetricSpace, Vector2};
+use entity_smith::Smith;
+use input::{Command, CommandManager};
+use legion::systems::{Builder, CommandBuffer, ParallelRunnable};
+use legion::world::SubWorld;
+use legion::{Entity, EntityStore, IntoQuery, Resources, SystemBuilder, World};
+use rand::prelude::*;
+use transforms::Position;
+
+use crate::components::{HitPoints, Player};
+use crate::items::components::{
+    Collector, Container, DroppedBy, GroundItem, InventoryCommand, LootDrop, OpenCommand,
+    PickupEvent, PickupEvents, PickupTarget,
+};
+use crate::items::inventory::{Equipment, EquipmentSlot, Inventory, InventoryError};
+use crate::items::loot::LootTables;
+use crate::items::registry::{ItemRegistry, UseEffect};
+use crate::items::ItemEntitySmith;
+use crate::stats::components::{StatusEffect, StatusEffects};
+use crate::world_gen::components::FloorNumber;
+
+// How close an entity needs to get to an item it was told to pick up
+const PICKUP_REACH: f32 = 1.0;
+// How close an entity needs to be to a container to open it
+pub const CONTAINER_REACH: f32 = 1.2;
+
+pub struct ItemsUnit;
+
+impl application::Unit for ItemsUnit {
+    fn load_resources(&self, _: &mut World, resources: &mut Resources) {
+        resources.insert(PickupEvents::default());
+    }
+    fn add_systems(&self, stage: UnitStage, builder: &mut Builder) {
+        if let UnitStage::Logic = stage {
+            builder
+                .add_system(player_inventory_system())
+                .add_system(inventory_system())
+                .add_system(pickup_system())
+                .add_system(container_system())
+                .add_system(loot_drop_system())
+                .add_system(pickup_report_system());
+        }
+    }
+}
+
+/// Turns the player's key presses into inventory commands
+fn player_inventory_system() -> impl ParallelRunnable {
+    SystemBuilder::new("player_inventory")
+        .read_component::<Inventory>()
+        .read_component::<Equipment>()
+        .read_component::<Position>()
+        .read_component::<Container>()
+        .read_resource::<CommandManager>()
+        .read_resource::<ItemRegistry>()
+        .read_resource::<Player>()
+        .build(move |cmd, world, (command_manager, registry, player), _| {
+            let (inventory, position) =
+                match <(&Inventory, &Position)>::query().get(world, player.player) {
+                    Ok((inventory, position)) => (inventory, position.0.truncate()),
+                    Err(_) => return,
+                };
+
+            if command_manager.get(Command::PlayerInteract) {
+                let nearest_container = <(Entity, &Position, &Container)>::query()
+                    .iter(world)
+                    .map(|(entity, container_position, _)| {
+                        (*entity, container_position.0.truncate().distance(position))
+                    })
+                    .filter(|(_, distance)| *distance <= CONTAINER_REACH)
+                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+                if let Some((container, _)) = nearest_container {
+                    cmd.add_component(player.player, OpenCommand(container));
+                }
+            }
+
+            let first_slot = |predicate: &dyn Fn(usize) -> bool| {
+                (0..inventory.slots().len()).find(|&slot| predicate(slot))
+            };
+            let definition = |slot| {
+                inventory
+                    .get(slot)
+                    .and_then(|stack| registry.get(&stack.item))
+            };
+
+            let command = if command_manager.get(Command::PlayerUseItem) {
+                first_slot(&|slot| matches!(definition(slot), Some(d) if d.use_effect.is_some()))
+                    .map(InventoryCommand::Use)
+            } else if command_manager.get(Command::PlayerEquipItem) {
+                first_slot(&|slot| matches!(definition(slot), Some(d) if d.equipment.is_some()))
+                    .map(InventoryCommand::Equip)
+            } else if command_manager.get(Command::PlayerDropItem) {
+                (0..inventory.slots().len())
+                    .rev()
+                    .find_map(|slot| inventory.get(slot).map(|stack| (slot, stack.count)))
+                    .map(|(slot, count)| InventoryCommand::Drop { slot, count })
+            } else if command_manager.get(Command::PlayerMoveItem) {
+                // The last stack goes to the front, onto whatever is there
+                (0..inventory.slots().len())
+                    .rev()
+                    .find(|&slot| inventory.get(slot).is_some())
+                    .map(|from| InventoryCommand::Move { from, to: 0 })
+            } else if command_manager.get(Command::PlayerSplitItem) {
+                // The first stack there is more than one of is split in half
+                (0..inventory.slots().len()).find_map(|slot| {
+                    let stack = inventory.get(slot).filter(|stack| stack.count > 1)?;
+                    Some(InventoryCommand::Split {
+                        slot,
+                        count: stack.count / 2,
+                    })
+                })
+            } else if command_manager.get(Command::PlayerUnequipItem) {
+                let equipment = <&Equipment>::query().get(world, player.player).ok();
+                [
+                    EquipmentSlot::Weapon,
+                    EquipmentSlot::Armour,
+                    EquipmentSlot::Trinket,
+                ]
+                .iter()
+                .copied()
+                .find(|slot| matches!(equipment, Some(equipment) if equipment.0.contains_key(slot)))
+                .map(InventoryCommand::Unequip)
+            } else {
+                None
+            };
+
+            if let Some(command) = command {
+                cmd.add_component(player.player, command);
+            }
+        })
+}
+
+fn inventory_system() -> impl ParallelRunnable {
+    SystemBuilder::new("inventory")
+        .read_component::<Position>()
+        .read_component::<InventoryCommand>()
+        .write_component::<Inventory>()
+        .write_component::<Equipment>()
+        .write_component::<HitPoints>()
+        .write_component::<StatusEffects>()
+        .read_resource::<ItemRegistry>()
+        .read_resource::<Player>()
+        .build(move |cmd, world, (registry, player), _| {
+            inventory(world, cmd, registry, player);
+        })
+}
+
+pub fn inventory(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    registry: &ItemRegistry,
+    player: &Player,
+) {
+    let (command_world, mut world) = world.split::<&InventoryCommand>();
+    let (mut equipment_world, mut world) = world.split::<&mut Equipment>();
+
+    for (entity, command) in <(Entity, &InventoryCommand)>::query().iter(&command_world) {
+        commands.remove_component::<InventoryCommand>(*entity);
+
+        let mut entry = match world.entry_mut(*entity) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let position = match entry.get_component::<Position>() {
+            Ok(position) => position.0.truncate(),
+            Err(_) => continue,
+        };
+
+        let result =
+            match (*command, entry.get_component_mut::<Inventory>()) {
+                (_, Err(_)) => continue,
+                (InventoryCommand::Move { from, to }, Ok(inventory)) => {
+                    inventory.move_stack(from, to, registry)
+                }
+                (InventoryCommand::Split { slot, count }, Ok(inventory)) => {
+                    inventory.split(slot, count).map(|_| ())
+                }
+                (InventoryCommand::Drop { slot, count }, Ok(inventory)) => {
+                    inventory.take(slot, count).map(|stack| {
+                        if let Some(definition) = registry.get(&stack.item) {
+                            commands
+                                .smith()
+                                .ground_item(definition, stack, position)
+                                .any(DroppedBy(*entity));
+                        }
+                    })
+                }
+                (InventoryCommand::Use(slot), Ok(inventory)) => inventory
+                    .use_item(slot, registry)
+                    .map(|effect| match effect {
+                        UseEffect::Heal(amount) => {
+                            if let Ok(hp) = entry.get_component_mut::<HitPoints>() {
+                                hp.health = (hp.health + amount).min(hp.max);
+                            }
+                        }
+                        UseEffect::Apply {
+                            kind,
+                            magnitude,
+                            duration,
+                        } => {
+                            let effect = StatusEffect::new(kind, magnitude, duration);
+                            match entry.get_component_mut::<StatusEffects>() {
+                                Ok(effects) => effects.apply(effect),
+                                Err(_) => {
+                                    let mut effects = StatusEffects::default();
+                                    effects.apply(effect);
+                                    commands.add_component(*entity, effects);
+                                }
+                            }
+                        }
+                    }),
+                (InventoryCommand::Equip(slot), Ok(inventory)) => {
+                    match <&mut Equipment>::query().get_mut(&mut equipment_world, *entity) {
+                        Ok(equipment) => inventory.equip(slot, equipment, registry),
+                        Err(_) => Err(InventoryError::NotEquippable),
+                    }
+                }
+                (InventoryCommand::Unequip(equipment_slot), Ok(inventory)) => {
+                    match <&mut Equipment>::query().get_mut(&mut equipment_world, *entity) {
+                        Ok(equipment) => inventory.unequip(equipment_slot, equipment, registry),
+                        Err(_) => Err(InventoryError::EmptySlot),
+                    }
+                }
+            };
+
+        if let (Err(error), true) = (result, *entity == player.player) {
+            println!("You can't do that, {}", error);
+        }
+    }
+}
+
+fn pickup_system() -> impl ParallelRunnable {
+    SystemBuilder::new("pickup")
+        .read_component::<Position>()
+        .read_component::<GroundItem>()
+        .read_component::<DroppedBy>()
+        .read_component::<Collector>()
+        .read_component::<PickupTarget>()
+        .write_component::<Inventory>()
+        .read_resource::<ItemRegistry>()
+        .read_resource::<Player>()
+        .write_resource::<PickupEvents>()
+        .build(move |cmd, world, (registry, player, events), _| {
+            pickup(world, cmd, registry, player, events);
+        })
+}
+
+pub fn pickup(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    registry: &ItemRegistry,
+    player: &Player,
+    events: &mut PickupEvents,
+) {
+    events.0.clear();
+
+    let (mut inventory_world, world) = world.split::<&mut Inventory>();
+
+    let items = <(Entity, &Position, &GroundItem, Option<&DroppedBy>)>::query()
+        .iter(&world)
+        .map(|(entity, position, item, dropped_by)| {
+            (*entity, position.0.truncate(), item, dropped_by)
+        })
+        .collect::<Vec<_>>();
+    let mut taken = HashSet::new();
+
+    for (picker, position, collector, target) in
+        <(Entity, &Position, &Collector, Option<&PickupTarget>)>::query().iter(&world)
+    {
+        let inventory = match <&mut Inventory>::query().get_mut(&mut inventory_world, *picker) {
+            Ok(inventory) => inventory,
+            Err(_) => continue,
+        };
+        let position = position.0.truncate();
+
+        for (item_entity, item_position, item, dropped_by) in items.iter() {
+            let distance = position.distance(*item_position);
+            let targeted = matches!(target, Some(PickupTarget(target)) if target == item_entity);
+            let dropped_here = matches!(dropped_by, Some(DroppedBy(dropper)) if dropper == picker);
+
+            if dropped_here && distance > collector.radius {
+                commands.remove_component::<DroppedBy>(*item_entity);
+            }
+            let in_reach = (distance <= collector.radius && !dropped_here)
+                || (targeted && distance <= PICKUP_REACH);
+            if !in_reach || taken.contains(item_entity) {
+                continue;
+            }
+            if targeted {
+                commands.remove_component::<PickupTarget>(*picker);
+            }
+
+            // Whatever doesn't fit stays on the ground
+            let mut stack = item.0.clone();
+            match inventory.insert(item.0.clone(), registry) {
+                None => {
+                    taken.insert(*item_entity);
+                    commands.remove(*item_entity);
+                }
+                Some(leftover) => {
+                    if targeted && *picker == player.player {
+                        println!("You can't carry any more");
+                    }
+                    stack.count -= leftover.count;
+                    if stack.count > 0 {
+                        commands.add_component(*item_entity, GroundItem(leftover));
+                    }
+                }
+            }
+
+            if stack.count > 0 {
+                events.0.push(PickupEvent {
+                    picker: *picker,
+                    stack,
+                });
+            }
+        }
+    }
+}
+
+/// Moves everything in an opened container into the opener's inventory,
+/// whatever doesn't fit is left inside
+fn container_system() -> impl ParallelRunnable {
+    SystemBuilder::new("containers")
+        .read_component::<OpenCommand>()
+        .read_component::<Container>()
+        .read_component::<Position>()
+        .write_component::<Inventory>()
+        .read_resource::<ItemRegistry>()
+        .read_resource::<Player>()
+        .write_resource::<PickupEvents>()
+        .build(move |cmd, world, (registry, player, events), _| {
+            open_containers(world, cmd, registry, player, events);
+        })
+}
+
+pub fn open_containers(
+    world: &mut SubWorld,
+    commands: &mut CommandBuffer,
+    registry: &ItemRegistry,
+    player: &Player,
+    events: &mut PickupEvents,
+) {
+    let (command_world, mut world) = world.split::<&OpenCommand>();
+
+    for (opener, OpenCommand(container)) in <(Entity, &OpenCommand)>::query().iter(&command_world) {
+        commands.remove_component::<OpenCommand>(*opener);
+
+        let opener_position = match <&Position>::query().get(&world, *opener) {
+            Ok(position) => position.0.truncate(),
+            Err(_) => continue,
+        };
+        // The container may have been left behind on another floor by now
+        let name = match <(&Position, &Container)>::query().get(&world, *container) {
+            Ok((position, container))
+                if position.0.truncate().distance(opener_position) <= CONTAINER_REACH =>
+            {
+                container.name.clone()
+            }
+            _ => continue,
+        };
+
+        // Empty the container first, since both inventories can't be borrowed at once
+        let contents = match <&mut Inventory>::query().get_mut(&mut world, *container) {
+            Ok(inventory) => (0..inventory.slots().len())
+                .filter_map(|slot| {
+                    let count = inventory.get(slot)?.count;
+                    inventory.take(slot, count).ok()
+                })
+                .collect::<Vec<_>>(),
+            Err(_) => continue,
+        };
+        if contents.is_empty() && *opener == player.player {
+            println!("The {} is empty", name);
+        }
+
+        let mut leftovers = Vec::new();
+        if let Ok(inventory) = <&mut Inventory>::query().get_mut(&mut world, *opener) {
+            for stack in contents {
+                let mut taken = stack.clone();
+                if let Some(leftover) = inventory.insert(stack, registry) {
+                    taken.count -= leftover.count;
+                    leftovers.push(leftover);
+                }
+                if taken.count > 0 {
+                    events.0.push(PickupEvent {
+                        picker: *opener,
+                        stack: taken,
+                    });
+                }
+            }
+        }
+        if !leftovers.is_empty() && *opener == player.player {
+            println!("You can't carry everything in the {}", name);
+        }
+
+        if let Ok(inventory) = <&mut Inventory>::query().get_mut(&mut world, *container) {
+            for leftover in leftovers {
+                inventory.insert(leftover, registry);
+            }
+        }
+    }
+}
+
+/// Rolls the loot of anything that died and leaves it on the ground where it fell
+fn loot_drop_system() -> impl ParallelRunnable {
+    SystemBuilder::new("loot_drops")
+        .read_resource::<LootTables>()
+        .read_resource::<ItemRegistry>()
+        .read_resource::<FloorNumber>()
+        .with_query(<(Entity, &Position, &HitPoints, &LootDrop)>::query())
+        .build(move |cmd, world, (loot, registry, floor), query| {
+            let mut rng = thread_rng();
+            for (entity, position, hp, LootDrop(table)) in query.iter(world) {
+                if hp.health > 0.0 {
+                    continue;
+                }
+                // Only ever rolled once, however long the body sticks around
+                cmd.remove_component::<LootDrop>(*entity);
+
+                for stack in loot.roll(table, floor.0, &mut rng) {
+                    if let Some(definition) = registry.get(&stack.item) {
+                        let scatter =
+                            Vector2::new(rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3));
+                        cmd.smith()
+                            .ground_item(definition, stack, position.0.truncate() + scatter);
+                    }
+                }
+            }
+        })
+}
+
+fn pickup_report_system() -> impl ParallelRunnable {
+    SystemBuilder::new("pickup_report")
+        .read_resource::<PickupEvents>()
+        .read_resource::<ItemRegistry>()
+        .read_resource::<Player>()
+        .build(move |_, _, (events, registry, player), _| {
+            for event in events
+                .0
+                .iter()
+                .filter(|event| event.picker == player.player)
+            {
+                if let Some(definition) = registry.get(&event.stack.item) {
+                    println!("Picked up {} x {}", event.stack.count, definition.name);
+                }
+            }
+        })
+}
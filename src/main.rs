@@ -1,7 +1,14 @@
 #![allow(deprecated)]
 
+mod ai;
+mod combat;
 mod components;
+mod doors;
+mod factions;
+mod items;
 mod misc;
+mod perception;
+mod stats;
 mod systems;
 mod world_gen;
 
@@ -13,6 +20,7 @@ use assman::data::AssetStorageInfo;
 use assman::systems::AssetManagerBuilderExtender;
 use assman::{AssetStore, GraphicsAssetManager};
 use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+use combat::components::{MeleeAttack, ProjectileKind, RangedAttack};
 use components::{Player, PlayerCamera};
 use debug::DebugTimer;
 use entity_smith::{FrameTime, Smith};
@@ -22,13 +30,25 @@ use graphics::gui::GuiRenderPipeline;
 use graphics::models::{ModelQueue, ModelRenderPipeline};
 use graphics::systems::RenderBuilderExtender;
 use input::InputState;
+use perception::components::Viewshed;
 use physics::{PhysicsBuilderExtender, PhysicsEntitySmith};
+use stats::components::{EffectKind, Level, Regeneration, StatusEffect};
+use stats::config::{StatsConfig, STATS_PATH};
 use transforms::{Parent, Scale, SphericalOffset, TransformBuilderExtender, TransformEntitySmith};
 use winit::dpi::PhysicalSize;
 use winit::event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 
-use crate::world_gen::components::{FloorNumber, MapTransition};
+use crate::factions::registry::FACTIONS_PATH;
+use crate::factions::FactionRegistry;
+use crate::items::components::Collector;
+use crate::items::inventory::{Equipment, Inventory};
+use crate::items::loot::{LootTables, LOOT_PATH};
+use crate::items::registry::{ItemRegistry, ITEMS_PATH};
+use crate::world_gen::components::{FloorNumber, FloorTiles, MapTransition};
+use crate::world_gen::floors::Floors;
+use crate::world_gen::level::{Levels, LEVELS_PATH};
+use crate::world_gen::seed::RunSeed;
 
 async fn run_async() {
     // world_gen::wfc::test();
@@ -85,6 +105,7 @@ async fn run_async() {
         builder.schedule_builders[UnitStage::Logic]
             .add_system(systems::player::player_system())
             .add_system(systems::player::camera_control_system())
+            .add_system(world_gen::systems::ladder_system())
             .add_system(world_gen::systems::dung_gen_system())
             .add_system(systems::go_to_destination_system())
             .add_physics_systems(&mut builder.world, &mut builder.resources)
@@ -94,10 +115,30 @@ async fn run_async() {
 
         builder
     }
+    .with_unit(perception::PerceptionUnit)
+    .with_unit(stats::StatsUnit)
+    .with_unit(ai::AIUnit)
+    .with_unit(combat::CombatUnit)
+    .with_unit(items::ItemsUnit)
+    .with_unit(doors::DoorsUnit)
     .with_unit(misc::SnakeUnit)
     .with_unit(input::InputUnit)
     .build();
 
+    let factions = FactionRegistry::load(FACTIONS_PATH.as_ref()).unwrap();
+    let stats = StatsConfig::load(STATS_PATH.as_ref()).unwrap();
+    let items = ItemRegistry::load(ITEMS_PATH.as_ref()).unwrap();
+    let loot = LootTables::load(LOOT_PATH.as_ref()).unwrap();
+    loot.check_items(&items).unwrap();
+    let mut levels = Levels::load(LEVELS_PATH.as_ref()).unwrap();
+    // `--level <file>` starts the run on a handcrafted level
+    if let Some(path) = std::env::args().skip_while(|arg| arg != "--level").nth(1) {
+        levels.insert(1, world_gen::level::Level::load(path.as_ref()).unwrap());
+    }
+    levels.check(&items, &loot).unwrap();
+    let formulas = &stats.formulas;
+    let attributes = stats.player;
+
     let mut command_buffer = legion::systems::CommandBuffer::new(&ecs.world);
 
     let player = command_buffer
@@ -105,10 +146,27 @@ async fn run_async() {
         .name("Player")
         .position(Vector3::unit_x())
         .orientation(0.0)
-        .agent(5., 30.)
+        .agent(
+            formulas.speed.of(&attributes),
+            formulas.acceleration.of(&attributes),
+        )
         .velocity(Vector2::zero())
         .dynamic_body(1.)
         .circle_collider(0.3)
+        .any(Viewshed::new(8))
+        .any(factions.get("Adventurers").expect("The player needs a side"))
+        .any(formulas.hit_points(&attributes))
+        .any(Regeneration(formulas.regeneration.of(&attributes)))
+        .any(
+            MeleeAttack::new(formulas.damage.of(&attributes), 1.0, 4.0, 0.4)
+                .inflicting(StatusEffect::new(EffectKind::Stun, 0.0, 0.25)),
+        )
+        .any(RangedAttack::new(ProjectileKind::bolt(), 0.6))
+        .any(attributes)
+        .any(Level::new(1))
+        .any(Collector { radius: 0.5 })
+        .any(Inventory::new(12, 30.0))
+        .any(Equipment::default())
         .get_entity();
 
     let player_model = command_buffer
@@ -163,8 +221,22 @@ async fn run_async() {
     });
 
     ecs.resources.insert(Instant::now());
-    ecs.resources.insert(MapTransition::Deeper);
+    ecs.resources.insert(MapTransition::Restart);
     ecs.resources.insert(FloorNumber(1));
+    ecs.resources.insert(FloorTiles::default());
+    ecs.resources.insert(Floors::default());
+    // A run can be replayed by starting the game with the seed it was generated from
+    ecs.resources.insert(
+        std::env::var("DEEPER_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .map_or_else(RunSeed::random, RunSeed),
+    );
+    ecs.resources.insert(factions);
+    ecs.resources.insert(stats);
+    ecs.resources.insert(items);
+    ecs.resources.insert(loot);
+    ecs.resources.insert(levels);
 
     ecs.resources.insert(ass_man);
 
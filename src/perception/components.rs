@@ -0,0 +1,89 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::perception::fov::TileLocation;
+
+/// The set of tiles an entity can currently see
+pub struct Viewshed {
+    pub range: i32,
+    pub visible_tiles: HashSet<TileLocation>,
+    // Where and against which floor revision the view was last computed
+    origin: Option<(TileLocation, u64)>,
+}
+
+impl Viewshed {
+    pub fn new(range: i32) -> Self {
+        Self {
+            range,
+            visible_tiles: HashSet::new(),
+            origin: None,
+        }
+    }
+
+    pub(crate) fn is_stale(&self, origin: TileLocation, revision: u64) -> bool {
+        self.origin != Some((origin, revision))
+    }
+
+    pub(crate) fn update(
+        &mut self,
+        origin: TileLocation,
+        revision: u64,
+        visible: HashSet<TileLocation>,
+    ) {
+        self.visible_tiles = visible;
+        self.origin = Some((origin, revision));
+    }
+}
+
+/// Entities that are only shown while they are in the player's sight
+pub struct ObscuredByFog;
+
+/// What the player has explored of each floor, and what they are looking at right now
+#[derive(Default)]
+pub struct FogOfWar {
+    explored: HashMap<i32, HashSet<TileLocation>>,
+    visible: HashSet<TileLocation>,
+    // The floor and tile revision that the fog was last updated for
+    updated_for: Option<(i32, u64)>,
+}
+
+impl FogOfWar {
+    const VISIBLE: u8 = 255;
+    const REMEMBERED: u8 = 80;
+    const UNEXPLORED: u8 = 0;
+
+    pub fn is_visible(&self, location: TileLocation) -> bool { self.visible.contains(&location) }
+
+    pub fn is_explored(&self, floor: i32, location: TileLocation) -> bool {
+        matches!(self.explored.get(&floor), Some(explored) if explored.contains(&location))
+    }
+
+    /// How brightly a tile should be drawn
+    pub fn brightness(&self, floor: i32, location: TileLocation) -> u8 {
+        if self.is_visible(location) {
+            Self::VISIBLE
+        } else if self.is_explored(floor, location) {
+            Self::REMEMBERED
+        } else {
+            Self::UNEXPLORED
+        }
+    }
+
+    /// Returns whether anything changed
+    pub(crate) fn update(
+        &mut self,
+        floor: i32,
+        revision: u64,
+        visible: &HashSet<TileLocation>,
+    ) -> bool {
+        if self.updated_for == Some((floor, revision)) && self.visible == *visible {
+            return false;
+        }
+        self.explored
+            .entry(floor)
+            .or_default()
+            .extend(visible.iter().copied());
+        self.visible = visible.clone();
+        self.updated_for = Some((floor, revision));
+        true
+    }
+}
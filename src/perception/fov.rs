@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+
+use cgmath::Vector2;
+
+use crate::world_gen::components::FloorTiles;
+
+pub type TileLocation = (i32, i32);
+
+/// The tile that a world position is standing on
+pub fn tile_at(position: Vector2<f32>) -> TileLocation {
+    (position.x.round() as i32, position.y.round() as i32)
+}
+
+/// Whether an observer at `from` has an unobstructed view of `to`
+pub fn can_see(tiles: &FloorTiles, from: Vector2<f32>, to: Vector2<f32>) -> bool {
+    line_of_sight(tile_at(from), tile_at(to), |location| {
+        tiles.blocks_sight(location)
+    })
+}
+
+/// Every tile visible from `origin` within `radius` tiles,
+/// computed with symmetric shadowcasting.
+/// Opaque tiles are visible themselves, but hide what is behind them.
+pub fn field_of_view<F>(origin: TileLocation, radius: i32, blocks_sight: F) -> HashSet<TileLocation>
+where
+    F: Fn(TileLocation) -> bool,
+{
+    let mut visible = HashSet::new();
+    visible.insert(origin);
+
+    for &quadrant in &[
+        Quadrant::North,
+        Quadrant::East,
+        Quadrant::South,
+        Quadrant::West,
+    ] {
+        let mut caster = ShadowCaster {
+            origin,
+            radius,
+            quadrant,
+            blocks_sight: &blocks_sight,
+            visible: &mut visible,
+        };
+        caster.scan(Row {
+            depth: 1,
+            start_slope: Slope::new(-1, 1),
+            end_slope: Slope::new(1, 1),
+        });
+    }
+
+    visible
+}
+
+/// Symmetric line of sight between two tiles.
+/// The line is traced in both directions so that `a` sees `b` exactly when `b` sees `a`.
+pub fn line_of_sight<F>(a: TileLocation, b: TileLocation, blocks_sight: F) -> bool
+where
+    F: Fn(TileLocation) -> bool,
+{
+    let clear = |from: TileLocation, to: TileLocation| {
+        bresenham(from, to)
+            .into_iter()
+            .filter(|&location| location != from && location != to)
+            .all(|location| !blocks_sight(location))
+    };
+    clear(a, b) || clear(b, a)
+}
+
+fn bresenham((x0, y0): TileLocation, (x1, y1): TileLocation) -> Vec<TileLocation> {
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+    let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+
+    let mut line = vec![(x, y)];
+    while (x, y) != (x1, y1) {
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += sx;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += sy;
+        }
+        line.push((x, y));
+    }
+    line
+}
+
+#[derive(Copy, Clone)]
+enum Quadrant {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Quadrant {
+    /// Maps a (depth, column) pair relative to the quadrant onto the map
+    fn transform(self, (x, y): TileLocation, depth: i32, column: i32) -> TileLocation {
+        match self {
+            Quadrant::North => (x + column, y + depth),
+            Quadrant::South => (x + column, y - depth),
+            Quadrant::East => (x + depth, y + column),
+            Quadrant::West => (x - depth, y + column),
+        }
+    }
+}
+
+/// An exact fraction, so that tiles exactly on a shadow edge are treated consistently
+#[derive(Copy, Clone)]
+struct Slope {
+    numerator: i64,
+    denominator: i64,
+}
+
+impl Slope {
+    fn new(numerator: i64, denominator: i64) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// The slope of the edge of a tile facing the start of the row
+    fn of_tile_edge(depth: i32, column: i32) -> Self {
+        Self::new(2 * column as i64 - 1, 2 * depth as i64)
+    }
+
+    /// depth * slope, rounded with ties going up
+    fn round_up_at(self, depth: i32) -> i32 {
+        (2 * depth as i64 * self.numerator + self.denominator).div_euclid(2 * self.denominator)
+            as i32
+    }
+
+    /// depth * slope, rounded with ties going down
+    fn round_down_at(self, depth: i32) -> i32 {
+        -(-2 * depth as i64 * self.numerator + self.denominator).div_euclid(2 * self.denominator)
+            as i32
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Row {
+    depth: i32,
+    start_slope: Slope,
+    end_slope: Slope,
+}
+
+impl Row {
+    fn columns(&self) -> std::ops::RangeInclusive<i32> {
+        self.start_slope.round_up_at(self.depth)..=self.end_slope.round_down_at(self.depth)
+    }
+
+    fn next(&self) -> Self {
+        Self {
+            depth: self.depth + 1,
+            ..*self
+        }
+    }
+
+    /// Floor tiles are only revealed when their center is inside the row's view,
+    /// which is what makes the algorithm symmetric.
+    fn is_symmetric(&self, column: i32) -> bool {
+        let column = column as i64;
+        let depth = self.depth as i64;
+        column * self.start_slope.denominator >= depth * self.start_slope.numerator
+            && column * self.end_slope.denominator <= depth * self.end_slope.numerator
+    }
+}
+
+struct ShadowCaster<'a, F> {
+    origin: TileLocation,
+    radius: i32,
+    quadrant: Quadrant,
+    blocks_sight: &'a F,
+    visible: &'a mut HashSet<TileLocation>,
+}
+
+impl<'a, F: Fn(TileLocation) -> bool> ShadowCaster<'a, F> {
+    fn scan(&mut self, mut row: Row) {
+        if row.depth > self.radius {
+            return;
+        }
+
+        let mut previous_opaque = None;
+        for column in row.columns() {
+            let location = self.quadrant.transform(self.origin, row.depth, column);
+            let opaque = (self.blocks_sight)(location);
+            let in_range = row.depth * row.depth + column * column <= self.radius * self.radius;
+
+            if in_range && (opaque || row.is_symmetric(column)) {
+                self.visible.insert(location);
+            }
+            if previous_opaque == Some(true) && !opaque {
+                row.start_slope = Slope::of_tile_edge(row.depth, column);
+            }
+            if previous_opaque == Some(false) && opaque {
+                let mut next_row = row.next();
+                next_row.end_slope = Slope::of_tile_edge(row.depth, column);
+                self.scan(next_row);
+            }
+            previous_opaque = Some(opaque);
+        }
+        if previous_opaque == Some(false) {
+            self.scan(row.next());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 9x9 room with a single pillar east of the center
+    fn pillar_room(location: TileLocation) -> bool {
+        let (x, y) = location;
+        x.abs() > 4 || y.abs() > 4 || location == (2, 0)
+    }
+
+    #[test]
+    fn open_room_is_fully_visible() {
+        let visible = field_of_view((0, 0), 10, |(x, y)| x.abs() > 4 || y.abs() > 4);
+        for x in -4..=4 {
+            for y in -4..=4 {
+                assert!(visible.contains(&(x, y)), "{:?} should be visible", (x, y));
+            }
+        }
+        // The walls around the room are seen, the rock behind them is not
+        assert!(visible.contains(&(5, 0)));
+        assert!(!visible.contains(&(6, 0)));
+    }
+
+    #[test]
+    fn pillars_cast_shadows() {
+        let visible = field_of_view((0, 0), 10, pillar_room);
+        assert!(visible.contains(&(2, 0)));
+        assert!(!visible.contains(&(3, 0)));
+        assert!(!visible.contains(&(4, 0)));
+        assert!(visible.contains(&(4, 3)));
+    }
+
+    #[test]
+    fn radius_limits_view() {
+        let visible = field_of_view((0, 0), 2, |_| false);
+        assert!(visible.contains(&(2, 0)));
+        assert!(!visible.contains(&(3, 0)));
+        assert!(!visible.contains(&(2, 2)));
+    }
+
+    #[test]
+    fn line_of_sight_is_symmetric() {
+        for &target in &[(3, 0), (4, 1), (4, -2), (-4, 4), (3, 3)] {
+            assert_eq!(
+                line_of_sight((0, 0), target, pillar_room),
+                line_of_sight(target, (0, 0), pillar_room),
+            );
+        }
+        assert!(!line_of_sight((0, 0), (4, 0), pillar_room));
+        assert!(line_of_sight((0, 0), (0, 4), pillar_room));
+    }
+}
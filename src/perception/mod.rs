@@ -0,0 +1,5 @@
+pub mod components;
+pub mod fov;
+pub mod systems;
+
+pub use systems::PerceptionUnit;
@@ -0,0 +1,130 @@
+use application::UnitStage;
+use graphics::components::Hidden;
+use graphics::models::ModelRenderPipeline;
+use graphics::GraphicsContext;
+use legion::systems::{Builder, CommandBuffer, ParallelRunnable};
+use legion::world::SubWorld;
+use legion::{component, Entity, IntoQuery, Resources, SystemBuilder, World};
+use transforms::Position;
+
+use crate::components::Player;
+use crate::perception::components::{FogOfWar, ObscuredByFog, Viewshed};
+use crate::perception::fov::{field_of_view, tile_at};
+use crate::world_gen::components::{FloorNumber, FloorTiles};
+
+pub struct PerceptionUnit;
+
+impl application::Unit for PerceptionUnit {
+    fn load_resources(&self, _: &mut World, resources: &mut Resources) {
+        resources.insert(FogOfWar::default());
+    }
+    fn add_systems(&self, stage: UnitStage, builder: &mut Builder) {
+        if let UnitStage::Logic = stage {
+            builder
+                .add_system(viewshed_system())
+                .add_system(fog_of_war_system())
+                .add_system(fog_concealment_system());
+        }
+    }
+}
+
+fn viewshed_system() -> impl ParallelRunnable {
+    SystemBuilder::new("viewshed")
+        .read_resource::<FloorTiles>()
+        .with_query(<(&mut Viewshed, &Position)>::query())
+        .build(move |_, world, floor_tiles, query| {
+            query.for_each_mut(world, |(viewshed, position)| {
+                viewshed_update(floor_tiles, viewshed, position);
+            });
+        })
+}
+
+fn viewshed_update(floor_tiles: &FloorTiles, viewshed: &mut Viewshed, position: &Position) {
+    let origin = tile_at(position.0.truncate());
+    if viewshed.is_stale(origin, floor_tiles.revision()) {
+        let visible = field_of_view(origin, viewshed.range, |location| {
+            floor_tiles.blocks_sight(location)
+        });
+        viewshed.update(origin, floor_tiles.revision(), visible);
+    }
+}
+
+fn fog_of_war_system() -> impl ParallelRunnable {
+    SystemBuilder::new("fog_of_war")
+        .read_component::<Viewshed>()
+        .read_resource::<Player>()
+        .read_resource::<FloorNumber>()
+        .read_resource::<FloorTiles>()
+        .read_resource::<GraphicsContext>()
+        .write_resource::<FogOfWar>()
+        .write_resource::<ModelRenderPipeline>()
+        .build(
+            move |_,
+                  world,
+                  (player, floor, floor_tiles, graphics_context, fog, model_render_pipeline),
+                  _| {
+                if let Ok(viewshed) = <&Viewshed>::query().get(world, player.player) {
+                    if fog.update(floor.0, floor_tiles.revision(), &viewshed.visible_tiles) {
+                        draw_fog_of_war(
+                            floor.0,
+                            floor_tiles,
+                            graphics_context,
+                            fog,
+                            model_render_pipeline,
+                        );
+                    }
+                }
+            },
+        )
+}
+
+fn draw_fog_of_war(
+    floor: i32,
+    floor_tiles: &FloorTiles,
+    graphics_context: &GraphicsContext,
+    fog: &FogOfWar,
+    model_render_pipeline: &mut ModelRenderPipeline,
+) {
+    if let Some(((min_x, min_y), (max_x, max_y))) = floor_tiles.bounds() {
+        let size = [(max_x - min_x + 1) as u32, (max_y - min_y + 1) as u32];
+        let mut visibility = Vec::with_capacity((size[0] * size[1]) as usize);
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                visibility.push(fog.brightness(floor, (x, y)));
+            }
+        }
+        model_render_pipeline.set_visibility_map(
+            graphics_context,
+            [min_x, min_y],
+            size,
+            &visibility,
+        );
+    } else {
+        model_render_pipeline.clear_visibility_map(graphics_context);
+    }
+}
+
+fn fog_concealment_system() -> impl ParallelRunnable {
+    SystemBuilder::new("fog_concealment")
+        .read_component::<Position>()
+        .read_component::<ObscuredByFog>()
+        .read_component::<Hidden>()
+        .read_resource::<FogOfWar>()
+        .build(move |cmd, world, fog, _| {
+            fog_concealment(world, cmd, fog);
+        })
+}
+
+fn fog_concealment(world: &mut SubWorld, commands: &mut CommandBuffer, fog: &FogOfWar) {
+    for (entity, position, hidden) in <(Entity, &Position, Option<&Hidden>)>::query()
+        .filter(component::<ObscuredByFog>())
+        .iter(world)
+    {
+        let in_sight = fog.is_visible(tile_at(position.0.truncate()));
+        match (in_sight, hidden.is_some()) {
+            (true, true) => commands.remove_component::<Hidden>(*entity),
+            (false, false) => commands.add_component(*entity, Hidden),
+            _ => {}
+        }
+    }
+}
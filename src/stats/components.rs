@@ -0,0 +1,304 @@
+use serde::Deserialize;
+
+/// The attributes every other stat is derived from
+#[derive(Copy, Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Attributes {
+    pub strength: f32,
+    pub agility: f32,
+    pub vitality: f32,
+}
+
+impl Attributes {
+    /// `self + other * factor`, attribute by attribute
+    pub fn plus(self, other: Attributes, factor: f32) -> Self {
+        Self {
+            strength: self.strength + other.strength * factor,
+            agility: self.agility + other.agility * factor,
+            vitality: self.vitality + other.vitality * factor,
+        }
+    }
+}
+
+pub struct Level {
+    pub level: u32,
+    pub experience: f32,
+}
+
+impl Level {
+    pub fn new(level: u32) -> Self {
+        Self {
+            level,
+            experience: 0.0,
+        }
+    }
+}
+
+/// Experience granted to whoever deals the killing blow
+pub struct ExperienceReward(pub f32);
+
+/// Hit points regained every second, before any status effects
+pub struct Regeneration(pub f32);
+
+/// Stats after every status effect has had its say, recomputed every frame.
+/// The base components (`Speed`, `Acceleration`, `Regeneration`) are left untouched.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DerivedStats {
+    pub speed: f32,
+    pub acceleration: f32,
+    pub regeneration: f32,
+    pub stunned: bool,
+}
+
+/// The stats that status effects can modify
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Stat {
+    Speed,
+    Acceleration,
+    Regeneration,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Modifier {
+    Add(f32),
+    Multiply(f32),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Stacking {
+    // A single instance, renewed by every new application
+    Refresh,
+    // A single instance, replaced only by something stronger
+    Strongest,
+    // Independent instances, up to a limit
+    Stack(usize),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize)]
+#[allow(dead_code)]
+pub enum EffectKind {
+    // Damage per second, dealt in ticks
+    Poison,
+    // Fraction of speed and acceleration lost
+    Slow,
+    // Fraction of speed and acceleration gained
+    Haste,
+    // Extra hit points regained per second
+    Regeneration,
+    // Can neither move nor attack
+    Stun,
+}
+
+impl EffectKind {
+    pub fn stacking(self) -> Stacking {
+        match self {
+            EffectKind::Poison => Stacking::Stack(5),
+            EffectKind::Slow | EffectKind::Haste => Stacking::Strongest,
+            EffectKind::Regeneration | EffectKind::Stun => Stacking::Refresh,
+        }
+    }
+
+    /// Seconds between ticks, for effects that do something periodically
+    pub fn tick_interval(self) -> Option<f32> {
+        match self {
+            EffectKind::Poison => Some(1.0),
+            _ => None,
+        }
+    }
+
+    pub fn modifiers(self, magnitude: f32) -> Vec<(Stat, Modifier)> {
+        match self {
+            EffectKind::Slow => vec![
+                (Stat::Speed, Modifier::Multiply(1.0 - magnitude)),
+                (Stat::Acceleration, Modifier::Multiply(1.0 - magnitude)),
+            ],
+            EffectKind::Haste => vec![
+                (Stat::Speed, Modifier::Multiply(1.0 + magnitude)),
+                (Stat::Acceleration, Modifier::Multiply(1.0 + magnitude)),
+            ],
+            EffectKind::Regeneration => vec![(Stat::Regeneration, Modifier::Add(magnitude))],
+            EffectKind::Poison | EffectKind::Stun => vec![],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct StatusEffect {
+    pub kind: EffectKind,
+    pub magnitude: f32,
+    // Seconds left
+    pub remaining: f32,
+    // Seconds until the next tick
+    pub until_tick: f32,
+}
+
+impl StatusEffect {
+    pub fn new(kind: EffectKind, magnitude: f32, duration: f32) -> Self {
+        Self {
+            kind,
+            magnitude,
+            remaining: duration,
+            until_tick: kind.tick_interval().unwrap_or(0.0),
+        }
+    }
+
+    /// Runs the effect's clock and returns how many times it ticked
+    pub fn advance(&mut self, seconds: f32) -> u32 {
+        self.remaining -= seconds;
+        let interval = match self.kind.tick_interval() {
+            Some(interval) => interval,
+            None => return 0,
+        };
+        // Ticks that came due before the effect ran out still count
+        let mut ticks = 0;
+        self.until_tick -= seconds;
+        while self.until_tick <= 0.0 && self.until_tick <= self.remaining {
+            self.until_tick += interval;
+            ticks += 1;
+        }
+        ticks
+    }
+
+    pub fn has_expired(&self) -> bool { self.remaining <= 0.0 }
+}
+
+/// Every status effect an entity is under
+#[derive(Default)]
+pub struct StatusEffects(pub Vec<StatusEffect>);
+
+impl StatusEffects {
+    pub fn apply(&mut self, effect: StatusEffect) {
+        let mut same_kind = self.0.iter_mut().filter(|e| e.kind == effect.kind);
+        match effect.kind.stacking() {
+            Stacking::Refresh => match same_kind.next() {
+                Some(existing) => *existing = effect,
+                None => self.0.push(effect),
+            },
+            Stacking::Strongest => match same_kind.next() {
+                Some(existing) if effect.magnitude > existing.magnitude => *existing = effect,
+                Some(existing) if effect.magnitude == existing.magnitude => {
+                    existing.remaining = existing.remaining.max(effect.remaining)
+                }
+                Some(_) => {}
+                None => self.0.push(effect),
+            },
+            Stacking::Stack(limit) => {
+                if same_kind.count() < limit {
+                    self.0.push(effect);
+                } else if let Some(closest_to_expiring) = self
+                    .0
+                    .iter_mut()
+                    .filter(|e| e.kind == effect.kind)
+                    .min_by(|a, b| a.remaining.partial_cmp(&b.remaining).unwrap())
+                {
+                    *closest_to_expiring = effect;
+                }
+            }
+        }
+    }
+
+    pub fn is_stunned(&self) -> bool { self.0.iter().any(|e| e.kind == EffectKind::Stun) }
+
+    /// Runs a base value through every modifier on the stat,
+    /// additions first and multiplications after
+    pub fn modify(&self, stat: Stat, base: f32) -> f32 {
+        let modifiers = self
+            .0
+            .iter()
+            .flat_map(|effect| effect.kind.modifiers(effect.magnitude))
+            .filter(|&(modified, _)| modified == stat)
+            .map(|(_, modifier)| modifier)
+            .collect::<Vec<_>>();
+
+        let added = modifiers
+            .iter()
+            .fold(base, |value, modifier| match modifier {
+                Modifier::Add(amount) => value + amount,
+                Modifier::Multiply(_) => value,
+            });
+        modifiers
+            .iter()
+            .fold(added, |value, modifier| match modifier {
+                Modifier::Add(_) => value,
+                Modifier::Multiply(factor) => value * factor,
+            })
+    }
+
+    pub fn derive(&self, speed: f32, acceleration: f32, regeneration: f32) -> DerivedStats {
+        let stunned = self.is_stunned();
+        DerivedStats {
+            speed: if stunned {
+                0.0
+            } else {
+                self.modify(Stat::Speed, speed).max(0.0)
+            },
+            acceleration: self.modify(Stat::Acceleration, acceleration).max(0.0),
+            regeneration: self.modify(Stat::Regeneration, regeneration),
+            stunned,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stacking_rules() {
+        let mut effects = StatusEffects::default();
+
+        for _ in 0..7 {
+            effects.apply(StatusEffect::new(EffectKind::Poison, 1.0, 3.0));
+        }
+        effects.apply(StatusEffect::new(EffectKind::Slow, 0.5, 1.0));
+        effects.apply(StatusEffect::new(EffectKind::Slow, 0.2, 10.0));
+        effects.apply(StatusEffect::new(EffectKind::Stun, 0.0, 1.0));
+        effects.apply(StatusEffect::new(EffectKind::Stun, 0.0, 0.5));
+
+        let count = |kind| effects.0.iter().filter(|e| e.kind == kind).count();
+        assert_eq!(count(EffectKind::Poison), 5);
+        assert_eq!(count(EffectKind::Slow), 1);
+        assert_eq!(count(EffectKind::Stun), 1);
+
+        let slow = effects
+            .0
+            .iter()
+            .find(|e| e.kind == EffectKind::Slow)
+            .unwrap();
+        assert_eq!(slow.magnitude, 0.5);
+        let stun = effects
+            .0
+            .iter()
+            .find(|e| e.kind == EffectKind::Stun)
+            .unwrap();
+        assert_eq!(stun.remaining, 0.5);
+    }
+
+    #[test]
+    fn modifiers_leave_base_stats_alone() {
+        let mut effects = StatusEffects::default();
+        effects.apply(StatusEffect::new(EffectKind::Slow, 0.5, 1.0));
+        effects.apply(StatusEffect::new(EffectKind::Regeneration, 2.0, 1.0));
+
+        let derived = effects.derive(4.0, 10.0, 1.0);
+        assert_eq!(derived.speed, 2.0);
+        assert_eq!(derived.acceleration, 5.0);
+        assert_eq!(derived.regeneration, 3.0);
+
+        effects.apply(StatusEffect::new(EffectKind::Stun, 0.0, 1.0));
+        assert_eq!(effects.derive(4.0, 10.0, 1.0).speed, 0.0);
+    }
+
+    #[test]
+    fn effects_tick_until_they_expire() {
+        let mut poison = StatusEffect::new(EffectKind::Poison, 1.0, 3.0);
+        assert_eq!(poison.advance(0.5), 0);
+        assert_eq!(poison.advance(0.75), 1);
+        assert_eq!(poison.advance(2.5), 2);
+        assert!(poison.has_expired());
+        assert_eq!(
+            StatusEffect::new(EffectKind::Slow, 0.5, 1.0).advance(2.0),
+            0
+        );
+    }
+}
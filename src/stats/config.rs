@@ -0,0 +1,146 @@
+use std::path::Path;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::components::HitPoints;
+use crate::stats::components::Attributes;
+
+pub const STATS_PATH: &str = "assets/Data/stats.ron";
+
+/// A stat as a weighted sum of attributes
+#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Formula {
+    pub base: f32,
+    pub strength: f32,
+    pub agility: f32,
+    pub vitality: f32,
+}
+
+impl Formula {
+    pub fn of(&self, attributes: &Attributes) -> f32 {
+        self.base
+            + self.strength * attributes.strength
+            + self.agility * attributes.agility
+            + self.vitality * attributes.vitality
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Formulas {
+    pub max_health: Formula,
+    pub speed: Formula,
+    pub acceleration: Formula,
+    pub regeneration: Formula,
+    pub damage: Formula,
+}
+
+impl Formulas {
+    /// Full health
+    pub fn hit_points(&self, attributes: &Attributes) -> HitPoints {
+        let max = self.max_health.of(attributes);
+        HitPoints { max, health: max }
+    }
+}
+
+/// How a kind of creature grows stronger the deeper it is found
+#[derive(Deserialize)]
+pub struct CreatureScaling {
+    pub base: Attributes,
+    pub per_floor: Attributes,
+    // Fraction each attribute is randomly nudged by
+    pub variance: f32,
+    // Experience granted per level of the creature
+    pub experience: f32,
+}
+
+impl CreatureScaling {
+    pub fn attributes<R: Rng>(&self, floor: i32, rng: &mut R) -> Attributes {
+        let scaled = self.base.plus(self.per_floor, (floor - 1).max(0) as f32);
+        let mut nudge = |value: f32| value * (1.0 + rng.gen_range(-1.0..=1.0) * self.variance);
+        Attributes {
+            strength: nudge(scaled.strength),
+            agility: nudge(scaled.agility),
+            vitality: nudge(scaled.vitality),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Levelling {
+    // Experience needed to reach level 2
+    pub first_level_up: f32,
+    // How much more every following level needs
+    pub growth: f32,
+    pub per_level: Attributes,
+}
+
+impl Levelling {
+    /// Experience needed to go from `level` to the next one
+    pub fn experience_needed(&self, level: u32) -> f32 {
+        self.first_level_up * self.growth.powi(level.max(1) as i32 - 1)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StatsConfig {
+    pub formulas: Formulas,
+    pub levelling: Levelling,
+    pub player: Attributes,
+    pub monster: CreatureScaling,
+    pub vermin: CreatureScaling,
+}
+
+impl StatsConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        ron::de::from_str(&data).map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    #[test]
+    fn shipped_config_loads() {
+        let config = StatsConfig::load(Path::new(STATS_PATH)).unwrap();
+        assert!(config.formulas.max_health.of(&config.player) > 0.0);
+    }
+
+    #[test]
+    fn creatures_scale_with_depth() {
+        let scaling = CreatureScaling {
+            base: Attributes {
+                strength: 2.0,
+                agility: 1.0,
+                vitality: 1.0,
+            },
+            per_floor: Attributes {
+                strength: 1.0,
+                ..Default::default()
+            },
+            variance: 0.0,
+            experience: 1.0,
+        };
+        let mut rng = StepRng::new(0, 1);
+        assert_eq!(scaling.attributes(1, &mut rng).strength, 2.0);
+        assert_eq!(scaling.attributes(4, &mut rng).strength, 5.0);
+        assert_eq!(scaling.attributes(4, &mut rng).agility, 1.0);
+    }
+
+    #[test]
+    fn levels_get_harder_to_reach() {
+        let levelling = Levelling {
+            first_level_up: 10.0,
+            growth: 2.0,
+            per_level: Attributes::default(),
+        };
+        assert_eq!(levelling.experience_needed(1), 10.0);
+        assert_eq!(levelling.experience_needed(3), 40.0);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod components;
+pub mod config;
+pub mod systems;
+
+pub use systems::StatsUnit;
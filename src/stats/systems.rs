@@ -0,0 +1,152 @@
+use application::UnitStage;
+use entity_smith::{Acceleration, FrameTime, Speed};
+use legion::systems::{Builder, ParallelRunnable};
+use legion::{component, maybe_changed, Entity, IntoQuery, SystemBuilder};
+
+use crate::combat::components::MeleeAttack;
+use crate::components::{HitPoints, Player};
+use crate::items::inventory::Equipment;
+use crate::items::registry::ItemRegistry;
+use crate::stats::components::{
+    Attributes, DerivedStats, EffectKind, Level, Regeneration, StatusEffects,
+};
+use crate::stats::config::StatsConfig;
+use crate::systems::hit_point_regen_system;
+
+pub struct StatsUnit;
+
+impl application::Unit for StatsUnit {
+    fn add_systems(&self, stage: UnitStage, builder: &mut Builder) {
+        if let UnitStage::Logic = stage {
+            builder
+                .add_system(level_up_system())
+                .add_system(attribute_system())
+                .add_system(status_effect_system())
+                .add_system(stat_pipeline_system())
+                .add_system(hit_point_regen_system());
+        }
+    }
+}
+
+fn level_up_system() -> impl ParallelRunnable {
+    SystemBuilder::new("level_up")
+        .read_resource::<StatsConfig>()
+        .read_resource::<Player>()
+        .with_query(<(Entity, &mut Level, &mut Attributes)>::query())
+        .build(move |_, world, (config, player), query| {
+            query.for_each_mut(world, |(entity, level, attributes)| {
+                let levelling = &config.levelling;
+                while level.experience >= levelling.experience_needed(level.level) {
+                    level.experience -= levelling.experience_needed(level.level);
+                    level.level += 1;
+                    *attributes = attributes.plus(levelling.per_level, 1.0);
+                    if *entity == player.player {
+                        println!("Level up! You are now level {}", level.level);
+                    }
+                }
+            });
+        })
+}
+
+/// Keeps the base stats in line with the attributes they are derived from,
+/// counting whatever bonuses the entity's equipment grants
+fn attribute_system() -> impl ParallelRunnable {
+    SystemBuilder::new("attributes")
+        .read_resource::<StatsConfig>()
+        .read_resource::<ItemRegistry>()
+        .with_query(
+            <(
+                &Attributes,
+                Option<&Equipment>,
+                Option<&mut HitPoints>,
+                Option<&mut Speed>,
+                Option<&mut Acceleration>,
+                Option<&mut Regeneration>,
+                Option<&mut MeleeAttack>,
+            )>::query()
+            .filter(maybe_changed::<Attributes>() | maybe_changed::<Equipment>()),
+        )
+        .build(move |_, world, (config, registry), query| {
+            let formulas = &config.formulas;
+            query.for_each_mut(
+                world,
+                |(attributes, equipment, hp, speed, acceleration, regeneration, melee)| {
+                    let attributes = &match equipment {
+                        Some(equipment) => attributes.plus(equipment.bonus(registry), 1.0),
+                        None => *attributes,
+                    };
+                    if let Some(hp) = hp {
+                        let max = formulas.max_health.of(attributes);
+                        // The dead stay dead, the living get to keep any extra hit points
+                        if hp.health > 0.0 {
+                            hp.health = (hp.health + (max - hp.max).max(0.0)).min(max);
+                        }
+                        hp.max = max;
+                    }
+                    if let Some(speed) = speed {
+                        speed.0 = formulas.speed.of(attributes);
+                    }
+                    if let Some(acceleration) = acceleration {
+                        acceleration.0 = formulas.acceleration.of(attributes);
+                    }
+                    if let Some(regeneration) = regeneration {
+                        regeneration.0 = formulas.regeneration.of(attributes);
+                    }
+                    if let Some(melee) = melee {
+                        melee.damage = formulas.damage.of(attributes);
+                    }
+                },
+            );
+        })
+}
+
+fn status_effect_system() -> impl ParallelRunnable {
+    SystemBuilder::new("status_effects")
+        .read_resource::<FrameTime>()
+        .with_query(<(&mut StatusEffects, Option<&mut HitPoints>)>::query())
+        .build(move |_, world, frame_time, query| {
+            query.for_each_mut(world, |(effects, mut hp)| {
+                for effect in effects.0.iter_mut() {
+                    let ticks = effect.advance(frame_time.0);
+                    if let (EffectKind::Poison, Some(hp)) = (effect.kind, hp.as_mut()) {
+                        let interval = effect.kind.tick_interval().unwrap_or(0.0);
+                        let damage = effect.magnitude * interval * ticks as f32;
+                        hp.health = (hp.health - damage).max(0.0);
+                    }
+                }
+                effects.0.retain(|effect| !effect.has_expired());
+            });
+        })
+}
+
+fn stat_pipeline_system() -> impl ParallelRunnable {
+    SystemBuilder::new("stat_pipeline")
+        .with_query(
+            <(
+                Entity,
+                Option<&Speed>,
+                Option<&Acceleration>,
+                Option<&Regeneration>,
+                Option<&StatusEffects>,
+                Option<&mut DerivedStats>,
+            )>::query()
+            .filter(component::<Speed>() | component::<Regeneration>()),
+        )
+        .build(move |cmd, world, _, query| {
+            let unaffected = StatusEffects::default();
+            query.for_each_mut(
+                world,
+                |(entity, speed, acceleration, regeneration, effects, derived)| {
+                    let stats = effects.unwrap_or(&unaffected).derive(
+                        speed.map_or(0.0, |speed| speed.0),
+                        acceleration.map_or(0.0, |acceleration| acceleration.0),
+                        regeneration.map_or(0.0, |regeneration| regeneration.0),
+                    );
+                    match derived {
+                        Some(derived) => *derived = stats,
+                        None => cmd.add_component(*entity, stats),
+                    }
+                },
+            );
+        })
+}
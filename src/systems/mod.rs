@@ -9,6 +9,7 @@ use physics::Velocity;
 use transforms::{Position, Rotation};
 
 use crate::components::{AIFollow, Destination, HitPoints};
+use crate::stats::components::DerivedStats;
 
 pub mod player;
 
@@ -19,38 +20,47 @@ pub(crate) fn order_tester(message: &'static str) -> impl ParallelRunnable {
     })
 }
 
-#[allow(dead_code)]
 pub fn hit_point_regen_system() -> impl ParallelRunnable {
     SystemBuilder::new("hit_point_regen")
         .read_resource::<FrameTime>()
-        .with_query(<(::legion::Entity, ::legion::Write<HitPoints>)>::query())
+        .with_query(<(
+            ::legion::Entity,
+            ::legion::Write<HitPoints>,
+            ::legion::TryRead<DerivedStats>,
+        )>::query())
         .build(move |cmd, world, resources, query| {
             let (mut for_query, mut world) = world.split_for_query(query);
             let for_query = &mut for_query;
             query.for_each_mut(for_query, |components| {
-                hit_point_regen(&mut world, cmd, &*resources, components.0, components.1);
+                hit_point_regen(
+                    &mut world,
+                    cmd,
+                    &*resources,
+                    components.0,
+                    components.1,
+                    components.2,
+                );
             });
         })
 }
-#[allow(dead_code)]
 pub fn hit_point_regen(
     _world: &mut SubWorld,
     commands: &mut CommandBuffer,
     frame_time: &FrameTime,
     ent: &Entity,
     hp: &mut HitPoints,
+    stats: Option<&DerivedStats>,
 ) {
     if hp.health <= 0.0 {
         commands.remove_component::<AIFollow>(*ent);
         commands.remove_component::<Destination>(*ent);
     } else {
-        hp.health += 0.7654321 * frame_time.0;
+        hp.health += stats.map_or(0.0, |stats| stats.regeneration) * frame_time.0;
         hp.health = hp.max.min(hp.health);
     }
 }
 
-#[allow(unused)]
-fn ai_follow_system() -> impl ParallelRunnable {
+pub fn ai_follow_system() -> impl ParallelRunnable {
     SystemBuilder::new("ai_follow")
         .read_component::<AIFollow>()
         .read_component::<Position>()
@@ -61,7 +71,6 @@ fn ai_follow_system() -> impl ParallelRunnable {
         })
 }
 
-#[allow(dead_code)]
 fn ai_follow(world: &mut SubWorld, command: &mut CommandBuffer) {
     let mut query = <(Entity, TryWrite<Rotation>, &AIFollow, &Position)>::query();
     let (mut hunter_world, hunted_world) = world.split_for_query(&query);
@@ -89,6 +98,7 @@ pub fn go_to_destination_system() -> impl ParallelRunnable {
         .read_component::<Position>()
         .read_component::<Speed>()
         .read_component::<Acceleration>()
+        .read_component::<DerivedStats>()
         .write_component::<Destination>()
         .write_component::<Velocity>()
         .read_resource::<FrameTime>()
@@ -110,21 +120,27 @@ pub fn go_to_destination(
         &mut Velocity,
         &Speed,
         &Acceleration,
+        Option<&DerivedStats>,
     )>::query();
-    for (ent, dest, hunter, vel, speed, accel) in query.iter_mut(world) {
+    for (ent, dest, hunter, vel, speed, accel, stats) in query.iter_mut(world) {
+        // Status effects have the final say on how fast anyone moves
+        let (speed, accel) = match stats {
+            Some(stats) => (stats.speed, stats.acceleration),
+            None => (speed.0, accel.0),
+        };
         let to_dest: Vector2<f32> = dest.goal - hunter.0.truncate();
         if to_dest.magnitude() < EPSILON {
             commands.remove_component::<Destination>(*ent);
             vel.0 = Vector2::new(0.0, 0.0);
         } else {
             let direction = to_dest.normalize();
-            let time_to_stop = speed.0 / accel.0;
+            let time_to_stop = speed / accel;
             let slowdown = FRAC_PI_2
                 .min(to_dest.magnitude() / time_to_stop * 0.5)
                 .sin();
-            let target_velocity = direction * speed.0 * slowdown;
+            let target_velocity = direction * speed * slowdown;
             let delta: Vector2<f32> = target_velocity - vel.0;
-            let velocity_change = (accel.0 * frame_time.0).min(delta.magnitude());
+            let velocity_change = (accel * frame_time.0).min(delta.magnitude());
             if delta != Vector2::unit_x() * 0.0 {
                 vel.0 += delta.normalize() * velocity_change;
             }
@@ -1,18 +1,20 @@
 use std::f32::consts::PI;
 
 use cgmath::num_traits::clamp;
-use cgmath::{Deg, InnerSpace, Vector2, Vector3};
+use cgmath::{Deg, InnerSpace, MetricSpace, Vector2, Vector3};
 use entity_smith::Smith;
 use graphics::components::{Camera, Target};
 use input::{Command, CommandManager, InputState};
 use legion::systems::ParallelRunnable;
 use legion::world::SubWorld;
-use legion::{EntityStore, IntoQuery, SystemBuilder};
+use legion::{Entity, EntityStore, IntoQuery, SystemBuilder};
 use physics::Velocity;
 use transforms::{Position, Rotation, SphericalOffset, Transform};
 
+use crate::combat::components::{AttackCommand, ShootCommand};
 use crate::components::{Destination, HitPoints, Player, PlayerCamera};
-use crate::world_gen::components::Faction;
+use crate::factions::Faction;
+use crate::items::components::{GroundItem, PickupTarget};
 
 pub fn camera_control_system() -> impl ParallelRunnable {
     SystemBuilder::new("camera_control_system")
@@ -126,6 +128,9 @@ pub fn camera_control(
     }
 }
 
+// How far from an item a click may land and still count as clicking on it
+const ITEM_CLICK_RADIUS: f32 = 0.5;
+
 pub fn player_system() -> impl ParallelRunnable {
     SystemBuilder::new("player_system")
         .write_component::<Rotation>()
@@ -136,7 +141,9 @@ pub fn player_system() -> impl ParallelRunnable {
         .read_component::<Target>()
         .read_component::<Faction>()
         .read_component::<HitPoints>()
+        .read_component::<GroundItem>()
         .read_resource::<InputState>()
+        .read_resource::<CommandManager>()
         .read_resource::<graphics::GraphicsContext>()
         .read_resource::<Player>()
         .read_resource::<PlayerCamera>()
@@ -148,6 +155,7 @@ pub fn player_system() -> impl ParallelRunnable {
                 &resources.1,
                 &resources.2,
                 &resources.3,
+                &resources.4,
             )
         })
 }
@@ -156,6 +164,7 @@ pub fn player(
     world: &mut SubWorld,
     commands: &mut legion::systems::CommandBuffer,
     input: &InputState,
+    command_manager: &CommandManager,
     context: &graphics::GraphicsContext,
     player: &Player,
     player_cam: &PlayerCamera,
@@ -166,10 +175,12 @@ pub fn player(
     let (mut orient_world, world) = world.split::<&mut Rotation>();
 
     let mouse_pos = input.mouse.pos;
+    let attacking = command_manager.get(Command::PlayerAttack);
+    let shooting = command_manager.get(Command::PlayerShoot);
 
-    // Click to move around
+    // Click to move around, the player also attacks towards whatever is under the cursor
     // Note(Jökull): We need to make this prettier
-    if input.mouse.left.down {
+    if input.mouse.left.down || attacking || shooting {
         // TODO: Clean up
 
         let mut camera: &mut Camera = <&mut Camera>::query()
@@ -199,10 +210,23 @@ pub fn player(
             let t: f32 = mouse_world_pos.z / ray_delta.z;
             let ray_hit = (mouse_world_pos - ray_delta * t).truncate();
 
-            commands
-                .forge(player.player)
-                .any(Destination::simple(ray_hit));
-            camera.roaming = false;
+            if input.mouse.left.down {
+                commands
+                    .forge(player.player)
+                    .any(Destination::simple(ray_hit));
+                camera.roaming = false;
+
+                // Clicking on an item walks over and picks it up
+                let clicked_item = <(Entity, &Position, &GroundItem)>::query()
+                    .iter(&world)
+                    .map(|(entity, position, _)| (*entity, position.0.truncate().distance(ray_hit)))
+                    .filter(|(_, distance)| *distance <= ITEM_CLICK_RADIUS)
+                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+                match clicked_item {
+                    Some((item, _)) => commands.add_component(player.player, PickupTarget(item)),
+                    None => commands.remove_component::<PickupTarget>(player.player),
+                }
+            }
 
             let difference: Vector2<f32> = {
                 let player_pos = <&Transform>::query()
@@ -212,6 +236,13 @@ pub fn player(
                 ray_hit - player_pos.truncate()
             };
 
+            if attacking {
+                commands.add_component(player.player, AttackCommand::towards(difference));
+            }
+            if shooting {
+                commands.add_component(player.player, ShootCommand::towards(difference));
+            }
+
             let mut new_rotation = (difference.y / difference.x).atan() / PI * 180.0;
             if difference.x > 0.0 {
                 new_rotation += 180.0;
@@ -224,15 +255,4 @@ pub fn player(
             }
         }
     }
-
-    //if input.is_key_pressed(Key::Space) {
-    //    for (ent, pos, &HitPoints { max, health }, &faction, dynamic) in (&ents, &pos, &hp, &faction, &dynamic).join() {
-    //        let forward_vector = cgmath::Basis2::<f32>::from_angle(player_orient.0).rotate_vector(-Vector2::unit_x());
-    //        let in_front = (pos.0 - player_pos.0).normalize().dot(forward_vector.normalize()) > 0.5;
-    //        if faction == Faction::Enemies && pos.0.distance(player_pos.0) < 2.0 && in_front {
-    //            updater.insert(ent, HitPoints { max, health: (health - 1.0).max(0.0) });
-    //            updater.insert(ent, Velocity((pos.0 - player_pos.0).normalize() * 1.5 / dynamic.0));
-    //        }
-    //    }
-    //}
 }
@@ -0,0 +1,101 @@
+use crate::world_gen::components::{Direction, TileType};
+use crate::world_gen::grid::{Grid, V2i, V2u};
+
+// The four sides of a tile, in the order corners go round:
+// a corner is named after the first of the two sides it is between.
+// North is towards larger y, like everywhere else in the tiles.
+const SIDES: [(Direction, V2i); 4] = [
+    (Direction::North, V2i::new(0, 1)),
+    (Direction::West, V2i::new(-1, 0)),
+    (Direction::South, V2i::new(0, -1)),
+    (Direction::East, V2i::new(1, 0)),
+];
+
+fn side(index: u32) -> Direction { SIDES[index as usize % 4].0 }
+
+/// Works out which piece of the wall kit every undirected wall should be,
+/// from which of the tiles around it can be walked on.
+/// Walls that already know which way they face, like the ones read from pictures, are left alone.
+pub fn autotile(tiles: &mut Grid<TileType>) {
+    let mut directed = vec![];
+    for y in 0..tiles.size.y {
+        for x in 0..tiles.size.x {
+            if tiles[V2u::new(x, y)] != TileType::UndirectedWall {
+                continue;
+            }
+            let at = V2i::new(x as isize, y as isize);
+            let open =
+                |offset: V2i| matches!(tiles.get(at + offset), Some(tile) if tile.is_walkable());
+
+            // One bit per side, and one per corner between a side and the next
+            let (mut sides, mut corners) = (0u8, 0u8);
+            for (i, &(_, offset)) in SIDES.iter().enumerate() {
+                let (_, next) = SIDES[(i + 1) % 4];
+                if open(offset) {
+                    sides |= 1 << i;
+                }
+                if open(offset + next) {
+                    corners |= 1 << i;
+                }
+            }
+            directed.push((V2u::new(x, y), wall_from_masks(sides, corners)));
+        }
+    }
+    for (at, tile_type) in directed {
+        tiles[at] = tile_type;
+    }
+}
+
+fn wall_from_masks(sides: u8, corners: u8) -> TileType {
+    let corner_out = (0..4).find(|&i| sides == (1 << i) | (1 << ((i + 1) % 4)));
+    match (sides.count_ones(), corner_out, corners.count_ones()) {
+        // Nothing can see it, so it's just rock
+        (0, _, 0) => TileType::Nothing,
+        // A wall faces away from the side it is walked along
+        (1, _, _) => TileType::Wall(side(sides.trailing_zeros() + 2)),
+        // Walked around on two sides, the corner of a wall that sticks out
+        (2, Some(i), _) => TileType::CornerOut(side(i)),
+        // Only seen from across a corner, the corner of a room
+        (0, _, 1) => TileType::CornerIn(side(corners.trailing_zeros() + 2)),
+        // Anything thinner than the wall kit allows for stays a plain block
+        _ => TileType::UndirectedWall,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid(rows: &[&str]) -> Grid<TileType> {
+        let mut tiles = Grid::new();
+        tiles.resize(V2u::new(rows[0].len(), rows.len()), TileType::Nothing);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, tile) in row.chars().enumerate() {
+                tiles[V2u::new(x, y)] = match tile {
+                    '#' => TileType::UndirectedWall,
+                    '.' => TileType::Floor,
+                    _ => TileType::Nothing,
+                };
+            }
+        }
+        tiles
+    }
+
+    #[test]
+    fn walls_face_away_from_the_floor() {
+        let mut tiles = grid(&["#####", "#...#", "#...#", "##.##", "##.# "]);
+        autotile(&mut tiles);
+        let at = |x, y| tiles[V2u::new(x, y)];
+
+        assert!(at(2, 0) == TileType::Wall(Direction::South));
+        assert!(at(0, 1) == TileType::Wall(Direction::West));
+        assert!(at(4, 2) == TileType::Wall(Direction::East));
+        assert!(at(1, 4) == TileType::Wall(Direction::West));
+        assert!(at(0, 0) == TileType::CornerIn(Direction::West));
+        assert!(at(4, 0) == TileType::CornerIn(Direction::South));
+        assert!(at(0, 3) == TileType::CornerIn(Direction::North));
+        assert!(at(1, 3) == TileType::CornerOut(Direction::South));
+        assert!(at(3, 3) == TileType::CornerOut(Direction::West));
+        assert!(at(0, 4) == TileType::Nothing);
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::world_gen::components::TileType;
+use crate::world_gen::generator::{grid_from_tiles, GeneratedLevel, LevelGenerator};
+use crate::world_gen::rooms::{Rect, RoomGraph};
+
+type Location = (i32, i32);
+
+/// Rooms and corridors laid out by splitting the map in two, and each half in two again,
+/// until the pieces are too small to split and every piece gets a room.
+/// Unlike `DungGen` it always finishes, however little room there is:
+/// maps smaller than 3x3 are made 3x3, which leaves a single tile of floor inside the walls.
+///
+/// usage:
+/// ```
+///let (tiles, rooms) = Bsp::default().generate(&mut rng);
+/// ```
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Bsp {
+    pub width: i32,
+    pub height: i32,
+
+    // Pieces are never split into anything narrower than this
+    pub min_leaf: i32,
+    // How many times the map is split at most, at most 2^max_depth rooms
+    pub max_depth: u32,
+    // Where along a piece it is split, as the smallest and largest fraction of it
+    pub split_ratio: (f64, f64),
+
+    // The smallest width and height of a room
+    pub room_min: i32,
+    // The space kept free between a room and the edge of its piece
+    pub room_padding: i32,
+
+    // How many corridors join rooms that are already joined some other way
+    pub loops: usize,
+    // How many corridors lead off from a room and stop in the middle of nowhere
+    pub dead_ends: usize,
+    // The longest a dead end can be
+    pub dead_end_length: i32,
+}
+
+impl Default for Bsp {
+    fn default() -> Self {
+        Bsp {
+            width: 80,
+            height: 60,
+            min_leaf: 10,
+            max_depth: 5,
+            split_ratio: (0.35, 0.65),
+            room_min: 4,
+            room_padding: 1,
+            loops: 2,
+            dead_ends: 2,
+            dead_end_length: 8,
+        }
+    }
+}
+
+impl Bsp {
+    pub fn generate<R: Rng>(&self, rng: &mut R) -> (HashMap<Location, TileType>, RoomGraph) {
+        let (width, height) = (self.width.max(3), self.height.max(3));
+        // The outermost tiles are kept for the walls
+        let bounds = Rect {
+            x: 1,
+            y: 1,
+            width: width - 2,
+            height: height - 2,
+        };
+        let mut carved = HashMap::new();
+        let mut corridors = vec![];
+        let rooms = self.split(bounds, 0, rng, &mut corridors);
+        let mut graph = RoomGraph::default();
+        for &room in &rooms {
+            for location in room.locations() {
+                carved.insert(location, TileType::Floor);
+            }
+            graph.add_room(room);
+        }
+
+        // Extra corridors between rooms and their nearest neighbours make loops
+        for _ in 0..self.loops {
+            let from = *rooms.choose(rng).unwrap();
+            let joined = |room: Rect| {
+                room == from
+                    || corridors.contains(&(from, room))
+                    || corridors.contains(&(room, from))
+            };
+            let nearest = rooms
+                .iter()
+                .filter(|&&room| !joined(room))
+                .min_by_key(|room| distance(room.center(), from.center()));
+            if let Some(&to) = nearest {
+                corridors.push((from, to));
+            }
+        }
+        for &(from, to) in &corridors {
+            let bend = if rng.gen() {
+                (to.center().0, from.center().1)
+            } else {
+                (from.center().0, to.center().1)
+            };
+            let mut tiles = carve(&mut carved, from.center(), bend);
+            tiles.extend(carve(&mut carved, bend, to.center()));
+            let index = |room| rooms.iter().position(|&other| other == room).unwrap();
+            graph.connect(index(from), index(to), tiles);
+        }
+
+        for _ in 0..self.dead_ends {
+            if let Some(room) = rooms.choose(rng) {
+                self.dead_end(&mut carved, &bounds, room, rng);
+            }
+        }
+
+        let mut world = carved.clone();
+        for &(x, y) in carved.keys() {
+            for neighbour in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy))) {
+                world.entry(neighbour).or_insert(TileType::UndirectedWall);
+            }
+        }
+        for y in 0..height {
+            for x in 0..width {
+                world.entry((x, y)).or_insert(TileType::Nothing);
+            }
+        }
+        (world, graph)
+    }
+
+    /// Splits the piece as long as it is big enough, returning the rooms in it
+    /// and adding the corridors that join its halves together
+    fn split<R: Rng>(
+        &self,
+        piece: Rect,
+        depth: u32,
+        rng: &mut R,
+        corridors: &mut Vec<(Rect, Rect)>,
+    ) -> Vec<Rect> {
+        let (low, high) = self.split_ratio;
+        let (low, high) = (low.min(high).max(0.0), high.max(low).min(1.0));
+
+        // Long pieces are split across, so that rooms don't come out as corridors
+        let across_x = if piece.width > piece.height * 5 / 4 {
+            true
+        } else if piece.height > piece.width * 5 / 4 {
+            false
+        } else {
+            rng.gen()
+        };
+        let length = if across_x { piece.width } else { piece.height };
+        let at = (length as f64 * rng.gen_range(low..=high)) as i32;
+        let min_leaf = self.min_leaf.max(1);
+        let at = at.max(min_leaf).min(length - min_leaf);
+
+        if depth >= self.max_depth || length < min_leaf * 2 {
+            return vec![self.room_in(piece, rng)];
+        }
+
+        let (first, second) = if across_x {
+            (
+                Rect { width: at, ..piece },
+                Rect {
+                    x: piece.x + at,
+                    width: piece.width - at,
+                    ..piece
+                },
+            )
+        } else {
+            (
+                Rect {
+                    height: at,
+                    ..piece
+                },
+                Rect {
+                    y: piece.y + at,
+                    height: piece.height - at,
+                    ..piece
+                },
+            )
+        };
+        let first = self.split(first, depth + 1, rng, corridors);
+        let second = self.split(second, depth + 1, rng, corridors);
+
+        // The halves are joined where they are closest
+        let closest = first
+            .iter()
+            .flat_map(|&a| second.iter().map(move |&b| (a, b)))
+            .min_by_key(|(a, b)| distance(a.center(), b.center()));
+        corridors.extend(closest);
+
+        first.into_iter().chain(second).collect()
+    }
+
+    /// A room somewhere in the piece, as big as it likes but no smaller than `room_min`
+    /// unless the piece itself is smaller than that
+    fn room_in<R: Rng>(&self, piece: Rect, rng: &mut R) -> Rect {
+        let padding = self
+            .room_padding
+            .min((piece.width - 1) / 2)
+            .min((piece.height - 1) / 2)
+            .max(0);
+        let (space_x, space_y) = (piece.width - padding * 2, piece.height - padding * 2);
+        let width = rng.gen_range(self.room_min.min(space_x).max(1)..=space_x.max(1));
+        let height = rng.gen_range(self.room_min.min(space_y).max(1)..=space_y.max(1));
+        Rect {
+            x: piece.x + padding + rng.gen_range(0..=space_x - width),
+            y: piece.y + padding + rng.gen_range(0..=space_y - height),
+            width,
+            height,
+        }
+    }
+
+    /// A corridor out of the room in a straight line, that ends before it runs into anything
+    fn dead_end<R: Rng>(
+        &self,
+        carved: &mut HashMap<Location, TileType>,
+        bounds: &Rect,
+        room: &Rect,
+        rng: &mut R,
+    ) {
+        let (dx, dy) = *[(1, 0), (-1, 0), (0, 1), (0, -1)].choose(rng).unwrap();
+        let (mut x, mut y) = room.center();
+        while room.contains((x, y)) {
+            x += dx;
+            y += dy;
+        }
+        for _ in 0..rng.gen_range(2..=self.dead_end_length.max(2)) {
+            // Stopping short of anything already carved keeps it from joining up
+            let ahead = (x + dx, y + dy);
+            let beside = [(x + dy, y + dx), (x - dy, y - dx)];
+            if !bounds.contains((x, y))
+                || carved.contains_key(&ahead)
+                || beside.iter().any(|side| carved.contains_key(side))
+            {
+                break;
+            }
+            carved.insert((x, y), TileType::Path);
+            x += dx;
+            y += dy;
+        }
+    }
+}
+
+fn distance((ax, ay): Location, (bx, by): Location) -> i32 { (ax - bx).abs() + (ay - by).abs() }
+
+/// Digs a straight corridor, leaving any room floor on the way as it is.
+/// Returns every tile the corridor goes through.
+fn carve(carved: &mut HashMap<Location, TileType>, from: Location, to: Location) -> Vec<Location> {
+    let (x0, x1) = (from.0.min(to.0), from.0.max(to.0));
+    let (y0, y1) = (from.1.min(to.1), from.1.max(to.1));
+    let tiles = (x0..=x1)
+        .flat_map(|x| (y0..=y1).map(move |y| (x, y)))
+        .collect::<Vec<_>>();
+    for &location in &tiles {
+        carved.entry(location).or_insert(TileType::Path);
+    }
+    tiles
+}
+
+impl LevelGenerator for Bsp {
+    fn generate(&self, _: i32, rng: &mut StdRng) -> Result<GeneratedLevel, String> {
+        let (tiles, rooms) = self.generate(rng);
+        Ok(GeneratedLevel {
+            rooms,
+            ..GeneratedLevel::empty(grid_from_tiles(&tiles))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn walkable(tile_type: TileType) -> bool {
+        tile_type == TileType::Floor || tile_type == TileType::Path
+    }
+
+    /// Every walkable tile that can be walked to from the given one
+    fn reachable(world: &HashMap<Location, TileType>, from: Location) -> Vec<Location> {
+        let mut seen = vec![from];
+        let mut next = vec![from];
+        while let Some((x, y)) = next.pop() {
+            for neighbour in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+                if matches!(world.get(neighbour), Some(&tile) if walkable(tile))
+                    && !seen.contains(neighbour)
+                {
+                    seen.push(*neighbour);
+                    next.push(*neighbour);
+                }
+            }
+        }
+        seen
+    }
+
+    #[test]
+    fn every_room_is_joined_up() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let bsp = Bsp::default();
+        let (world, rooms) = bsp.generate(&mut rng);
+
+        // Joined up as rooms, and joined up on the ground
+        let distances = rooms.distances(0);
+        assert!(rooms.rooms.len() > 4);
+        assert!(distances.iter().all(|distance| distance.is_some()));
+        assert_eq!(world.len(), (bsp.width * bsp.height) as usize);
+        let walkable = world
+            .iter()
+            .filter(|&(_, &tile)| walkable(tile))
+            .map(|(&location, _)| location)
+            .collect::<Vec<_>>();
+        assert!(walkable.len() > 100);
+        assert_eq!(reachable(&world, walkable[0]).len(), walkable.len());
+
+        // Nothing walkable on the edge, where it couldn't be walled in
+        assert!(walkable
+            .iter()
+            .all(|&(x, y)| x > 0 && y > 0 && x < bsp.width - 1 && y < bsp.height - 1));
+    }
+
+    #[test]
+    fn even_a_tiny_map_gets_a_room() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let bsp = Bsp {
+            width: 5,
+            height: 4,
+            min_leaf: 20,
+            room_min: 10,
+            ..Bsp::default()
+        };
+        let (world, rooms) = bsp.generate(&mut rng);
+        assert_eq!(rooms.rooms.len(), 1);
+        assert!(world.values().any(|&tile| tile == TileType::Floor));
+        assert!(world.keys().all(|&(x, y)| x < 5 && y < 4));
+    }
+
+    #[test]
+    fn maps_too_small_for_walls_are_made_bigger() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for &(width, height) in [(2, 2), (0, 5), (5, 1)].iter() {
+            let bsp = Bsp {
+                width,
+                height,
+                ..Bsp::default()
+            };
+            let (world, rooms) = bsp.generate(&mut rng);
+            assert_eq!(rooms.rooms.len(), 1);
+            assert!(world.get(&(1, 1)) == Some(&TileType::Floor));
+            assert!(world
+                .keys()
+                .all(|&(x, y)| x < width.max(3) && y < height.max(3)));
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use ena::unify::{InPlace, UnificationTable};
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::world_gen::components::TileType;
+use crate::world_gen::dung_gen::UnitKey;
+use crate::world_gen::generator::{grid_from_tiles, GeneratedLevel, LevelGenerator};
+use crate::world_gen::grid::{Grid, V2i, V2u};
+
+type Location = (i32, i32);
+
+/// Caves grown by cellular automata: the map starts out as noise
+/// and every step each tile becomes whatever most of its neighbours are.
+/// Caves that end up apart are tunnelled together afterwards.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Caves {
+    pub width: usize,
+    pub height: usize,
+
+    // The chance of a tile starting out as rock
+    pub fill: f64,
+    // How many times the map is smoothed
+    pub steps: u32,
+    // Caves smaller than this many tiles are filled in rather than tunnelled to
+    pub min_cave: usize,
+}
+
+impl Default for Caves {
+    fn default() -> Self {
+        Caves {
+            width: 64,
+            height: 48,
+            fill: 0.45,
+            steps: 5,
+            min_cave: 12,
+        }
+    }
+}
+
+const NEIGHBOURS: [V2i; 8] = [
+    V2i::new(-1, -1),
+    V2i::new(0, -1),
+    V2i::new(1, -1),
+    V2i::new(-1, 0),
+    V2i::new(1, 0),
+    V2i::new(-1, 1),
+    V2i::new(0, 1),
+    V2i::new(1, 1),
+];
+
+const SIDES: [V2i; 4] = [
+    V2i::new(0, -1),
+    V2i::new(-1, 0),
+    V2i::new(1, 0),
+    V2i::new(0, 1),
+];
+
+impl Caves {
+    pub fn generate<R: Rng>(&self, rng: &mut R) -> HashMap<Location, TileType> {
+        let (width, height) = (self.width.max(3), self.height.max(3));
+        let on_edge = |x: usize, y: usize| x == 0 || y == 0 || x == width - 1 || y == height - 1;
+
+        // Rock is true, the edge of the map is always rock
+        let mut rock = Grid::new();
+        rock.resize(V2u::new(width, height), true);
+        for y in 0..height {
+            for x in 0..width {
+                rock[V2u::new(x, y)] = on_edge(x, y) || rng.gen_bool(self.fill.clamp(0.0, 1.0));
+            }
+        }
+
+        for _ in 0..self.steps {
+            let mut next = rock.clone();
+            for y in 1..height - 1 {
+                for x in 1..width - 1 {
+                    let at = V2i::new(x as isize, y as isize);
+                    let walls = NEIGHBOURS
+                        .iter()
+                        .filter(|&&offset| rock.get(at + offset) != Some(&false))
+                        .count();
+                    // A tie leaves the tile as it was
+                    if walls != 4 {
+                        next[V2u::new(x, y)] = walls > 4;
+                    }
+                }
+            }
+            rock = next;
+        }
+
+        // The biggest cave is always kept, however small
+        let mut caves = caves(&rock);
+        let min_cave = caves
+            .iter()
+            .map(|cave| cave.len())
+            .max()
+            .unwrap_or(0)
+            .min(self.min_cave);
+        for cave in caves.iter().filter(|cave| cave.len() < min_cave) {
+            for &at in cave {
+                rock[at] = true;
+            }
+        }
+        caves.retain(|cave| cave.len() >= min_cave);
+
+        for (from, to) in connections(&caves) {
+            tunnel(&mut rock, from, to, rng);
+        }
+
+        let mut world = HashMap::new();
+        for y in 0..height {
+            for x in 0..width {
+                let at = V2i::new(x as isize, y as isize);
+                let tile_type = if !rock[V2u::new(x, y)] {
+                    TileType::Floor
+                } else if NEIGHBOURS
+                    .iter()
+                    .any(|&offset| rock.get(at + offset) == Some(&false))
+                {
+                    TileType::UndirectedWall
+                } else {
+                    TileType::Nothing
+                };
+                world.insert((x as i32, y as i32), tile_type);
+            }
+        }
+        world
+    }
+}
+
+/// Every separate stretch of open tiles, in the order they are found
+fn caves(rock: &Grid<bool>) -> Vec<Vec<V2u>> {
+    let mut seen = Grid::new();
+    seen.resize(rock.size, false);
+    let mut caves = vec![];
+    for y in 0..rock.size.y {
+        for x in 0..rock.size.x {
+            let start = V2u::new(x, y);
+            if rock[start] || seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut cave = vec![start];
+            let mut next = vec![start];
+            while let Some(at) = next.pop() {
+                for offset in SIDES.iter() {
+                    let neighbour = at.cast::<isize>().unwrap() + *offset;
+                    if rock.get(neighbour) == Some(&false) && seen.get(neighbour) == Some(&false) {
+                        let neighbour = neighbour.cast::<usize>().unwrap();
+                        seen[neighbour] = true;
+                        cave.push(neighbour);
+                        next.push(neighbour);
+                    }
+                }
+            }
+            caves.push(cave);
+        }
+    }
+    caves
+}
+
+fn distance(a: V2u, b: V2u) -> usize {
+    (a.x as isize - b.x as isize).unsigned_abs() + (a.y as isize - b.y as isize).unsigned_abs()
+}
+
+/// Which tiles to tunnel between so that every cave can be reached from every other one.
+/// Caves are joined closest first, skipping any that are already joined through others.
+fn connections(caves: &[Vec<V2u>]) -> Vec<(V2u, V2u)> {
+    let centers = caves
+        .iter()
+        .map(|cave| cave.iter().fold(V2u::new(0, 0), |sum, &at| sum + at) / cave.len())
+        .collect::<Vec<_>>();
+
+    let mut pairs = (0..caves.len())
+        .flat_map(|a| (a + 1..caves.len()).map(move |b| (a, b)))
+        .collect::<Vec<_>>();
+    pairs.sort_by_key(|&(a, b)| distance(centers[a], centers[b]));
+
+    let mut components: UnificationTable<InPlace<UnitKey>> = UnificationTable::new();
+    let keys = caves
+        .iter()
+        .map(|_| components.new_key(()))
+        .collect::<Vec<_>>();
+
+    let mut connections = vec![];
+    for (a, b) in pairs {
+        if components.unioned(keys[a], keys[b]) {
+            continue;
+        }
+        components.union(keys[a], keys[b]);
+
+        // From the edge of one cave to the closest edge of the other
+        let closest = |cave: &[V2u], to: V2u| {
+            *cave
+                .iter()
+                .min_by_key(|&&at| (distance(at, to), at.y, at.x))
+                .unwrap()
+        };
+        let from = closest(&caves[a], centers[b]);
+        let to = closest(&caves[b], from);
+        connections.push((from, to));
+    }
+    connections
+}
+
+/// Digs a winding tunnel, stepping along whichever way there is more of left to go
+fn tunnel<R: Rng>(rock: &mut Grid<bool>, from: V2u, to: V2u, rng: &mut R) {
+    let mut at = from;
+    while at != to {
+        let (dx, dy) = (to.x as isize - at.x as isize, to.y as isize - at.y as isize);
+        let along_x = rng.gen_range(0..dx.abs() + dy.abs()) < dx.abs();
+        if along_x {
+            at.x = (at.x as isize + dx.signum()) as usize;
+        } else {
+            at.y = (at.y as isize + dy.signum()) as usize;
+        }
+        rock[at] = false;
+    }
+}
+
+impl LevelGenerator for Caves {
+    fn generate(&self, _: i32, rng: &mut StdRng) -> Result<GeneratedLevel, String> {
+        Ok(GeneratedLevel {
+            // Doors would look out of place in a cave
+            doors: false,
+            ..GeneratedLevel::empty(grid_from_tiles(&self.generate(rng)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn every_cave_is_tunnelled_to() {
+        let generator = Caves::default();
+        let world = generator.generate(&mut StdRng::seed_from_u64(11));
+        assert_eq!(world.len(), generator.width * generator.height);
+
+        let mut rock = Grid::new();
+        rock.resize(V2u::new(generator.width, generator.height), true);
+        for (&(x, y), &tile_type) in &world {
+            rock[V2u::new(x as usize, y as usize)] = tile_type != TileType::Floor;
+        }
+        let caves = caves(&rock);
+        assert_eq!(caves.len(), 1);
+        assert!(caves[0].len() > 100);
+
+        // The edge of the map is never open
+        assert!(caves[0].iter().all(|at| at.x > 0
+            && at.y > 0
+            && at.x < generator.width - 1
+            && at.y < generator.height - 1));
+    }
+
+    #[test]
+    fn tunnels_reach_where_they_are_going() {
+        let mut rock = Grid::new();
+        rock.resize(V2u::new(10, 10), true);
+        let (from, to) = (V2u::new(1, 8), V2u::new(7, 2));
+        rock[from] = false;
+        tunnel(&mut rock, from, to, &mut StdRng::seed_from_u64(0));
+        assert!(!rock[to]);
+        assert_eq!(caves(&rock).len(), 1);
+        assert_eq!(rock.buf.iter().filter(|&&rock| !rock).count(), 13);
+    }
+}
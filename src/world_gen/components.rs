@@ -1,22 +1,17 @@
+use std::collections::HashMap;
+
 #[derive(Copy, Clone)]
 pub enum MapTransition {
     None,
-    Deeper, // Down to the next floor
+    Deeper,    // Down to the next floor
+    Shallower, // Back up to the previous floor
+    Restart,   // A fresh dungeon, starting from the top
 }
 
 pub struct MapSwitcher(pub MapTransition);
 
 pub struct FloorNumber(pub i32);
 
-#[derive(Eq, PartialEq)]
-#[derive(Copy, Clone)]
-#[allow(unused)]
-pub enum Faction {
-    Enemies,
-    Friends,
-    Frenemies,
-}
-
 #[derive(Eq, PartialEq, Copy, Clone)]
 #[allow(dead_code)]
 pub enum TileType {
@@ -29,6 +24,45 @@ pub enum TileType {
     Path,
     Nothing,
     LadderDown,
+    LadderUp,
+    Door(DoorState),
+}
+
+impl TileType {
+    /// Whether light, and thus sight, stops at this tile
+    pub fn blocks_sight(&self) -> bool {
+        matches!(
+            self,
+            TileType::Wall(_)
+                | TileType::UndirectedWall
+                | TileType::CornerIn(_)
+                | TileType::CornerOut(_)
+                | TileType::Nothing
+                | TileType::Door(DoorState::Closed)
+                | TileType::Door(DoorState::Locked)
+        )
+    }
+
+    /// Whether the player can get across this tile, locked doors included
+    /// as long as there is a key for them
+    pub fn is_walkable(&self) -> bool {
+        matches!(
+            self,
+            TileType::Floor
+                | TileType::Path
+                | TileType::LadderDown
+                | TileType::LadderUp
+                | TileType::Door(_)
+        )
+    }
+}
+
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub enum DoorState {
+    Open,
+    Closed,
+    // Closed, and only opened with a key
+    Locked,
 }
 
 #[derive(Eq, PartialEq, Copy, Clone)]
@@ -38,3 +72,57 @@ pub enum Direction {
     South,
     East,
 }
+
+/// The tiles of the floor that is currently being played.
+/// The revision is bumped on every change so that anything derived
+/// from the tiles knows when to recompute.
+#[derive(Default)]
+pub struct FloorTiles {
+    tiles: HashMap<(i32, i32), TileType>,
+    revision: u64,
+}
+
+impl FloorTiles {
+    pub fn get(&self, location: (i32, i32)) -> Option<TileType> {
+        self.tiles.get(&location).copied()
+    }
+
+    pub fn replace(&mut self, tiles: HashMap<(i32, i32), TileType>) {
+        self.tiles = tiles;
+        self.revision += 1;
+    }
+
+    /// Empties the floor, handing over the tiles it had
+    pub fn take(&mut self) -> HashMap<(i32, i32), TileType> {
+        self.revision += 1;
+        std::mem::take(&mut self.tiles)
+    }
+
+    /// Changes a single tile, like a door being opened
+    pub fn set(&mut self, location: (i32, i32), tile_type: TileType) {
+        self.tiles.insert(location, tile_type);
+        self.revision += 1;
+    }
+
+    pub fn revision(&self) -> u64 { self.revision }
+
+    /// The lowest and highest corners of the floor, if there is any floor at all
+    pub fn bounds(&self) -> Option<((i32, i32), (i32, i32))> {
+        self.tiles
+            .keys()
+            .fold(None, |bounds, &(x, y)| match bounds {
+                None => Some(((x, y), (x, y))),
+                Some(((min_x, min_y), (max_x, max_y))) => {
+                    Some(((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y))))
+                }
+            })
+    }
+
+    /// Anything off the map is solid rock
+    pub fn blocks_sight(&self, location: (i32, i32)) -> bool {
+        match self.get(location) {
+            Some(tile_type) => tile_type.blocks_sight(),
+            None => true,
+        }
+    }
+}
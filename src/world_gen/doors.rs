@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+
+use crate::world_gen::components::{DoorState, TileType};
+
+type Location = (i32, i32);
+
+// The fraction of doors that need a key to get through
+pub const LOCKED_DOOR_CHANCE: f64 = 0.1;
+
+fn is_open(world: &HashMap<Location, TileType>, location: Location) -> bool {
+    matches!(
+        world.get(&location),
+        Some(TileType::Floor)
+            | Some(TileType::Path)
+            | Some(TileType::LadderDown)
+            | Some(TileType::LadderUp)
+    )
+}
+
+fn is_solid(world: &HashMap<Location, TileType>, location: Location) -> bool {
+    match world.get(&location) {
+        Some(TileType::Door(_)) => false,
+        Some(tile_type) => tile_type.blocks_sight(),
+        None => true,
+    }
+}
+
+/// Doorways are where a corridor passes through the wall of a room:
+/// a single open tile with walls on either side, the room on one end and the corridor on the other.
+/// They are returned in order, so that the same map always gets the same doors.
+pub fn doorways(world: &HashMap<Location, TileType>) -> Vec<Location> {
+    let offset =
+        |(x, y): Location, (dx, dy): Location, scale: i32| (x + dx * scale, y + dy * scale);
+
+    let mut doorways = world
+        .keys()
+        .copied()
+        .filter(|&location| is_open(world, location))
+        .filter(|&location| {
+            [((1, 0), (0, 1)), ((0, 1), (1, 0))]
+                .iter()
+                .any(|&(along_wall, through_wall)| {
+                    let walled_in = |location| {
+                        is_solid(world, offset(location, along_wall, 1))
+                            && is_solid(world, offset(location, along_wall, -1))
+                    };
+                    let roomy = |location| {
+                        is_open(world, offset(location, along_wall, 1))
+                            && is_open(world, offset(location, along_wall, -1))
+                    };
+                    let (ahead, behind) = (
+                        offset(location, through_wall, 1),
+                        offset(location, through_wall, -1),
+                    );
+
+                    walled_in(location)
+                        && is_open(world, ahead)
+                        && is_open(world, behind)
+                        && ((roomy(ahead) && walled_in(behind))
+                            || (roomy(behind) && walled_in(ahead)))
+                })
+        })
+        .collect::<Vec<_>>();
+    doorways.sort();
+    doorways
+}
+
+/// How many locked doors there are, each of which needs a key of its own
+pub fn locked_doors(world: &HashMap<Location, TileType>) -> usize {
+    world
+        .values()
+        .filter(|&&tile_type| tile_type == TileType::Door(DoorState::Locked))
+        .count()
+}
+
+/// Puts a door in every doorway, some of them locked.
+/// Doorways right next to each other only get a single door between them.
+pub fn place_doors<R: Rng>(
+    world: &mut HashMap<Location, TileType>,
+    locked_chance: f64,
+    rng: &mut R,
+) -> Vec<Location> {
+    let mut doors: Vec<Location> = vec![];
+    for (x, y) in doorways(world) {
+        let next_to_door = doors
+            .iter()
+            .any(|&(door_x, door_y)| (door_x - x).abs() + (door_y - y).abs() <= 1);
+        if next_to_door {
+            continue;
+        }
+
+        let state = if rng.gen_bool(locked_chance) {
+            DoorState::Locked
+        } else {
+            DoorState::Closed
+        };
+        world.insert((x, y), TileType::Door(state));
+        doors.push((x, y));
+    }
+    doors
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    /// Reads a map drawn with `#` for walls, `.` for room floor and `,` for corridors
+    fn parse(map: &str) -> HashMap<Location, TileType> {
+        map.lines()
+            .enumerate()
+            .flat_map(|(y, line)| {
+                line.trim().chars().enumerate().map(move |(x, c)| {
+                    let tile_type = match c {
+                        '.' => TileType::Floor,
+                        ',' => TileType::Path,
+                        _ => TileType::UndirectedWall,
+                    };
+                    ((x as i32, y as i32), tile_type)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn doors_go_where_corridors_meet_rooms() {
+        let mut world = parse(
+            "#########
+             #...#####
+             #...,,,,#
+             #...###,#
+             #######,#",
+        );
+        assert_eq!(doorways(&world), vec![(4, 2)]);
+
+        let doors = place_doors(&mut world, 0.0, &mut StepRng::new(0, 1));
+        assert_eq!(doors, vec![(4, 2)]);
+        assert!(world[&(4, 2)] == TileType::Door(DoorState::Closed));
+        assert!(world[&(4, 2)].blocks_sight());
+        assert!(!TileType::Door(DoorState::Open).blocks_sight());
+        assert_eq!(locked_doors(&world), 0);
+
+        world.insert((4, 2), TileType::Door(DoorState::Locked));
+        assert_eq!(locked_doors(&world), 1);
+    }
+
+    #[test]
+    fn short_corridors_get_a_single_door() {
+        let world = parse(
+            "#########
+             #...#...#
+             #...,...#
+             #...#...#
+             #########",
+        );
+        // With no corridor to speak of, the gap between the rooms is not a doorway
+        assert!(doorways(&world).is_empty());
+
+        let mut world = parse(
+            "##########
+             #...##...#
+             #...,,...#
+             #...##...#
+             ##########",
+        );
+        assert_eq!(doorways(&world), vec![(4, 2), (5, 2)]);
+        assert_eq!(
+            place_doors(&mut world, 0.0, &mut StepRng::new(0, 1)),
+            vec![(4, 2)]
+        );
+    }
+}
@@ -3,11 +3,14 @@ extern crate rand;
 
 use std::collections::HashMap;
 
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
 
 use self::ena::unify::{InPlace, UnificationTable, UnifyKey};
 use self::rand::thread_rng;
-use crate::world_gen::components::{Direction, TileType};
+use crate::world_gen::components::TileType;
+use crate::world_gen::rooms::{Rect, RoomGraph};
 
 /// usage:
 /// ```
@@ -17,8 +20,11 @@ use crate::world_gen::components::{Direction, TileType};
 ///    .n_rooms(10)
 ///    .room_min(5)
 ///    .room_range(5)
+///    .seed(1337)
 ///    .generate();
 /// ```
+#[derive(Deserialize)]
+#[serde(default)]
 pub struct DungGen {
     pub width: i32,
     pub height: i32,
@@ -31,17 +37,27 @@ pub struct DungGen {
 
     pub n_rooms: usize,
 
+    // The same seed always generates the same dungeon,
+    // a random one is picked unless told otherwise
+    #[serde(skip)]
+    pub seed: u64,
+
     // Used over the course of the algorithm,
     // made public to position player currently
+    #[serde(skip)]
     pub room_centers: Vec<(i32, i32)>,
+    // The rooms, in the same order as their centers, and the corridors between them
+    #[serde(skip)]
+    pub rooms: RoomGraph,
     // The result of the algorithm is stored here
+    #[serde(skip)]
     pub world: HashMap<(i32, i32), TileType>,
 }
 
 // (Internal screaming)
 // Needed for the Union-Find algorithm used (UnificationTable)
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
-struct UnitKey(u32);
+pub(crate) struct UnitKey(u32);
 
 impl UnifyKey for UnitKey {
     type Value = ();
@@ -50,6 +66,10 @@ impl UnifyKey for UnitKey {
     fn tag() -> &'static str { "UnitKey" }
 }
 
+impl Default for DungGen {
+    fn default() -> Self { Self::new() }
+}
+
 #[allow(dead_code)]
 impl DungGen {
     pub fn new() -> DungGen {
@@ -59,7 +79,9 @@ impl DungGen {
             room_min: 4,
             room_range: 11,
             n_rooms: 10,
+            seed: thread_rng().gen(),
             room_centers: vec![],
+            rooms: RoomGraph::default(),
             world: HashMap::<(i32, i32), TileType>::new(),
         }
     }
@@ -87,10 +109,16 @@ impl DungGen {
         self
     }
 
+    pub fn seed(mut self, seed: u64) -> DungGen {
+        self.seed = seed;
+        self
+    }
+
     pub fn generate(mut self) -> DungGen {
-        let mut rng = rand::thread_rng();
+        let mut rng = StdRng::seed_from_u64(self.seed);
 
         self.room_centers = Vec::<(i32, i32)>::new();
+        self.rooms = RoomGraph::default();
 
         // This is how close to the edges of the map floors can be.
         // This parameter is needed since rooms are now simply the floor
@@ -152,6 +180,12 @@ impl DungGen {
             // Add the center of the generated room to the list
             self.room_centers
                 .push((x_min + (x_max - x_min) / 2, y_min + (y_max - y_min) / 2));
+            self.rooms.add_room(Rect {
+                x: x_min,
+                y: y_min,
+                width: x_max - x_min + 1,
+                height: y_max - y_min + 1,
+            });
         }
 
         // Step 4: Once all rooms are generated, add the centers as
@@ -211,9 +245,11 @@ impl DungGen {
                 y_end = y0;
             }
 
+            let mut corridor = vec![];
             for x in x_start..=x_end + 1 {
                 if x <= x_end {
                     self.world.insert((x, y_start), TileType::Path);
+                    corridor.push((x, y_start));
                 }
                 if self.world.get(&(x, y_start + 1)).is_none() {
                     self.world
@@ -233,6 +269,7 @@ impl DungGen {
             for y in y_start..=y_end + 1 {
                 if y <= y_end {
                     self.world.insert((x_end, y), TileType::Path);
+                    corridor.push((x_end, y));
                 }
                 if self.world.get(&(x_end + 1, y)).is_none() {
                     self.world.insert((x_end + 1, y), TileType::UndirectedWall);
@@ -245,88 +282,25 @@ impl DungGen {
             // Finally mark these rooms as being connected
             let (r1, r2) = to_connect;
             comps.union(*keys.get(&r1).unwrap(), *keys.get(&r2).unwrap());
+            let index = |center| self.room_centers.iter().position(|&c| c == center).unwrap();
+            let (r1, r2) = (index(r1), index(r2));
+            self.rooms.connect(r1, r2, corridor);
         }
 
-        // Determine the orientation of walls to assign the correct model and rotation
-        let mut directed_walls = vec![];
-
-        for (&(x, y), &wall_type) in self.world.iter() {
-            let loc = (x, y);
-            if let TileType::Wall(_) = wall_type {
-                let n = *self
-                    .world
-                    .get(&(x, y + 1))
-                    .unwrap_or(&TileType::UndirectedWall);
-                let w = *self
-                    .world
-                    .get(&(x - 1, y))
-                    .unwrap_or(&TileType::UndirectedWall);
-                let s = *self
-                    .world
-                    .get(&(x, y - 1))
-                    .unwrap_or(&TileType::UndirectedWall);
-                let e = *self
-                    .world
-                    .get(&(x + 1, y))
-                    .unwrap_or(&TileType::UndirectedWall);
-
-                let ne = *self
-                    .world
-                    .get(&(x + 1, y + 1))
-                    .unwrap_or(&TileType::UndirectedWall);
-                let nw = *self
-                    .world
-                    .get(&(x - 1, y + 1))
-                    .unwrap_or(&TileType::UndirectedWall);
-                let se = *self
-                    .world
-                    .get(&(x + 1, y - 1))
-                    .unwrap_or(&TileType::UndirectedWall);
-                let sw = *self
-                    .world
-                    .get(&(x - 1, y - 1))
-                    .unwrap_or(&TileType::UndirectedWall);
-
-                for &(a, b, c, d, e, f, typ) in [
-                    (s, e, n, w, ne, nw, TileType::Wall(Direction::North)),
-                    (e, n, w, s, sw, nw, TileType::Wall(Direction::West)),
-                    (n, w, s, e, se, sw, TileType::Wall(Direction::South)),
-                    (w, s, e, n, se, ne, TileType::Wall(Direction::East)),
-                ]
-                .iter()
-                {
-                    if (a == TileType::Floor || a == TileType::Path)
-                        && b == TileType::UndirectedWall
-                        && c == TileType::UndirectedWall
-                        && d == TileType::UndirectedWall
-                        && e == TileType::UndirectedWall
-                        && f == TileType::UndirectedWall
-                    {
-                        directed_walls.push((loc, typ));
-                    }
-                }
-            }
-        }
-
-        for (loc, typ) in directed_walls {
-            self.world.insert(loc, typ);
-        }
+        // Which way the walls face is worked out by `autotile` once the floor is laid out
 
         // Mark the rest of the world as consisting of nothing
         for x in 0..self.width {
-            for y in 0..self.width {
+            for y in 0..self.height {
                 if self.world.get(&(x, y)).is_none() {
                     self.world.insert((x, y), TileType::Nothing);
                 }
             }
         }
 
-        // Step 4.5: make a thing
-
-        let mut rng = thread_rng();
-        let ladder_loc = rng.gen_range(0..self.room_centers.len());
-        self.world
-            .insert(self.room_centers[ladder_loc], TileType::LadderDown);
+        // Doors and ladders are placed along with everything else on the floor,
+        // doors where the corridors break through the walls of rooms
+        // and ladders where the rooms say they are far apart
 
         self
     }
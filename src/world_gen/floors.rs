@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use legion::{any, component, Entity, IntoQuery, World};
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
+
+use crate::world_gen::components::TileType;
+use crate::world_gen::rooms::RoomGraph;
+
+type Location = (i32, i32);
+
+/// Marks the entities that are on their way out of the world along with their floor
+pub struct Parked;
+
+/// Everything that was on a floor when the player left it
+#[derive(Default)]
+struct ParkedFloor {
+    tiles: Option<HashMap<Location, TileType>>,
+    world: World,
+}
+
+/// The floors the player has been to and left, kept exactly as they were left
+/// so that coming back to one picks up where they left off
+#[derive(Default)]
+pub struct Floors {
+    parked: HashMap<i32, ParkedFloor>,
+}
+
+impl Floors {
+    pub fn park_tiles(&mut self, floor: i32, tiles: HashMap<Location, TileType>) {
+        self.parked.entry(floor).or_default().tiles = Some(tiles);
+    }
+
+    /// Hands back the tiles of a floor that was left, if there ever was one
+    pub fn take_tiles(&mut self, floor: i32) -> Option<HashMap<Location, TileType>> {
+        self.parked
+            .get_mut(&floor)
+            .and_then(|parked| parked.tiles.take())
+    }
+
+    /// Moves the entities out of the world and keeps them with the floor
+    pub fn park(&mut self, floor: i32, world: &mut World, entities: &[Entity]) {
+        for &entity in entities {
+            if let Some(mut entry) = world.entry(entity) {
+                entry.add_component(Parked);
+            }
+        }
+        self.parked
+            .entry(floor)
+            .or_default()
+            .world
+            .move_from(world, &component::<Parked>());
+    }
+
+    /// Puts everything that was kept with the floor back into the world
+    pub fn unpark(&mut self, floor: i32, world: &mut World) -> Vec<Entity> {
+        let mut parked = match self.parked.remove(&floor) {
+            Some(parked) => parked.world,
+            None => return vec![],
+        };
+        let entities = Entity::query().iter(&parked).copied().collect::<Vec<_>>();
+        world.move_from(&mut parked, &any());
+        for &entity in &entities {
+            if let Some(mut entry) = world.entry(entity) {
+                entry.remove_component::<Parked>();
+            }
+        }
+        entities
+    }
+
+    /// Forgets every floor, for when the dungeon starts over
+    pub fn clear(&mut self) { self.parked.clear(); }
+}
+
+/// Puts a ladder down somewhere far from where the player arrives if there isn't one yet,
+/// and below the first floor a ladder back up right where they arrive.
+/// On floors laid out as rooms the player arrives in a room, and the ladder down
+/// is in the room the most corridors away from it.
+/// Returns where the player arrives, if the floor has any room for them at all.
+pub fn place_ladders<R: Rng>(
+    world: &mut HashMap<Location, TileType>,
+    rooms: &RoomGraph,
+    floor: i32,
+    rng: &mut R,
+) -> Option<Location> {
+    let mut floor_tiles = world
+        .iter()
+        .filter(|&(_, &tile_type)| tile_type == TileType::Floor)
+        .map(|(&location, _)| location)
+        .collect::<Vec<_>>();
+    floor_tiles.sort_unstable();
+    let in_room = |room: usize| {
+        floor_tiles
+            .iter()
+            .copied()
+            .filter(|&location| rooms.rooms[room].contains(location))
+            .collect::<Vec<_>>()
+    };
+
+    let start = (0..rooms.rooms.len())
+        .filter(|&room| !in_room(room).is_empty())
+        .collect::<Vec<_>>()
+        .choose(rng)
+        .copied();
+    let (arrival, ladders) = match start {
+        Some(start) => {
+            let arrival = *in_room(start).choose(rng)?;
+            let ladders = rooms.farthest(start).map(in_room).unwrap_or_default();
+            (arrival, ladders)
+        }
+        None => (*floor_tiles.choose(rng)?, vec![]),
+    };
+
+    // Without rooms to go by, anywhere in the farthest quarter of the floor will do
+    let ladders = if ladders.is_empty() {
+        let distance = |(x, y): Location| (x - arrival.0).abs() + (y - arrival.1).abs();
+        floor_tiles.sort_by_key(|&location| distance(location));
+        floor_tiles[floor_tiles.len() * 3 / 4..].to_vec()
+    } else {
+        ladders
+    };
+    // unless the generator already put one somewhere
+    let has_ladder = world.values().any(|&tile| tile == TileType::LadderDown);
+    if let (false, Some(&ladder)) = (
+        has_ladder,
+        ladders.iter().filter(|&&tile| tile != arrival).choose(rng),
+    ) {
+        world.insert(ladder, TileType::LadderDown);
+    }
+    if floor > 1 {
+        world.insert(arrival, TileType::LadderUp);
+    }
+
+    Some(arrival)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::world_gen::rooms::Rect;
+
+    fn room() -> HashMap<Location, TileType> {
+        (0..10)
+            .flat_map(|x| (0..10).map(move |y| ((x, y), TileType::Floor)))
+            .collect()
+    }
+
+    fn count(world: &HashMap<Location, TileType>, tile_type: TileType) -> usize {
+        world.values().filter(|&&tile| tile == tile_type).count()
+    }
+
+    #[test]
+    fn only_deeper_floors_lead_back_up() {
+        let mut rng = StdRng::seed_from_u64(3);
+
+        let mut top = room();
+        let arrival = place_ladders(&mut top, &RoomGraph::default(), 1, &mut rng).unwrap();
+        assert!(top[&arrival] == TileType::Floor);
+        assert_eq!(count(&top, TileType::LadderDown), 1);
+        assert_eq!(count(&top, TileType::LadderUp), 0);
+
+        let mut below = room();
+        let arrival = place_ladders(&mut below, &RoomGraph::default(), 2, &mut rng).unwrap();
+        assert!(below[&arrival] == TileType::LadderUp);
+        assert_eq!(count(&below, TileType::LadderDown), 1);
+
+        assert_eq!(
+            place_ladders(&mut HashMap::new(), &RoomGraph::default(), 2, &mut rng),
+            None
+        );
+    }
+
+    #[test]
+    fn the_ladder_down_is_in_the_farthest_room() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut rooms = RoomGraph::default();
+        for y in 0..3 {
+            rooms.add_room(Rect {
+                x: 0,
+                y: y * 4,
+                width: 10,
+                height: 3,
+            });
+        }
+        rooms.connect(0, 1, vec![]);
+        rooms.connect(1, 2, vec![]);
+
+        for _ in 0..10 {
+            let mut world = room();
+            let arrival = place_ladders(&mut world, &rooms, 1, &mut rng).unwrap();
+            let ladder = world
+                .iter()
+                .find(|&(_, &tile)| tile == TileType::LadderDown)
+                .map(|(&location, _)| location)
+                .unwrap();
+            let start = rooms.room_at(arrival).unwrap();
+            assert_eq!(rooms.room_at(ladder), rooms.farthest(start));
+            if start != 1 {
+                assert_eq!(rooms.room_at(ladder), Some(2 - start));
+            }
+        }
+    }
+
+    #[test]
+    fn parked_floors_come_back_as_they_were_left() {
+        let mut world = World::default();
+        let mut floors = Floors::default();
+        let player = world.push((0usize,));
+        let left = vec![world.push((1usize,)), world.push((2usize, 0.5f32))];
+
+        floors.park_tiles(1, room());
+        floors.park(1, &mut world, &left);
+        assert!(left.iter().all(|&entity| world.entry(entity).is_none()));
+        assert!(world.entry(player).is_some());
+
+        assert_eq!(floors.take_tiles(1).map(|tiles| tiles.len()), Some(100));
+        let back = floors.unpark(1, &mut world);
+        assert_eq!(back.len(), 2);
+        assert!(left.iter().all(|entity| back.contains(entity)));
+        let entry = world.entry(left[1]).unwrap();
+        assert_eq!(entry.get_component::<f32>().ok(), Some(&0.5));
+        assert!(entry.get_component::<Parked>().is_err());
+        assert!(floors.unpark(1, &mut world).is_empty());
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use cgmath::Array;
+use image::Rgb;
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::world_gen::bsp::Bsp;
+use crate::world_gen::caves::Caves;
+use crate::world_gen::components::TileType;
+use crate::world_gen::dung_gen::DungGen;
+use crate::world_gen::grid::{Grid, V2i, V2u};
+use crate::world_gen::level::{
+    tile_from_pixel, tiles_from_image, ContainerSpawn, CreatureSpawn, ItemSpawn, Level,
+};
+use crate::world_gen::rooms::{Rect, RoomGraph};
+use crate::world_gen::wfc::{wfc, Constraints, Orientation, SQUARE_NEIGHBOURHOOD, STEPS_PER_CELL};
+
+type Location = (i32, i32);
+
+// What every floor falls back on when its generator can't make it
+pub const SAMPLE_MAP: &str = "maps/WFC.png";
+
+/// A floor as a generator lays it out, before anything is put into the world
+pub struct GeneratedLevel {
+    pub tiles: Grid<TileType>,
+    // Where the player arrives, generated floors leave it for the ladders to decide
+    pub player_start: Option<Location>,
+    pub creatures: Vec<CreatureSpawn>,
+    pub items: Vec<ItemSpawn>,
+    pub containers: Vec<ContainerSpawn>,
+    // The rooms and corridors, for floors that are laid out as rooms
+    pub rooms: RoomGraph,
+    // Handcrafted floors come with their doors, ladders and keys in place
+    pub handcrafted: bool,
+    // Whether doors are put where corridors meet rooms
+    pub doors: bool,
+    // Whether the floor gets random monsters, items and containers
+    pub populate: bool,
+}
+
+impl GeneratedLevel {
+    /// A generated floor with nothing on it yet
+    pub fn empty(tiles: Grid<TileType>) -> Self {
+        GeneratedLevel {
+            tiles,
+            player_start: None,
+            creatures: vec![],
+            items: vec![],
+            containers: vec![],
+            rooms: RoomGraph::default(),
+            handcrafted: false,
+            doors: true,
+            populate: true,
+        }
+    }
+
+    /// The tiles by location, the way the rest of world generation wants them
+    pub fn tile_map(&self) -> HashMap<Location, TileType> {
+        (0..self.tiles.size.y)
+            .flat_map(|y| (0..self.tiles.size.x).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let tile_type = self.tiles[V2u::new(x, y)];
+                ((x as i32, y as i32), tile_type)
+            })
+            .collect()
+    }
+
+    /// Where the floor says the player starts, or else by the ladder up,
+    /// or else anywhere on the floor at all
+    pub fn arrival(&self) -> Option<Location> {
+        let tiles = self.tile_map();
+        let first = |tile_type: TileType| {
+            tiles
+                .iter()
+                .filter(|&(_, &tile)| tile == tile_type)
+                .map(|(&location, _)| location)
+                .min()
+        };
+        self.player_start
+            .or_else(|| first(TileType::LadderUp))
+            .or_else(|| first(TileType::Floor))
+    }
+}
+
+/// Lays out tiles in a grid, anything not in the map is nothing but rock
+pub fn grid_from_tiles(tiles: &HashMap<Location, TileType>) -> Grid<TileType> {
+    let (width, height) = tiles
+        .keys()
+        .filter(|&&(x, y)| x >= 0 && y >= 0)
+        .fold((0, 0), |(width, height), &(x, y)| {
+            (width.max(x as usize + 1), height.max(y as usize + 1))
+        });
+    let mut grid = Grid::new();
+    grid.resize(V2u::new(width, height), TileType::Nothing);
+    for (&(x, y), &tile_type) in tiles {
+        if let Some(tile) = grid.get_mut(V2i::new(x as isize, y as isize)) {
+            *tile = tile_type;
+        }
+    }
+    grid
+}
+
+/// Something that can lay out a floor
+pub trait LevelGenerator: Send + Sync {
+    fn generate(&self, floor: i32, rng: &mut StdRng) -> Result<GeneratedLevel, String>;
+}
+
+/// The same picture every time, in the palette of `tiles_from_image`
+pub struct SampleImage(pub PathBuf);
+
+impl LevelGenerator for SampleImage {
+    fn generate(&self, _: i32, _: &mut StdRng) -> Result<GeneratedLevel, String> {
+        let image = image::open(&self.0)
+            .map_err(|error| format!("Could not open {}: {}", self.0.display(), error))?;
+        Ok(GeneratedLevel::empty(grid_from_tiles(&tiles_from_image(
+            image,
+        ))))
+    }
+}
+
+impl LevelGenerator for DungGen {
+    fn generate(&self, _: i32, rng: &mut StdRng) -> Result<GeneratedLevel, String> {
+        let dungeon = DungGen {
+            seed: rng.gen(),
+            room_centers: vec![],
+            rooms: RoomGraph::default(),
+            world: HashMap::new(),
+            ..*self
+        }
+        .generate();
+        Ok(GeneratedLevel {
+            rooms: dungeon.rooms,
+            ..GeneratedLevel::empty(grid_from_tiles(&dungeon.world))
+        })
+    }
+}
+
+/// Wave function collapse over a sample picture in the palette of `tiles_from_image`
+#[derive(Deserialize)]
+pub struct WfcGenerator {
+    pub sample: PathBuf,
+    // The width and height of the floor
+    pub size: usize,
+    // Which ways the sample is turned to find more patterns in it
+    #[serde(default = "Orientation::rotations")]
+    pub orientations: Vec<Orientation>,
+    // How many cells can be collapsed or taken back before giving up,
+    // by default `STEPS_PER_CELL` for every cell of the floor
+    #[serde(default)]
+    pub max_steps: Option<usize>,
+    #[serde(default)]
+    pub rules: FloorRules,
+    // How many times more or less common colours of the sample, as red, green and blue,
+    // come out on the floor than they are in the sample
+    #[serde(default)]
+    pub weights: Vec<([u8; 3], f64)>,
+}
+
+/// What a wave function collapse floor has to be like, on top of looking like its sample.
+/// The edge of the floor is always wall.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FloorRules {
+    // How much of the floor can be walked on at least, as a fraction of it
+    pub min_walkable: f64,
+    // Whether all of the floor can be walked to from anywhere else on it
+    pub connected: bool,
+    // A picture in the palette of the sample put in the middle of the floor as it is,
+    // where the player arrives
+    pub entrance: Option<PathBuf>,
+    // Colours of the sample that have to turn up at least once, as red, green and blue
+    pub required: Vec<[u8; 3]>,
+}
+
+impl Default for FloorRules {
+    fn default() -> Self {
+        FloorRules {
+            min_walkable: 0.2,
+            connected: true,
+            entrance: None,
+            required: vec![],
+        }
+    }
+}
+
+impl LevelGenerator for WfcGenerator {
+    fn generate(&self, _: i32, rng: &mut StdRng) -> Result<GeneratedLevel, String> {
+        let sample = image::open(&self.sample)
+            .map_err(|error| format!("Could not open {}: {}", self.sample.display(), error))?;
+        let sample = Grid::from(&sample.into_rgb8());
+        let walkable = |&Rgb([r, g, b]): &Rgb<u8>| tile_from_pixel([b, g, r]).is_walkable();
+        let (walkable, border) = sample.buf.iter().copied().unique().partition(walkable);
+        let mut constraints = Constraints {
+            border,
+            required: self
+                .rules
+                .required
+                .iter()
+                .map(|&colour| Rgb(colour))
+                .collect(),
+            walkable,
+            min_walkable: self.rules.min_walkable,
+            connected: self.rules.connected,
+            ..Constraints::default()
+        };
+
+        let weights = self
+            .weights
+            .iter()
+            .map(|&(colour, weight)| (Rgb(colour), weight))
+            .collect::<Vec<_>>();
+
+        let mut rooms = RoomGraph::default();
+        if let Some(path) = &self.rules.entrance {
+            let entrance = image::open(path)
+                .map_err(|error| format!("Could not open {}: {}", path.display(), error))?;
+            let entrance = Grid::from(&entrance.into_rgb8());
+            // Whatever of the entrance doesn't fit on the floor is left out, of the room too
+            let size = entrance.size.map(|e| e.min(self.size));
+            let at = (V2u::from_value(self.size) - size) / 2;
+            constraints = constraints.stamp(at, &entrance);
+            rooms.add_room(Rect {
+                x: at.x as i32,
+                y: at.y as i32,
+                width: size.x as i32,
+                height: size.y as i32,
+            });
+        }
+
+        let output = wfc(
+            sample,
+            &SQUARE_NEIGHBOURHOOD,
+            V2u::from_value(self.size),
+            &self.orientations,
+            &constraints,
+            &weights,
+            self.max_steps
+                .unwrap_or(self.size * self.size * STEPS_PER_CELL),
+            rng.gen(),
+        )
+        .map_err(|error| format!("Wave function collapse gave up: {}", error))?;
+
+        let mut tiles = Grid::new();
+        tiles.resize(output.size, TileType::Nothing);
+        for (tile, &Rgb([r, g, b])) in tiles.buf.iter_mut().zip(output.buf.iter()) {
+            *tile = tile_from_pixel([b, g, r]);
+        }
+        Ok(GeneratedLevel {
+            rooms,
+            ..GeneratedLevel::empty(tiles)
+        })
+    }
+}
+
+impl LevelGenerator for Level {
+    fn generate(&self, _: i32, _: &mut StdRng) -> Result<GeneratedLevel, String> {
+        Ok(GeneratedLevel {
+            tiles: grid_from_tiles(&self.tiles),
+            player_start: self.player_start,
+            creatures: self.creatures.clone(),
+            items: self.items.clone(),
+            containers: self.containers.clone(),
+            rooms: RoomGraph::default(),
+            handcrafted: true,
+            doors: false,
+            populate: self.populate,
+        })
+    }
+}
+
+/// The generators that can be scheduled in the level schedule
+#[derive(Deserialize)]
+pub enum GeneratorConfig {
+    Image(PathBuf),
+    Rooms(DungGen),
+    Bsp(Bsp),
+    Caves(Caves),
+    Wfc(WfcGenerator),
+}
+
+impl GeneratorConfig {
+    pub fn build(self) -> Box<dyn LevelGenerator> {
+        match self {
+            GeneratorConfig::Image(path) => Box::new(SampleImage(path)),
+            GeneratorConfig::Rooms(rooms) => Box::new(rooms),
+            GeneratorConfig::Bsp(bsp) => Box::new(bsp),
+            GeneratorConfig::Caves(caves) => Box::new(caves),
+            GeneratorConfig::Wfc(wfc) => Box::new(wfc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn generators_share_the_tile_format() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let rooms = DungGen::new()
+            .width(40)
+            .height(30)
+            .n_rooms(4)
+            .room_min(3)
+            .room_range(5);
+        let rooms = LevelGenerator::generate(&rooms, 1, &mut rng).unwrap();
+        let image = SampleImage(SAMPLE_MAP.into())
+            .generate(1, &mut rng)
+            .unwrap();
+
+        for level in [rooms, image].iter() {
+            let tiles = level.tile_map();
+            assert_eq!(tiles.len(), level.tiles.size.x * level.tiles.size.y);
+            assert!(tiles.values().any(|&tile| tile == TileType::Floor));
+            assert!(level.arrival().is_some());
+            assert!(grid_from_tiles(&tiles).buf == level.tiles.buf);
+        }
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, Rgb};
+use rand::rngs::StdRng;
+use serde::Deserialize;
+
+use crate::items::components::ItemId;
+use crate::items::loot::LootTables;
+use crate::items::registry::ItemRegistry;
+use crate::world_gen::components::{Direction, DoorState, TileType};
+use crate::world_gen::generator::{
+    GeneratedLevel, GeneratorConfig, LevelGenerator, SampleImage, SAMPLE_MAP,
+};
+use crate::world_gen::wfc::Orient;
+
+pub const LEVELS_PATH: &str = "assets/Data/levels.ron";
+
+type Location = (i32, i32);
+
+/// How the tiles of a level are drawn
+#[derive(Deserialize)]
+pub enum LevelTiles {
+    /// One string per row, top row first, with one character per tile:
+    ///
+    /// | Character | Tile                                |
+    /// |-----------|-------------------------------------|
+    /// | `#`       | wall                                |
+    /// | `.`       | room floor                          |
+    /// | `,`       | corridor                            |
+    /// | ` `       | nothing, solid rock                 |
+    /// | `+`       | closed door                         |
+    /// | `=`       | locked door                         |
+    /// | `/`       | open door                           |
+    /// | `>`       | ladder down                         |
+    /// | `<`       | ladder up                           |
+    /// | `@`       | room floor, where the player starts |
+    ///
+    /// Rows shorter than the longest one are padded with nothing.
+    Rows(Vec<String>),
+    /// A picture where every pixel is a tile, see `tile_from_pixel` for the palette.
+    /// The path is relative to where the game is run from.
+    Image(PathBuf),
+}
+
+#[derive(Copy, Clone, Deserialize)]
+pub enum Creature {
+    Monster,
+    Vermin,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct CreatureSpawn {
+    pub at: Location,
+    pub creature: Creature,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ItemSpawn {
+    pub at: Location,
+    pub item: ItemId,
+    #[serde(default = "one")]
+    pub count: u32,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ContainerSpawn {
+    pub at: Location,
+    // The name of one of the containers in the loot tables
+    pub container: String,
+    // What's inside, rolled from the container's loot table when left out
+    #[serde(default)]
+    pub contents: Option<Vec<(ItemId, u32)>>,
+}
+
+fn one() -> u32 { 1 }
+
+/// What a level file holds, before its tiles have been read
+#[derive(Deserialize)]
+struct LevelFile {
+    tiles: LevelTiles,
+    // Where things go that the tiles themselves can't say,
+    // which is everything for levels drawn as images
+    #[serde(default)]
+    player_start: Option<Location>,
+    #[serde(default)]
+    ladder_down: Option<Location>,
+    #[serde(default)]
+    ladder_up: Option<Location>,
+    #[serde(default)]
+    creatures: Vec<CreatureSpawn>,
+    #[serde(default)]
+    items: Vec<ItemSpawn>,
+    #[serde(default)]
+    containers: Vec<ContainerSpawn>,
+    // Whether the level gets the same random monsters, items and containers
+    // as a generated floor on top of what it places itself
+    #[serde(default)]
+    populate: bool,
+}
+
+/// A handcrafted floor, loaded from disk
+pub struct Level {
+    pub tiles: HashMap<Location, TileType>,
+    pub player_start: Option<Location>,
+    pub creatures: Vec<CreatureSpawn>,
+    pub items: Vec<ItemSpawn>,
+    pub containers: Vec<ContainerSpawn>,
+    pub populate: bool,
+}
+
+impl Level {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        Self::from_ron(&data).map_err(|error| format!("In {}: {}", path.display(), error))
+    }
+
+    pub fn from_ron(data: &str) -> Result<Self, String> {
+        let file: LevelFile = ron::de::from_str(data).map_err(|error| error.to_string())?;
+
+        let (mut tiles, drawn_start) = match &file.tiles {
+            LevelTiles::Rows(rows) => tiles_from_rows(rows)?,
+            LevelTiles::Image(path) => {
+                let image = image::open(path)
+                    .map_err(|error| format!("Could not open {}: {}", path.display(), error))?;
+                (tiles_from_image(image), None)
+            }
+        };
+        if let Some(location) = file.ladder_down {
+            tiles.insert(location, TileType::LadderDown);
+        }
+        if let Some(location) = file.ladder_up {
+            tiles.insert(location, TileType::LadderUp);
+        }
+
+        let level = Level {
+            tiles,
+            player_start: file.player_start.or(drawn_start),
+            creatures: file.creatures,
+            items: file.items,
+            containers: file.containers,
+            populate: file.populate,
+        };
+        level.check()?;
+        Ok(level)
+    }
+
+    /// Makes sure there is a way down, and everything that is placed on the level
+    /// stands somewhere it can stand
+    fn check(&self) -> Result<(), String> {
+        if !self
+            .tiles
+            .values()
+            .any(|&tile| tile == TileType::LadderDown)
+        {
+            return Err(String::from("The level has no ladder down"));
+        }
+        let spawns = self
+            .player_start
+            .iter()
+            .map(|&at| ("The player start", at))
+            .chain(self.creatures.iter().map(|spawn| ("A creature", spawn.at)))
+            .chain(self.items.iter().map(|spawn| ("An item", spawn.at)))
+            .chain(
+                self.containers
+                    .iter()
+                    .map(|spawn| ("A container", spawn.at)),
+            );
+        for (what, at) in spawns {
+            match self.tiles.get(&at) {
+                // Not in a doorway either, where it would be shut in by the door
+                Some(tile_type)
+                    if tile_type.is_walkable() && !matches!(tile_type, TileType::Door(_)) => {}
+                _ => return Err(format!("{} at {:?} is not on open floor", what, at)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn tiles_from_rows(
+    rows: &[String],
+) -> Result<(HashMap<Location, TileType>, Option<Location>), String> {
+    let width = rows
+        .iter()
+        .map(|row| row.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut tiles = HashMap::new();
+    let mut start = None;
+    for (y, row) in rows.iter().enumerate() {
+        let mut row = row.chars();
+        for x in 0..width {
+            let location = (x as i32, y as i32);
+            let tile_type = match row.next().unwrap_or(' ') {
+                '#' => TileType::UndirectedWall,
+                '.' => TileType::Floor,
+                ',' => TileType::Path,
+                ' ' => TileType::Nothing,
+                '+' => TileType::Door(DoorState::Closed),
+                '=' => TileType::Door(DoorState::Locked),
+                '/' => TileType::Door(DoorState::Open),
+                '>' => TileType::LadderDown,
+                '<' => TileType::LadderUp,
+                '@' => {
+                    start = Some(location);
+                    TileType::Floor
+                }
+                other => return Err(format!("Unknown tile {:?} at {:?}", other, location)),
+            };
+            tiles.insert(location, tile_type);
+        }
+    }
+    Ok((tiles, start))
+}
+
+/// Reads a level drawn as a picture, where every pixel is a tile:
+///
+/// - Pixels without any blue are nothing, solid rock
+/// - Otherwise green says what the tile is: 0 for floor, 64 for an inner corner,
+///   128 for an outer corner and 192 for a wall
+/// - Red says which way walls and corners face: 0 for north, 64 for east,
+///   128 for south and 192 for west
+pub fn tiles_from_image(image: DynamicImage) -> HashMap<Location, TileType> {
+    image
+        .into_bgr8()
+        .enumerate_pixels()
+        .map(|(x, y, pixel)| ((x as i32, y as i32), tile_from_pixel(pixel.0)))
+        .collect()
+}
+
+pub(crate) fn tile_from_pixel([b, g, r]: [u8; 3]) -> TileType {
+    let direction = match r {
+        0 => Direction::North,
+        64 => Direction::East,
+        128 => Direction::South,
+        192 => Direction::West,
+        _ => Direction::North,
+    };
+    if b > 0 {
+        match g {
+            0 => TileType::Floor,
+            64 => TileType::CornerIn(direction),
+            128 => TileType::CornerOut(direction),
+            192 => TileType::Wall(direction),
+            _ => TileType::Unknown,
+        }
+    } else {
+        TileType::Nothing
+    }
+}
+
+// Red counts quarter turns clockwise from north in the picture palette,
+// but only walls and corners face anywhere
+impl Orient for Rgb<u8> {
+    fn rotated(&self) -> Self {
+        let Rgb([r, g, b]) = *self;
+        match g {
+            64 | 128 | 192 if b > 0 => Rgb([r.wrapping_add(64), g, b]),
+            _ => *self,
+        }
+    }
+
+    fn mirrored(&self) -> Self {
+        let Rgb([r, g, b]) = *self;
+        let quarter = r / 64;
+        // A corner is named after the first of its two sides going round north, west, south, east,
+        // so once flipped it is named after the other side
+        let flipped = match g {
+            192 if b > 0 => 4 - quarter,
+            64 | 128 if b > 0 => 5 - quarter,
+            _ => return *self,
+        };
+        Rgb([flipped % 4 * 64, g, b])
+    }
+}
+
+/// Which floors are handcrafted, and what generates the rest
+#[derive(Default)]
+pub struct Levels {
+    levels: HashMap<i32, Level>,
+    // Sorted by the floor they start on, each one generates
+    // every floor until the next one takes over
+    generators: Vec<(i32, Box<dyn LevelGenerator>)>,
+}
+
+#[derive(Deserialize)]
+struct LevelSchedule {
+    #[serde(default)]
+    floors: HashMap<i32, PathBuf>,
+    #[serde(default)]
+    generators: HashMap<i32, GeneratorConfig>,
+}
+
+impl Levels {
+    /// Loads every level the schedule mentions, so that a broken one is found right away
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        let schedule: LevelSchedule =
+            ron::de::from_str(&data).map_err(|error| error.to_string())?;
+        let mut levels = Levels::default();
+        for (floor, path) in schedule.floors {
+            levels.insert(floor, Level::load(&path)?);
+        }
+        for (from, config) in schedule.generators {
+            levels.schedule(from, config.build());
+        }
+        Ok(levels)
+    }
+
+    /// Generates every floor from `from` on with the given generator,
+    /// until a generator scheduled on a deeper floor takes over
+    pub fn schedule(&mut self, from: i32, generator: Box<dyn LevelGenerator>) {
+        self.generators.retain(|&(other, _)| other != from);
+        self.generators.push((from, generator));
+        self.generators.sort_by_key(|&(from, _)| from);
+    }
+
+    /// Plays the level on the given floor, instead of whatever would have been there
+    pub fn insert(&mut self, floor: i32, level: Level) { self.levels.insert(floor, level); }
+
+    /// Lays out the given floor, from its handcrafted level if it has one
+    /// and otherwise with whichever generator is scheduled for it.
+    /// Floors before the first scheduled generator are the sample map.
+    pub fn generate(&self, floor: i32, rng: &mut StdRng) -> Result<GeneratedLevel, String> {
+        if let Some(level) = self.levels.get(&floor) {
+            return level.generate(floor, rng);
+        }
+        match self
+            .generators
+            .iter()
+            .rev()
+            .find(|&&(from, _)| from <= floor)
+        {
+            Some((_, generator)) => generator.generate(floor, rng),
+            None => SampleImage(SAMPLE_MAP.into()).generate(floor, rng),
+        }
+    }
+
+    /// Makes sure every item and container the levels place is one that exists
+    pub fn check(&self, items: &ItemRegistry, loot: &LootTables) -> Result<(), String> {
+        for (floor, level) in &self.levels {
+            let unknown_item = level
+                .items
+                .iter()
+                .map(|spawn| &spawn.item)
+                .chain(
+                    level
+                        .containers
+                        .iter()
+                        .filter_map(|spawn| spawn.contents.as_ref())
+                        .flatten()
+                        .map(|(item, _)| item),
+                )
+                .find(|&item| items.get(item).is_none());
+            if let Some(item) = unknown_item {
+                return Err(format!(
+                    "The level on floor {} places the unknown item {:?}",
+                    floor, item
+                ));
+            }
+            if let Some(spawn) = level
+                .containers
+                .iter()
+                .find(|spawn| loot.container(&spawn.container).is_none())
+            {
+                return Err(format!(
+                    "The level on floor {} places the unknown container {}",
+                    floor, spawn.container
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::items::loot::LOOT_PATH;
+    use crate::items::registry::ITEMS_PATH;
+    use crate::world_gen::grid::{Grid, V2u};
+    use crate::world_gen::wfc::Orientation;
+
+    /// A level drawn with the given rows, and whatever else is in `rest`
+    fn level(rows: &[&str], rest: &str) -> Result<Level, String> {
+        Level::from_ron(&format!("(tiles: Rows({:?}), {})", rows, rest))
+    }
+
+    #[test]
+    fn levels_are_read_from_rows() {
+        let level = level(
+            &["#####", "#@.>#", "##+#", " #<#"],
+            r#"
+                creatures: [(at: (2, 1), creature: Vermin)],
+                items: [(at: (2, 3), item: "gold", count: 3)],
+            "#,
+        )
+        .unwrap();
+        assert_eq!(level.player_start, Some((1, 1)));
+        assert!(level.tiles[&(3, 1)] == TileType::LadderDown);
+        assert!(level.tiles[&(2, 2)] == TileType::Door(DoorState::Closed));
+        assert!(level.tiles[&(0, 3)] == TileType::Nothing);
+        // Short rows are padded out to the longest
+        assert!(level.tiles[&(4, 3)] == TileType::Nothing);
+        assert_eq!(level.tiles.len(), 20);
+        assert_eq!(level.items[0].count, 3);
+        assert!(!level.populate);
+    }
+
+    #[test]
+    fn spawns_must_stand_on_open_floor() {
+        let walls = ["####", "#.+#", "#>.#", "####"];
+        assert!(level(&walls, r#"items: [(at: (1, 1), item: "gold")]"#).is_ok());
+        assert!(level(&walls, r#"items: [(at: (0, 0), item: "gold")]"#).is_err());
+        assert!(level(&walls, r#"items: [(at: (2, 1), item: "gold")]"#).is_err());
+        assert!(level(&["#?#"], "").is_err());
+    }
+
+    #[test]
+    fn levels_need_a_ladder_down() {
+        assert!(level(&["####", "#.<#", "####"], "").is_err());
+        assert!(level(&["####", "#.<#", "####"], "ladder_down: Some((1, 1))").is_ok());
+    }
+
+    #[test]
+    fn walls_turn_with_the_sample() {
+        // A floor with a wall east of it, facing away from it
+        let mut sample = Grid::new();
+        sample.resize(V2u::new(2, 1), Rgb([0, 0, 255]));
+        sample[V2u::new(1, 0)] = Rgb([64, 192, 255]);
+        let tile = |grid: &Grid<Rgb<u8>>, x, y| {
+            let Rgb([r, g, b]) = grid[V2u::new(x, y)];
+            tile_from_pixel([b, g, r])
+        };
+
+        let turned = Orientation::Clockwise90.apply_to(&sample);
+        assert_eq!(turned.size, V2u::new(1, 2));
+        assert!(tile(&turned, 0, 0) == TileType::Wall(Direction::South));
+        assert!(tile(&turned, 0, 1) == TileType::Floor);
+
+        let flipped = Orientation::Mirrored.apply_to(&sample);
+        assert!(tile(&flipped, 0, 0) == TileType::Wall(Direction::West));
+        assert!(tile(&flipped, 1, 0) == TileType::Floor);
+
+        // The corner between north and west ends up between north and east
+        let Rgb([r, g, b]) = Rgb([0, 64, 255]).mirrored();
+        assert!(tile_from_pixel([b, g, r]) == TileType::CornerIn(Direction::East));
+    }
+
+    #[test]
+    fn shipped_levels_load() {
+        let mut levels = Levels::load(Path::new(LEVELS_PATH)).unwrap();
+        let tutorial = Level::load(Path::new("assets/Levels/tutorial.ron")).unwrap();
+        levels.insert(1, tutorial);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let first = levels.generate(1, &mut rng).unwrap();
+        assert!(first.handcrafted);
+        assert_eq!(first.arrival(), Some((3, 2)));
+        // Every generator but the slow wave function collapse one
+        for floor in &[2, 4, 7] {
+            let level = levels.generate(*floor, &mut rng).unwrap();
+            assert!(!level.handcrafted);
+            assert!(level.tiles.buf.iter().any(|&tile| tile == TileType::Floor));
+        }
+
+        let items = ItemRegistry::load(Path::new(ITEMS_PATH)).unwrap();
+        let loot = LootTables::load(Path::new(LOOT_PATH)).unwrap();
+        assert_eq!(levels.check(&items, &loot), Ok(()));
+    }
+}
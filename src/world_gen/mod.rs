@@ -1,5 +1,15 @@
+pub mod autotile;
+pub mod bsp;
+pub mod caves;
 pub mod components;
+pub mod doors;
+pub mod floors;
+pub mod generator;
+pub mod level;
+pub mod rooms;
+pub mod seed;
 mod dung_gen;
 mod grid;
 pub mod systems;
+pub mod validate;
 pub mod wfc;
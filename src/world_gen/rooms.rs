@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+type Location = (i32, i32);
+
+/// A rectangle of tiles, inclusive of its lower corner and exclusive of its upper one
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn center(&self) -> Location { (self.x + self.width / 2, self.y + self.height / 2) }
+
+    pub fn contains(&self, (x, y): Location) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    pub fn locations(&self) -> impl Iterator<Item = Location> {
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = *self;
+        (y..y + height).flat_map(move |y| (x..x + width).map(move |x| (x, y)))
+    }
+}
+
+/// A corridor between two rooms, by where they are in the graph
+#[derive(Clone, Debug)]
+pub struct Corridor {
+    pub between: (usize, usize),
+    // Every tile of the corridor, including the ones that run through rooms
+    pub tiles: Vec<Location>,
+}
+
+/// The rooms of a floor and the corridors between them,
+/// for the generators that lay out their floors as rooms
+#[derive(Clone, Debug, Default)]
+pub struct RoomGraph {
+    pub rooms: Vec<Rect>,
+    pub corridors: Vec<Corridor>,
+}
+
+impl RoomGraph {
+    pub fn add_room(&mut self, bounds: Rect) -> usize {
+        self.rooms.push(bounds);
+        self.rooms.len() - 1
+    }
+
+    pub fn connect(&mut self, a: usize, b: usize, tiles: Vec<Location>) {
+        self.corridors.push(Corridor {
+            between: (a, b),
+            tiles,
+        });
+    }
+
+    pub fn room_at(&self, location: Location) -> Option<usize> {
+        self.rooms.iter().position(|room| room.contains(location))
+    }
+
+    /// The rooms a corridor leads to from the given one, each of them once
+    pub fn neighbours(&self, room: usize) -> Vec<usize> {
+        let mut neighbours = self
+            .corridors
+            .iter()
+            .filter_map(|corridor| match corridor.between {
+                (a, b) if a == room && b != room => Some(b),
+                (a, b) if b == room && a != room => Some(a),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    /// How many corridors away every room is from the given one,
+    /// nothing for the rooms that can't be reached from it at all
+    pub fn distances(&self, from: usize) -> Vec<Option<usize>> {
+        let mut distances = vec![None; self.rooms.len()];
+        distances[from] = Some(0);
+        let mut next = VecDeque::from(vec![from]);
+        while let Some(room) = next.pop_front() {
+            let distance = distances[room].map(|distance| distance + 1);
+            for neighbour in self.neighbours(room) {
+                if distances[neighbour].is_none() {
+                    distances[neighbour] = distance;
+                    next.push_back(neighbour);
+                }
+            }
+        }
+        distances
+    }
+
+    /// The room the most corridors away from the given one,
+    /// and of those the one farthest away as the crow flies
+    pub fn farthest(&self, from: usize) -> Option<usize> {
+        let (x, y) = self.rooms[from].center();
+        let distances = self.distances(from);
+        (0..self.rooms.len())
+            .filter(|&room| room != from)
+            .filter_map(|room| Some((room, distances[room]?)))
+            .max_by_key(|&(room, distance)| {
+                let (room_x, room_y) = self.rooms[room].center();
+                (distance, (room_x - x).abs() + (room_y - y).abs())
+            })
+            .map(|(room, _)| room)
+    }
+
+    /// The rooms with only a single way in or out
+    pub fn dead_ends(&self) -> Vec<usize> {
+        (0..self.rooms.len())
+            .filter(|&room| self.neighbours(room).len() == 1)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(x: i32) -> Rect {
+        Rect {
+            x,
+            y: 0,
+            width: 4,
+            height: 4,
+        }
+    }
+
+    #[test]
+    fn rooms_know_how_far_apart_they_are() {
+        // 0 - 1 - 2 - 3, with 4 off to the side of 1 and 5 on its own
+        let mut graph = RoomGraph::default();
+        for x in 0..6 {
+            graph.add_room(room(x * 10));
+        }
+        for &(a, b) in [(0, 1), (1, 2), (2, 3), (1, 4), (4, 1)].iter() {
+            graph.connect(a, b, vec![]);
+        }
+
+        assert_eq!(graph.neighbours(1), vec![0, 2, 4]);
+        assert_eq!(
+            graph.distances(0),
+            vec![Some(0), Some(1), Some(2), Some(3), Some(2), None]
+        );
+        assert_eq!(graph.farthest(0), Some(3));
+        assert_eq!(graph.farthest(3), Some(0));
+        assert_eq!(graph.dead_ends(), vec![0, 3, 4]);
+        assert_eq!(graph.room_at((21, 3)), Some(2));
+        assert_eq!(graph.room_at((25, 3)), None);
+    }
+}
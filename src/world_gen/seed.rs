@@ -0,0 +1,25 @@
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+
+/// Everything in a run is generated from this one seed,
+/// so the same seed always makes the same dungeon
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RunSeed(pub u64);
+
+impl RunSeed {
+    pub fn random() -> Self { RunSeed(thread_rng().gen()) }
+
+    /// Every floor gets its own seed, so that what happens on one floor
+    /// doesn't change how the next one turns out
+    pub fn floor_seed(&self, floor: i32) -> u64 {
+        // SplitMix64, to spread neighbouring floor numbers far apart
+        let mut z = self
+            .0
+            .wrapping_add((floor as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn floor_rng(&self, floor: i32) -> StdRng { StdRng::seed_from_u64(self.floor_seed(floor)) }
+}
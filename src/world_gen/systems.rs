@@ -3,26 +3,61 @@ use std::collections::HashMap;
 use assman::components::{DynamicModelRequest, StaticModelRequest};
 use cgmath::{vec2, Vector2};
 use entity_smith::Smith;
+use graphics::components::StaticModel;
 use graphics::data::LocalUniforms;
 use legion::systems::{CommandBuffer, Runnable};
 use legion::world::SubWorld;
-use legion::{Entity, IntoQuery, SystemBuilder};
-use physics::PhysicsEntitySmith;
+use legion::{component, Entity, IntoQuery, SystemBuilder};
+use physics::{release_physics, PhysicsEntitySmith};
 use rand::prelude::*;
-use transforms::{Scale, TransformEntitySmith};
+use transforms::{Position, Scale, TransformEntitySmith};
 
-use crate::components::{HitPoints, Player};
+use crate::ai::components::AIBrain;
+use crate::combat::components::{MeleeAttack, OnDeath, Projectile};
+use crate::components::Player;
+use crate::doors::components::DOOR_KEY;
+use crate::doors::DoorEntitySmith;
+use crate::factions::{Faction, FactionRegistry};
+use crate::items::components::{Container, GroundItem, ItemId, ItemStack, LootDrop};
+use crate::items::inventory::Inventory;
+use crate::items::loot::LootTables;
+use crate::items::registry::ItemRegistry;
+use crate::items::ItemEntitySmith;
+use crate::perception::components::ObscuredByFog;
+use crate::perception::fov::tile_at;
+use crate::stats::components::{EffectKind, ExperienceReward, Regeneration, StatusEffect};
+use crate::stats::config::StatsConfig;
+use crate::world_gen::autotile::autotile;
 use crate::world_gen::components::{
-    Direction, Faction, FloorNumber, MapSwitcher, MapTransition, TileType,
+    Direction, FloorNumber, FloorTiles, MapSwitcher, MapTransition, TileType,
 };
+use crate::world_gen::doors::{locked_doors, place_doors, LOCKED_DOOR_CHANCE};
+use crate::world_gen::floors::{place_ladders, Floors};
+use crate::world_gen::generator::{GeneratedLevel, LevelGenerator, SampleImage, SAMPLE_MAP};
+use crate::world_gen::level::{Creature, Levels};
+use crate::world_gen::rooms::{Rect, RoomGraph};
+use crate::world_gen::seed::RunSeed;
+use crate::world_gen::validate::{seal_edges, validate, MAX_FLOOR_ATTEMPTS, MIN_FLOOR_AREA};
 
 pub fn dung_gen_system() -> impl Runnable {
     SystemBuilder::new("DungGen System")
         .read_component::<TileType>()
         .read_component::<Faction>()
+        .read_component::<GroundItem>()
+        .read_component::<Container>()
+        .read_component::<Projectile>()
+        .read_component::<StaticModel>()
         .write_resource::<MapTransition>()
         .write_resource::<FloorNumber>()
         .read_resource::<Player>()
+        .write_resource::<FloorTiles>()
+        .write_resource::<Floors>()
+        .read_resource::<RunSeed>()
+        .read_resource::<Levels>()
+        .read_resource::<FactionRegistry>()
+        .read_resource::<StatsConfig>()
+        .read_resource::<ItemRegistry>()
+        .read_resource::<LootTables>()
         .build(move |command_buffer, world, resources, _| {
             dung_gen(
                 command_buffer,
@@ -30,111 +65,294 @@ pub fn dung_gen_system() -> impl Runnable {
                 &mut resources.0,
                 &mut resources.1,
                 &resources.2,
+                &mut resources.3,
+                &mut resources.4,
+                &resources.5,
+                &resources.6,
+                &resources.7,
+                &resources.8,
+                &resources.9,
+                &resources.10,
             );
         })
 }
 
+/// Climbing happens when the player steps onto a ladder rather than while they stand on one,
+/// so that arriving on a floor by its ladder doesn't send them straight back
+pub fn ladder_system() -> impl Runnable {
+    let mut last_visited = None;
+    SystemBuilder::new("ladder")
+        .read_component::<Position>()
+        .read_component::<MapSwitcher>()
+        .read_resource::<Player>()
+        .read_resource::<FloorNumber>()
+        .write_resource::<MapTransition>()
+        .with_query(<(&Position, &MapSwitcher)>::query())
+        .build(move |_, world, (player, floor, transition), query| {
+            let tile = match <&Position>::query().get(world, player.player) {
+                Ok(position) => tile_at(position.0.truncate()),
+                Err(_) => return,
+            };
+            match last_visited.replace((floor.0, tile)) {
+                Some((last_floor, last_tile)) if last_floor == floor.0 && last_tile != tile => {}
+                _ => return,
+            }
+            if let Some((_, MapSwitcher(switch))) = query
+                .iter(world)
+                .find(|(position, _)| tile_at(position.0.truncate()) == tile)
+            {
+                **transition = *switch;
+            }
+        })
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn dung_gen(
     command_buffer: &mut legion::systems::CommandBuffer,
     world: &mut SubWorld,
     transition: &mut MapTransition,
     floor: &mut FloorNumber,
     player: &Player,
+    floor_tiles: &mut FloorTiles,
+    floors: &mut Floors,
+    seed: &RunSeed,
+    levels: &Levels,
+    factions: &FactionRegistry,
+    stats: &StatsConfig,
+    items: &ItemRegistry,
+    loot: &LootTables,
 ) {
-    #[allow(clippy::single_match)]
-    match *transition {
-        MapTransition::Deeper => {
-            // TODO(Arnaldur): bruh
-            for (entity, _) in <(Entity, &TileType)>::query().iter(world) {
-                command_buffer.remove(*entity);
-            }
+    let restart = matches!(*transition, MapTransition::Restart);
+    let (next, arriving_by) = match *transition {
+        MapTransition::None => return,
+        MapTransition::Deeper => (floor.0 + 1, TileType::LadderUp),
+        MapTransition::Shallower => (floor.0 - 1, TileType::LadderDown),
+        MapTransition::Restart => (1, TileType::Floor),
+    };
+    *transition = MapTransition::None;
+    // There is nothing above the first floor
+    if next < 1 {
+        return;
+    }
 
-            for (entity, faction) in <(Entity, &Faction)>::query().iter(world) {
-                if let Faction::Enemies = faction {
-                    command_buffer.remove(*entity);
-                }
+    let left = floor.0;
+    let leaving = floor_entities(world, player);
+    if restart {
+        println!("Generating the dungeon from seed {}", seed.0);
+        floors.clear();
+    } else {
+        floors.park_tiles(left, floor_tiles.take());
+    }
+    command_buffer.exec_mut(move |world, resources| {
+        release_physics(world, resources, &leaving);
+        if restart {
+            for &entity in &leaving {
+                world.remove(entity);
             }
+        } else {
+            let mut floors = resources.get_mut::<Floors>().unwrap();
+            floors.park(left, world, &leaving);
+        }
+    });
 
-            floor.0 += 1;
-
-            println!("You have reached floor {}", floor.0);
-
-            //let mut rng = thread_rng();
-
-            //let wfc_source = image::open("assets/Images/dungeon_5_separated.png").unwrap();
-            let wfc_source = image::open("maps/WFC.png").unwrap();
-
-            // use crate::world_gen::grid::Grid;
-            // use crate::world_gen::wfc::{wfc, SQUARE_NEIGHBOURHOOD};
-            // let wfc_result = wfc(
-            //     Grid::from(&wfc_source.into_rgb8()),
-            //     &SQUARE_NEIGHBOURHOOD,
-            //     Vector2::<usize>::from_value(64),
-            //     &[],
-            // );
-
-            // let wfc_result = image_patterns
-            //     .collapse_wave_retrying(
-            //         map_size,
-            //         wrap::WrapXY,
-            //         EmptyEdgesForbid {
-            //             empty_tile_id: top_left_corner_id,
-            //         },
-            //         retry::Forever,
-            //         &mut StdRng::from_entropy(),
-            //     )
-            //     .apply(|wave| image_patterns.image_from_wave(&wave));
-            let wfc_result = wfc_source;
-
-            let test_world = wfc_result
-                .into_bgr8()
-                .enumerate_pixels()
-                .map(|(x, y, pixel)| {
-                    ((x as i32, y as i32), {
-                        let [b, g, r] = pixel.0;
-                        let direction = match r {
-                            0 => Direction::North,
-                            64 => Direction::East,
-                            128 => Direction::South,
-                            192 => Direction::West,
-                            _ => Direction::North,
-                        };
-                        if b > 0 {
-                            match g {
-                                0 => TileType::Floor,
-                                64 => TileType::CornerIn(direction),
-                                128 => TileType::CornerOut(direction),
-                                192 => TileType::Wall(direction),
-                                _ => TileType::Unknown,
-                            }
-                        } else {
-                            TileType::Nothing
-                        }
-                    })
-                })
-                .collect::<HashMap<(i32, i32), TileType>>();
-
-            populate_environment(command_buffer, &test_world);
+    floor.0 = next;
+    println!("You have reached floor {}", floor.0);
 
-            let player_start = test_world
+    let arrival = match floors.take_tiles(next) {
+        Some(tiles) => {
+            command_buffer.exec_mut(move |world, resources| {
+                let mut floors = resources.get_mut::<Floors>().unwrap();
+                floors.unpark(next, world);
+            });
+            let mut ladders = tiles
                 .iter()
-                .filter(|&(_, &tile_type)| tile_type == TileType::Floor)
-                .nth(100)
-                //.choose(&mut rng)
-                .map(|((x, y), _)| vec2(*x as f32, *y as f32))
-                .unwrap();
-
-            // Reset player position and stuff
-            command_buffer
-                .forge(player.player)
-                .position(player_start.extend(0.))
-                .velocity_zero();
-
-            add_enemies(command_buffer, floor, &test_world);
+                .filter(|&(_, &tile_type)| tile_type == arriving_by)
+                .map(|(&location, _)| location)
+                .collect::<Vec<_>>();
+            ladders.sort_unstable();
+            let arrival = ladders.first().copied();
+            floor_tiles.replace(tiles);
+            arrival
         }
-        _ => {}
+        None => generate_floor(
+            command_buffer,
+            floor,
+            floor_tiles,
+            &mut seed.floor_rng(next),
+            levels,
+            factions,
+            stats,
+            items,
+            loot,
+        ),
+    };
+
+    // Reset player position and stuff
+    if let Some((x, y)) = arrival {
+        command_buffer
+            .forge(player.player)
+            .position(vec2(x as f32, y as f32).extend(0.))
+            .velocity_zero();
     }
-    *transition = MapTransition::None;
+}
+
+/// Everything that belongs to the floor being played, and stays behind when the player leaves it
+fn floor_entities(world: &SubWorld, player: &Player) -> Vec<Entity> {
+    // Everyone but the player belongs to the floor they were met on
+    let mut entities = <(Entity, &Faction)>::query()
+        .iter(world)
+        .map(|(entity, _)| *entity)
+        .filter(|&entity| entity != player.player)
+        .collect::<Vec<_>>();
+
+    // TODO(Arnaldur): bruh
+    entities.extend(
+        <Entity>::query()
+            .filter(
+                component::<TileType>()
+                    | component::<GroundItem>()
+                    | component::<Container>()
+                    | component::<Projectile>()
+                    | component::<StaticModel>(),
+            )
+            .iter(world),
+    );
+    entities
+}
+
+/// A floor with its doors and ladders in place, before anything is put on it
+struct LaidOut {
+    level: GeneratedLevel,
+    tiles: HashMap<(i32, i32), TileType>,
+    arrival: (i32, i32),
+}
+
+/// Lays out the tiles of the floor, returning nothing when there is nowhere to stand on it
+fn lay_out_floor(floor: &FloorNumber, levels: &Levels, rng: &mut StdRng) -> Option<LaidOut> {
+    // A floor that can't be generated is still better than no floor at all
+    let mut level = levels
+        .generate(floor.0, rng)
+        .or_else(|error| {
+            println!("Could not generate floor {}: {}", floor.0, error);
+            SampleImage(SAMPLE_MAP.into()).generate(floor.0, rng)
+        })
+        .ok()?;
+    if !level.handcrafted {
+        seal_edges(&mut level.tiles);
+    }
+    // Walls are drawn with whichever piece of the wall kit fits where they are
+    autotile(&mut level.tiles);
+    let mut tiles = level.tile_map();
+
+    // Handcrafted levels come with their doors, ladders and keys already in place
+    if level.handcrafted {
+        let arrival = level.arrival()?;
+        return Some(LaidOut {
+            level,
+            tiles,
+            arrival,
+        });
+    }
+    if level.doors {
+        place_doors(&mut tiles, LOCKED_DOOR_CHANCE, rng);
+    }
+    let arrival = place_ladders(&mut tiles, &level.rooms, floor.0, rng)?;
+    Some(LaidOut {
+        level,
+        tiles,
+        arrival,
+    })
+}
+
+/// Lays out a floor that has never been visited and fills it up,
+/// returning where the player arrives
+#[allow(clippy::too_many_arguments)]
+fn generate_floor(
+    command_buffer: &mut CommandBuffer,
+    floor: &FloorNumber,
+    floor_tiles: &mut FloorTiles,
+    rng: &mut StdRng,
+    levels: &Levels,
+    factions: &FactionRegistry,
+    stats: &StatsConfig,
+    items: &ItemRegistry,
+    loot: &LootTables,
+) -> Option<(i32, i32)> {
+    // Generated floors are laid out again from a new seed until one can be played,
+    // handcrafted ones are taken as they are
+    let mut attempt = 1;
+    let laid_out = loop {
+        let laid_out = lay_out_floor(floor, levels, &mut StdRng::seed_from_u64(rng.gen()));
+        let error = match &laid_out {
+            Some(attempted) if attempted.level.handcrafted => break laid_out,
+            Some(attempted) => match validate(
+                &attempted.tiles,
+                &attempted.level.rooms,
+                attempted.arrival,
+                locked_doors(&attempted.tiles),
+                MIN_FLOOR_AREA,
+            ) {
+                Ok(()) => break laid_out,
+                Err(error) => error.to_string(),
+            },
+            None => String::from("there is nowhere to stand"),
+        };
+        if attempt == MAX_FLOOR_ATTEMPTS {
+            println!("Floor {} will have to do, {}", floor.0, error);
+            break laid_out;
+        }
+        attempt += 1;
+    };
+    let LaidOut {
+        level,
+        tiles: test_world,
+        arrival,
+    } = laid_out?;
+
+    // Unlocking a door uses its key up, so generated floors have a key for every locked door,
+    // lying where the player arrives
+    let locked_doors = locked_doors(&test_world);
+    let key = ItemId(DOOR_KEY.to_string());
+    if let (false, true, Some(definition)) = (level.handcrafted, locked_doors > 0, items.get(&key))
+    {
+        command_buffer.smith().ground_item(
+            definition,
+            ItemStack::new(key, locked_doors as u32),
+            vec2(arrival.0 as f32, arrival.1 as f32),
+        );
+    }
+
+    populate_environment(command_buffer, &test_world);
+
+    add_level_spawns(
+        command_buffer,
+        rng,
+        floor,
+        &level,
+        factions,
+        stats,
+        items,
+        loot,
+    );
+    // Generated floors are always populated, handcrafted ones only when they ask for it
+    if level.populate {
+        let rooms = Rooms::new(&level.rooms, arrival);
+        add_enemies(
+            command_buffer,
+            rng,
+            floor,
+            factions,
+            stats,
+            &test_world,
+            &rooms,
+        );
+        add_items(command_buffer, rng, items, &test_world);
+        add_containers(command_buffer, rng, floor, items, loot, &test_world, &rooms);
+    }
+
+    floor_tiles.replace(test_world);
+
+    Some(arrival)
 }
 
 fn populate_environment(
@@ -154,7 +372,9 @@ fn populate_environment(
                 TileType::Path => "DevFloor.obj",
                 TileType::CornerIn(_) => "DevCornerIn.obj",
                 TileType::CornerOut(_) => "DevCornerOut.obj",
-                TileType::LadderDown => "DevFloor.obj",
+                TileType::LadderDown => "StairsDown.obj",
+                TileType::LadderUp => "StairsUp.obj",
+                TileType::Door(_) => "DevFloor.obj",
 
                 TileType::Unknown => "cube.obj",
                 TileType::UndirectedWall => "cube.obj",
@@ -201,57 +421,442 @@ fn populate_environment(
 
         // tile specific behaviors
         match tile_type {
-            TileType::Wall(_) | TileType::CornerIn(_) | TileType::CornerOut(_) => {
+            TileType::Wall(_)
+            | TileType::UndirectedWall
+            | TileType::CornerIn(_)
+            | TileType::CornerOut(_) => {
                 smith.static_square_body(1.0);
             }
             TileType::LadderDown => {
                 smith.any(MapSwitcher(MapTransition::Deeper));
             }
+            TileType::LadderUp => {
+                smith.any(MapSwitcher(MapTransition::Shallower));
+            }
+            TileType::Door(state) => {
+                smith.door((x, y), state);
+            }
             _ => {}
         }
     }
 }
 
+/// The rooms of a floor as seen from the room the player arrives in
+struct Rooms<'a> {
+    graph: &'a RoomGraph,
+    start: Option<usize>,
+    distances: Vec<Option<usize>>,
+    // Corridors are as dangerous as the more dangerous room they lead to
+    corridors: HashMap<(i32, i32), f64>,
+}
+
+impl<'a> Rooms<'a> {
+    fn new(graph: &'a RoomGraph, arrival: (i32, i32)) -> Self {
+        let start = graph.room_at(arrival);
+        let distances = match start {
+            Some(start) => graph.distances(start),
+            None => vec![None; graph.rooms.len()],
+        };
+        let mut rooms = Rooms {
+            graph,
+            start,
+            distances,
+            corridors: HashMap::new(),
+        };
+        for corridor in &graph.corridors {
+            let (a, b) = corridor.between;
+            let danger = rooms.room_danger(a).max(rooms.room_danger(b));
+            for &location in &corridor.tiles {
+                let tile = rooms.corridors.entry(location).or_insert(danger);
+                *tile = tile.max(danger);
+            }
+        }
+        rooms
+    }
+
+    /// How many more monsters than usual there are in the room.
+    /// The room the player arrives in is left empty,
+    /// and the farther a room is from it the more crowded it gets.
+    fn room_danger(&self, room: usize) -> f64 {
+        let farthest = self.distances.iter().flatten().max().copied().unwrap_or(0);
+        match self.distances[room] {
+            Some(0) => 0.0,
+            Some(distance) => 0.5 + distance as f64 / farthest as f64,
+            None => 1.0,
+        }
+    }
+
+    /// How many more monsters than usual there are at the location
+    fn danger(&self, location: (i32, i32)) -> f64 {
+        match self.graph.room_at(location) {
+            Some(room) => self.room_danger(room),
+            None => self.corridors.get(&location).copied().unwrap_or(1.0),
+        }
+    }
+
+    /// The rooms off the beaten path, with only a single way in or out
+    fn dead_ends(&self) -> Vec<Rect> {
+        self.graph
+            .dead_ends()
+            .into_iter()
+            .filter(|&room| Some(room) != self.start)
+            .map(|room| self.graph.rooms[room])
+            .collect()
+    }
+}
+
 fn add_enemies(
     command_buffer: &mut CommandBuffer,
-    floor: &mut FloorNumber,
+    rng: &mut StdRng,
+    floor: &FloorNumber,
+    factions: &FactionRegistry,
+    stats: &StatsConfig,
     dungeon: &HashMap<(i32, i32), TileType>,
+    rooms: &Rooms,
 ) {
-    let mut rng = thread_rng();
-
     // Add enemies to floor
+    let chance = ((floor.0 - 1) as f64 * 0.05 + 1.).log2().min(1.);
 
-    for (&(x, y), &tile_type) in dungeon.iter() {
+    for ((x, y), tile_type) in tiles_in_order(dungeon) {
         let pos = Vector2::new(x as f32, y as f32);
 
-        if TileType::Floor == tile_type
-            && rng.gen_bool(((floor.0 - 1) as f64 * 0.05 + 1.).log2().min(1.) as f64)
-        {
+        if TileType::Floor == tile_type && rng.gen_bool((chance * rooms.danger((x, y))).min(1.)) {
             let rad = rng.gen_range(0.1..0.4) + rng.gen_range(0.0..0.1);
-            let mut smith = command_buffer.smith();
-            smith
-                .position(
-                    (pos + Vector2::new(rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3)))
-                        .extend(0.),
-                )
-                .agent(
-                    rng.gen_range(1.0..4.0) - 1.6 * rad,
-                    rng.gen_range(3.0..9.0) + 2.0 * rad,
-                )
-                .orientation(0.0)
-                .velocity_zero()
-                .dynamic_body(rad)
-                .circle_collider(rad)
-                .any(Faction::Enemies)
-                .any(HitPoints {
-                    max: rng.gen_range(0.0..2.0) + 8. * rad,
-                    health: rng.gen_range(0.0..2.0) + 8. * rad,
-                })
-                .any(DynamicModelRequest {
-                    label: "monstroman.obj".to_string(),
-                })
-                .any(Scale(rad * 1.7))
-                .done();
+            let pos = pos + Vector2::new(rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3));
+            add_creature(command_buffer, rng, floor, factions, stats, pos, rad);
+        }
+    }
+}
+
+/// A monster of the given size, the smallest critters among them are vermin
+fn add_creature(
+    command_buffer: &mut CommandBuffer,
+    rng: &mut StdRng,
+    floor: &FloorNumber,
+    factions: &FactionRegistry,
+    stats: &StatsConfig,
+    pos: Vector2<f32>,
+    rad: f32,
+) {
+    let monsters = factions.get("Monsters").expect("There are no monsters");
+    let vermin = factions.get("Vermin").expect("There are no vermin");
+
+    // The smallest critters are prey to the others
+    let is_vermin = rad < 0.2;
+    let creature = if is_vermin {
+        &stats.vermin
+    } else {
+        &stats.monster
+    };
+    let attributes = creature.attributes(floor.0, rng);
+    let formulas = &stats.formulas;
+    let damage = formulas.damage.of(&attributes);
+
+    let mut smith = command_buffer.smith();
+    smith
+        .position(pos.extend(0.))
+        .agent(
+            formulas.speed.of(&attributes),
+            formulas.acceleration.of(&attributes),
+        )
+        .orientation(0.0)
+        .velocity_zero()
+        .dynamic_body(rad)
+        .circle_collider(rad)
+        .any(if is_vermin { vermin } else { monsters })
+        .any(AIBrain::monster(pos))
+        .any(Regeneration(formulas.regeneration.of(&attributes)))
+        .any(if is_vermin {
+            // Small, but their bites fester
+            MeleeAttack::new(damage, 0.9, 1.0, 1.0).inflicting(StatusEffect::new(
+                EffectKind::Poison,
+                0.5,
+                4.0,
+            ))
+        } else {
+            MeleeAttack::new(damage, 0.9, 2.0, 1.0)
+        })
+        .any(OnDeath::Corpse)
+        .any(ObscuredByFog)
+        .any(formulas.hit_points(&attributes))
+        .any(attributes)
+        .any(ExperienceReward(creature.experience * floor.0 as f32))
+        .any(LootDrop(
+            if is_vermin { "vermin" } else { "monster" }.to_string(),
+        ))
+        .any(DynamicModelRequest {
+            label: "monstroman.obj".to_string(),
+        })
+        .any(Scale(rad * 1.7))
+        .done();
+}
+
+fn add_items(
+    command_buffer: &mut CommandBuffer,
+    rng: &mut StdRng,
+    items: &ItemRegistry,
+    dungeon: &HashMap<(i32, i32), TileType>,
+) {
+    let ids = items.ids();
+
+    for ((x, y), tile_type) in tiles_in_order(dungeon) {
+        if TileType::Floor != tile_type || !rng.gen_bool(0.02) {
+            continue;
+        }
+        if let Some(&id) = ids.choose(rng) {
+            let definition = items.get(id).unwrap();
+            let count = rng.gen_range(1..=definition.max_stack.min(10));
+            command_buffer.smith().ground_item(
+                definition,
+                ItemStack::new(id.clone(), count),
+                Vector2::new(x as f32, y as f32),
+            );
+        }
+    }
+}
+
+/// Containers go up against the walls of rooms, never in corridors where they would block the way.
+/// Every room at a dead end has one, as a reward for going out of the way.
+fn add_containers(
+    command_buffer: &mut CommandBuffer,
+    rng: &mut StdRng,
+    floor: &FloorNumber,
+    items: &ItemRegistry,
+    loot: &LootTables,
+    dungeon: &HashMap<(i32, i32), TileType>,
+    rooms: &Rooms,
+) {
+    let is_floor = |location| matches!(dungeon.get(&location), Some(TileType::Floor));
+    let by_wall = |(x, y): (i32, i32)| {
+        is_floor((x, y))
+            && [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]
+                .iter()
+                .filter(|&&location| is_floor(location))
+                .count()
+                == 3
+    };
+
+    let mut spots = tiles_in_order(dungeon)
+        .into_iter()
+        .map(|(location, _)| location)
+        .filter(|&location| by_wall(location) && rng.gen_bool(0.03))
+        .collect::<Vec<_>>();
+    for room in rooms.dead_ends() {
+        let in_room = room.locations().filter(|&location| by_wall(location));
+        if let Some(spot) = in_room.filter(|spot| !spots.contains(spot)).choose(rng) {
+            spots.push(spot);
+        }
+    }
+
+    for (x, y) in spots {
+        if let Some(definition) = loot.choose_container(rng) {
+            let stacks = loot.roll(&definition.table, floor.0, rng);
+            command_buffer.smith().container(
+                definition,
+                container_contents(stacks, items),
+                Vector2::new(x as f32, y as f32),
+            );
+        }
+    }
+}
+
+fn container_contents(stacks: Vec<ItemStack>, items: &ItemRegistry) -> Inventory {
+    let mut contents = Inventory::new(8, f32::INFINITY);
+    for stack in stacks {
+        contents.insert(stack, items);
+    }
+    contents
+}
+
+/// Everything a level places itself
+#[allow(clippy::too_many_arguments)]
+fn add_level_spawns(
+    command_buffer: &mut CommandBuffer,
+    rng: &mut StdRng,
+    floor: &FloorNumber,
+    level: &GeneratedLevel,
+    factions: &FactionRegistry,
+    stats: &StatsConfig,
+    items: &ItemRegistry,
+    loot: &LootTables,
+) {
+    let at = |(x, y): (i32, i32)| Vector2::new(x as f32, y as f32);
+
+    for spawn in &level.creatures {
+        let rad = match spawn.creature {
+            Creature::Vermin => rng.gen_range(0.1..0.2),
+            Creature::Monster => rng.gen_range(0.2..0.5),
+        };
+        add_creature(
+            command_buffer,
+            rng,
+            floor,
+            factions,
+            stats,
+            at(spawn.at),
+            rad,
+        );
+    }
+
+    for spawn in &level.items {
+        if let Some(definition) = items.get(&spawn.item) {
+            command_buffer.smith().ground_item(
+                definition,
+                ItemStack::new(spawn.item.clone(), spawn.count),
+                at(spawn.at),
+            );
+        }
+    }
+
+    for spawn in &level.containers {
+        if let Some(definition) = loot.container(&spawn.container) {
+            let stacks = match &spawn.contents {
+                Some(contents) => contents
+                    .iter()
+                    .map(|(item, count)| ItemStack::new(item.clone(), *count))
+                    .collect(),
+                None => loot.roll(&definition.table, floor.0, rng),
+            };
+            command_buffer.smith().container(
+                definition,
+                container_contents(stacks, items),
+                at(spawn.at),
+            );
+        }
+    }
+}
+
+/// The tiles sorted by location, so that going through them
+/// consumes the random numbers in the same order every time
+fn tiles_in_order(dungeon: &HashMap<(i32, i32), TileType>) -> Vec<((i32, i32), TileType)> {
+    let mut tiles = dungeon
+        .iter()
+        .map(|(&location, &tile_type)| (location, tile_type))
+        .collect::<Vec<_>>();
+    tiles.sort_unstable_by_key(|&(location, _)| location);
+    tiles
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use legion::{Resources, World};
+
+    use super::*;
+    use crate::components::HitPoints;
+    use crate::factions::registry::FACTIONS_PATH;
+    use crate::items::loot::LOOT_PATH;
+    use crate::items::registry::ITEMS_PATH;
+    use crate::stats::config::STATS_PATH;
+    use crate::world_gen::bsp::Bsp;
+    use crate::world_gen::caves::Caves;
+    use crate::world_gen::dung_gen::DungGen;
+    use crate::world_gen::generator::GeneratorConfig;
+
+    /// What came out of generating a floor, in a form that can be compared bit for bit
+    struct Generated {
+        tiles: HashMap<(i32, i32), TileType>,
+        arrival: Option<(i32, i32)>,
+        positions: Vec<[u32; 3]>,
+        hit_points: Vec<u32>,
+        loot: Vec<ItemStack>,
+    }
+
+    fn generate(seed: RunSeed, floor: i32, levels: &Levels) -> Generated {
+        let factions = FactionRegistry::load(Path::new(FACTIONS_PATH)).unwrap();
+        let stats = StatsConfig::load(Path::new(STATS_PATH)).unwrap();
+        let items = ItemRegistry::load(Path::new(ITEMS_PATH)).unwrap();
+        let loot = LootTables::load(Path::new(LOOT_PATH)).unwrap();
+
+        let mut world = World::default();
+        let mut command_buffer = CommandBuffer::new(&world);
+        let mut floor_tiles = FloorTiles::default();
+        let arrival = generate_floor(
+            &mut command_buffer,
+            &FloorNumber(floor),
+            &mut floor_tiles,
+            &mut seed.floor_rng(floor),
+            levels,
+            &factions,
+            &stats,
+            &items,
+            &loot,
+        );
+        command_buffer.flush(&mut world, &mut Resources::default());
+
+        let mut positions = <&Position>::query()
+            .iter(&world)
+            .map(|position| {
+                let [x, y, z]: [f32; 3] = position.0.into();
+                [x.to_bits(), y.to_bits(), z.to_bits()]
+            })
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+        let mut hit_points = <&HitPoints>::query()
+            .iter(&world)
+            .map(|hp| hp.max.to_bits())
+            .collect::<Vec<_>>();
+        hit_points.sort_unstable();
+        let mut loot = <&GroundItem>::query()
+            .iter(&world)
+            .map(|GroundItem(stack)| stack.clone())
+            .chain(
+                <&Inventory>::query()
+                    .iter(&world)
+                    .flat_map(|inventory| inventory.slots().iter().flatten().cloned()),
+            )
+            .collect::<Vec<_>>();
+        loot.sort_unstable_by(|a, b| (&a.item, a.count).cmp(&(&b.item, b.count)));
+
+        Generated {
+            tiles: floor_tiles.take(),
+            arrival,
+            positions,
+            hit_points,
+            loot,
+        }
+    }
+
+    fn same_floor(first: &Generated, again: &Generated) {
+        assert!(first.tiles == again.tiles);
+        assert_eq!(first.arrival, again.arrival);
+        assert_eq!(first.positions, again.positions);
+        assert_eq!(first.hit_points, again.hit_points);
+        assert_eq!(first.loot, again.loot);
+    }
+
+    #[test]
+    fn the_same_seed_makes_the_same_floor() {
+        let levels = Levels::default();
+        let first = generate(RunSeed(1337), 3, &levels);
+        same_floor(&first, &generate(RunSeed(1337), 3, &levels));
+        assert!(!first.hit_points.is_empty());
+
+        let other = generate(RunSeed(1338), 3, &levels);
+        assert!(first.arrival != other.arrival || first.positions != other.positions);
+    }
+
+    #[test]
+    fn every_generator_makes_the_same_floor_from_the_same_seed() {
+        // Only the sample as it is, as every pattern makes the wave function collapse slower
+        let wfc = r#"Wfc((
+            sample: "assets/Images/dungeon_5_separated.png",
+            size: 16,
+            orientations: [Original],
+        ))"#;
+        let generators: Vec<Box<dyn LevelGenerator>> = vec![
+            Box::new(DungGen::new()),
+            Box::new(Bsp::default()),
+            Box::new(Caves::default()),
+            ron::de::from_str::<GeneratorConfig>(wfc).unwrap().build(),
+        ];
+        for generator in generators {
+            let mut levels = Levels::default();
+            levels.schedule(1, generator);
+            let first = generate(RunSeed(1337), 3, &levels);
+            same_floor(&first, &generate(RunSeed(1337), 3, &levels));
+            assert!(first.tiles != generate(RunSeed(1338), 3, &levels).tiles);
         }
     }
 }
@@ -0,0 +1,288 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+
+use crate::world_gen::components::{DoorState, TileType};
+use crate::world_gen::grid::{Grid, V2u};
+use crate::world_gen::rooms::RoomGraph;
+
+type Location = (i32, i32);
+
+// The fewest tiles a generated floor can have to walk around on
+pub const MIN_FLOOR_AREA: usize = 50;
+
+// How many times a floor is generated again before settling for what came out
+pub const MAX_FLOOR_ATTEMPTS: usize = 10;
+
+/// Why a generated floor can't be played as it is
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidFloor {
+    // There is no floor under where the player starts
+    NoStart(Location),
+    NoLadderDown,
+    // None of the ladders down can be walked to from where the player starts
+    LadderUnreachable { start: Location, ladder: Location },
+    // Every ladder down is behind more locked doors than there are keys for
+    NotEnoughKeys { needed: usize, keys: usize },
+    // Some room can't be walked to from where the player starts
+    RoomUnreachable { room: usize, at: Location },
+    TooSmall { area: usize, min_area: usize },
+    // Something walkable at the very edge of the map, with no room left for a wall around it
+    OutOfBounds(Location),
+}
+
+impl Display for InvalidFloor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidFloor::NoStart(at) => write!(f, "there is no floor to start on at {:?}", at),
+            InvalidFloor::NoLadderDown => write!(f, "there is no ladder down"),
+            InvalidFloor::LadderUnreachable { start, ladder } => write!(
+                f,
+                "the ladder down at {:?} can't be reached from {:?}",
+                ladder, start
+            ),
+            InvalidFloor::NotEnoughKeys { needed, keys } => write!(
+                f,
+                "the ladder down needs {} keys and there are {}",
+                needed, keys
+            ),
+            InvalidFloor::RoomUnreachable { room, at } => {
+                write!(f, "room {} at {:?} can't be reached", room, at)
+            }
+            InvalidFloor::TooSmall { area, min_area } => write!(
+                f,
+                "only {} tiles can be walked on, {} are needed",
+                area, min_area
+            ),
+            InvalidFloor::OutOfBounds(at) => {
+                write!(f, "{:?} is open right up to the edge of the map", at)
+            }
+        }
+    }
+}
+
+fn is_walkable(tiles: &HashMap<Location, TileType>, location: Location) -> bool {
+    matches!(tiles.get(&location), Some(tile) if tile.is_walkable())
+}
+
+/// Every tile that can be walked to from the given one,
+/// with the fewest locked doors that have to be unlocked on the way there
+pub fn keys_needed(
+    tiles: &HashMap<Location, TileType>,
+    from: Location,
+) -> HashMap<Location, usize> {
+    let mut needed = HashMap::new();
+    let mut next = VecDeque::new();
+    next.push_back((from, 0));
+    while let Some(((x, y), keys)) = next.pop_front() {
+        if !is_walkable(tiles, (x, y)) || needed.contains_key(&(x, y)) {
+            continue;
+        }
+        needed.insert((x, y), keys);
+        for &neighbour in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)].iter() {
+            // Tiles behind a locked door go to the back, so everything is reached
+            // through as few of them as it can be
+            match tiles.get(&neighbour) {
+                Some(TileType::Door(DoorState::Locked)) => next.push_back((neighbour, keys + 1)),
+                _ => next.push_front((neighbour, keys)),
+            }
+        }
+    }
+    needed
+}
+
+/// Makes sure the floor can be played: the ladder down and every room
+/// can be walked to from the start with the keys there are, there is enough of it,
+/// and all of it is walled in
+pub fn validate(
+    tiles: &HashMap<Location, TileType>,
+    rooms: &RoomGraph,
+    start: Location,
+    keys: usize,
+    min_area: usize,
+) -> Result<(), InvalidFloor> {
+    let mut walkable = tiles
+        .keys()
+        .copied()
+        .filter(|&location| is_walkable(tiles, location))
+        .collect::<Vec<_>>();
+    walkable.sort_unstable();
+
+    let at_edge = |&(x, y): &Location| {
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .any(|neighbour| !tiles.contains_key(&neighbour))
+    };
+    if let Some(&location) = walkable.iter().find(|location| at_edge(location)) {
+        return Err(InvalidFloor::OutOfBounds(location));
+    }
+
+    if !is_walkable(tiles, start) {
+        return Err(InvalidFloor::NoStart(start));
+    }
+    let needed = keys_needed(tiles, start);
+    let reached = needed
+        .iter()
+        .filter(|&(_, &needed)| needed <= keys)
+        .map(|(&location, _)| location)
+        .collect::<HashSet<_>>();
+
+    let ladders = walkable
+        .iter()
+        .copied()
+        .filter(|location| tiles[location] == TileType::LadderDown)
+        .collect::<Vec<_>>();
+    let fewest_keys = ladders.iter().filter_map(|ladder| needed.get(ladder)).min();
+    match (ladders.first(), fewest_keys) {
+        (None, _) => return Err(InvalidFloor::NoLadderDown),
+        (Some(&ladder), None) => return Err(InvalidFloor::LadderUnreachable { start, ladder }),
+        (_, Some(&needed)) if needed > keys => {
+            return Err(InvalidFloor::NotEnoughKeys { needed, keys })
+        }
+        _ => {}
+    }
+
+    for (room, bounds) in rooms.rooms.iter().enumerate() {
+        let mut floor = bounds
+            .locations()
+            .filter(|&location| is_walkable(tiles, location))
+            .peekable();
+        if let Some(&at) = floor.peek() {
+            if !floor.any(|location| reached.contains(&location)) {
+                return Err(InvalidFloor::RoomUnreachable { room, at });
+            }
+        }
+    }
+
+    if reached.len() < min_area {
+        return Err(InvalidFloor::TooSmall {
+            area: reached.len(),
+            min_area,
+        });
+    }
+    Ok(())
+}
+
+/// Walls up anything walkable on the edge of the map,
+/// where there would be no room for a wall around it
+pub fn seal_edges(tiles: &mut Grid<TileType>) {
+    let size = tiles.size;
+    for y in 0..size.y {
+        for x in 0..size.x {
+            let on_edge = x == 0 || y == 0 || x + 1 == size.x || y + 1 == size.y;
+            let tile = &mut tiles[V2u::new(x, y)];
+            if on_edge && tile.is_walkable() {
+                *tile = TileType::UndirectedWall;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world_gen::rooms::Rect;
+
+    /// Reads a map drawn with `#` for walls, `.` for floor, `+` for locked doors
+    /// and `>` for the ladder down
+    fn parse(rows: &[&str]) -> HashMap<Location, TileType> {
+        rows.iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.chars().enumerate().map(move |(x, c)| {
+                    let tile_type = match c {
+                        '.' => TileType::Floor,
+                        '>' => TileType::LadderDown,
+                        '+' => TileType::Door(DoorState::Locked),
+                        _ => TileType::UndirectedWall,
+                    };
+                    ((x as i32, y as i32), tile_type)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn floors_must_be_walked_from_start_to_ladder() {
+        let rooms = RoomGraph::default();
+        let good = parse(&["######", "#..#>#", "#....#", "######"]);
+        assert_eq!(validate(&good, &rooms, (1, 1), 0, 7), Ok(()));
+        assert_eq!(
+            validate(&good, &rooms, (1, 1), 0, 8),
+            Err(InvalidFloor::TooSmall {
+                area: 7,
+                min_area: 8
+            })
+        );
+        assert_eq!(
+            validate(&good, &rooms, (0, 0), 0, 1),
+            Err(InvalidFloor::NoStart((0, 0)))
+        );
+
+        let cut_off = parse(&["######", "#..#>#", "#..###", "######"]);
+        assert_eq!(
+            validate(&cut_off, &rooms, (1, 1), 0, 1),
+            Err(InvalidFloor::LadderUnreachable {
+                start: (1, 1),
+                ladder: (4, 1)
+            })
+        );
+
+        let no_ladder = parse(&["######", "#....#", "######"]);
+        assert_eq!(
+            validate(&no_ladder, &rooms, (1, 1), 0, 1),
+            Err(InvalidFloor::NoLadderDown)
+        );
+
+        let open = parse(&["#####", "#.>..", "#####"]);
+        assert_eq!(
+            validate(&open, &rooms, (1, 1), 0, 1),
+            Err(InvalidFloor::OutOfBounds((4, 1)))
+        );
+    }
+
+    #[test]
+    fn locked_doors_need_keys() {
+        let rooms = RoomGraph::default();
+        let locked_in = parse(&["#######", "#.+.+>#", "#######"]);
+        assert_eq!(validate(&locked_in, &rooms, (1, 1), 2, 1), Ok(()));
+        assert_eq!(
+            validate(&locked_in, &rooms, (1, 1), 1, 1),
+            Err(InvalidFloor::NotEnoughKeys { needed: 2, keys: 1 })
+        );
+
+        let way_around = parse(&["#######", "#.+.+>#", "#.###.#", "#.....#", "#######"]);
+        assert_eq!(validate(&way_around, &rooms, (1, 1), 0, 1), Ok(()));
+        assert_eq!(keys_needed(&way_around, (1, 1))[&(3, 1)], 1);
+    }
+
+    #[test]
+    fn every_room_must_be_reached() {
+        let tiles = parse(&["#########", "#.>#..#.#", "#########"]);
+        let mut rooms = RoomGraph::default();
+        for &(x, width) in [(1, 2), (4, 2), (7, 1)].iter() {
+            rooms.add_room(Rect {
+                x,
+                y: 1,
+                width,
+                height: 1,
+            });
+        }
+        assert_eq!(
+            validate(&tiles, &rooms, (1, 1), 0, 1),
+            Err(InvalidFloor::RoomUnreachable {
+                room: 1,
+                at: (4, 1)
+            })
+        );
+    }
+
+    #[test]
+    fn edges_are_walled_up() {
+        let mut tiles = Grid::new();
+        tiles.resize(V2u::new(3, 3), TileType::Floor);
+        seal_edges(&mut tiles);
+        assert!(tiles[V2u::new(1, 1)] == TileType::Floor);
+        assert!(tiles[V2u::new(0, 1)] == TileType::UndirectedWall);
+        assert!(tiles[V2u::new(2, 2)] == TileType::UndirectedWall);
+    }
+}
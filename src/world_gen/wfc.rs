@@ -1,16 +1,16 @@
 use std::cmp::{max, min};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::fmt::Debug;
+use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::iter::{FromIterator, Map};
 use std::ops::{Index, IndexMut, Range};
 
-use apply::Apply;
 use bit_set::BitSet;
 use image::{ImageBuffer, Pixel};
 use itertools::Itertools;
 use rand::prelude::*;
+use serde::Deserialize;
 
 use crate::world_gen::grid::{Grid, V2i, V2u};
 
@@ -26,7 +26,6 @@ pub(crate) const SQUARE_NEIGHBOURHOOD: [V2i; 9] = [
     V2i::new(0, 1),
     V2i::new(1, 1),
 ];
-#[allow(dead_code)]
 const CROSS_NEIGHBOURHOOD: [V2i; 5] = [
     V2i::new(0, -1),
     V2i::new(-1, 0),
@@ -54,80 +53,284 @@ impl<P: Pixel + 'static> From<&Grid<P>> for ImageBuffer<P, Vec<P::Subpixel>> {
     }
 }
 
-/// A container that
+/// The undecided output cells, grouped by their entropy.
+/// Entropies are kept as their bits, which sort the same as they do since they are never negative.
 #[derive(Clone, Debug)]
 struct EntropyHierarchy {
-    pub hierarchy: BTreeMap<usize, BitSet>,
+    pub hierarchy: BTreeMap<u64, BitSet>,
+    entropies: Vec<Option<u64>>,
 }
 
 impl EntropyHierarchy {
-    fn new() -> Self {
+    fn new(cells: usize, entropy: Option<f64>) -> Self {
+        let entropy = entropy.map(f64::to_bits);
         Self {
-            hierarchy: BTreeMap::new(),
+            hierarchy: BTreeMap::from_iter(
+                entropy
+                    .filter(|_| cells > 0)
+                    .map(|entropy| (entropy, BitSet::from_iter(0..cells))),
+            ),
+            entropies: vec![entropy; cells],
         }
     }
-    fn add(&mut self, value: usize, entropy: usize) {
-        if !self.hierarchy.entry(entropy).or_default().insert(value) {
-            println!(
-                "adding {} to {}, in hierarchy {:?}",
-                value, entropy, self.hierarchy
-            );
-            panic!();
+
+    /// Moves the cell to its new entropy, or out of the hierarchy altogether once it is decided
+    fn set(&mut self, value: usize, entropy: Option<f64>) {
+        let entropy = entropy.map(f64::to_bits);
+        let original_entropy = std::mem::replace(&mut self.entropies[value], entropy);
+        if original_entropy == entropy {
+            return;
+        }
+        if let Some(original_entropy) = original_entropy {
+            if let Some(class) = self.hierarchy.get_mut(&original_entropy) {
+                class.remove(value);
+                if class.is_empty() {
+                    self.hierarchy.remove(&original_entropy);
+                }
+            }
+        }
+        if let Some(entropy) = entropy {
+            self.hierarchy.entry(entropy).or_default().insert(value);
         }
     }
 
-    fn reduce_entropy(&mut self, value: usize, original_entropy: usize, reduced_entropy: usize) {
-        if let Some(true) = self
-            .hierarchy
-            .get_mut(&original_entropy)
-            .map(|e| e.remove(value))
-        {
-            // println!(
-            //     "Reducing entropy of {} from {} to {}",
-            //     value, original_entropy, reduced_entropy
-            // );
-        } else {
-            panic!(
-                "value {} missing from entropy class {}, going to {} in hierarchy {:?}",
-                value, original_entropy, reduced_entropy, self.hierarchy
-            );
-        }
-        self.add(value, reduced_entropy);
-    }
-    // TODO: see if this is as slow as I suspect.
-    fn get_lowest_entropy(&mut self, mut rng: &mut StdRng) -> (usize, usize) {
+    /// One of the undecided cells with the lowest entropy, nothing once every cell is decided
+    fn get_lowest_entropy(&self, mut rng: &mut StdRng) -> Option<usize> {
         self.hierarchy
-            .iter_mut()
-            .find(|(i, _)| **i > 1)
-            .unwrap()
-            .apply(|(tile, set)| (*tile, set.iter().choose(&mut rng).unwrap()))
-    }
-    fn cleanup(&mut self) { self.hierarchy.retain(|_, set| !set.is_empty()); }
-    fn is_converged(&self) -> bool {
-        for layer in self.hierarchy.iter() {
-            if *layer.0 > 1 {
-                return false;
-            }
-        }
-        return true;
+            .values()
+            .next()
+            .and_then(|set| set.iter().choose(&mut rng))
     }
+
     #[allow(dead_code)]
     fn print<T>(&self, map: &Grid<T>) {
-        let mut display = Grid::<usize>::new();
-        display.resize(map.size, 0);
         println!("Entropy map:");
-        for (entropy, bit_set) in self.hierarchy.iter() {
-            for bit in bit_set.iter() {
-                display.buf[bit] = *entropy;
+        for row in self.entropies.chunks(map.size.x.max(1)) {
+            for entropy in row {
+                print!("{:.2}, ", entropy.map_or(0.0, f64::from_bits));
+            }
+            println!();
+        }
+    }
+}
+
+/// How uncertain a cell is, going by how often each of its tiles turns up in the sample.
+/// Nothing once there is at most one tile left for it.
+fn shannon_entropy(tiles: &BitSet, weights: &[f64]) -> Option<f64> {
+    if tiles.len() < 2 {
+        return None;
+    }
+    let (sum, sum_of_logs) = tiles
+        .iter()
+        .map(|tile| weights[tile])
+        .filter(|&weight| weight > 0.0)
+        .fold((0.0, 0.0), |(sum, sum_of_logs), weight| {
+            (sum + weight, sum_of_logs + weight * weight.ln())
+        });
+    Some(if sum > 0.0 {
+        (sum.ln() - sum_of_logs / sum).max(0.0)
+    } else {
+        0.0
+    })
+}
+
+/// The tiles every output cell could still be,
+/// along with what they could be before each change so that changes can be undone
+struct Wave {
+    cells: Grid<BitSet>,
+    // How likely each tile is to be picked, by how often it turns up in the sample
+    weights: Vec<f64>,
+    entropy_hierarchy: EntropyHierarchy,
+    trail: Vec<(usize, BitSet)>,
+}
+
+impl Wave {
+    fn new(size: V2u, weights: Vec<f64>) -> Self {
+        let mut cells = Grid::new();
+        let all = BitSet::from_iter(0..weights.len());
+        cells.resize(size, all.clone());
+        Self {
+            entropy_hierarchy: EntropyHierarchy::new(
+                cells.buf.len(),
+                shannon_entropy(&all, &weights),
+            ),
+            cells,
+            weights,
+            trail: Vec::new(),
+        }
+    }
+
+    fn set(&mut self, cell: usize, tiles: BitSet) {
+        self.entropy_hierarchy
+            .set(cell, shannon_entropy(&tiles, &self.weights));
+        let original = std::mem::replace(&mut self.cells.buf[cell], tiles);
+        self.trail.push((cell, original));
+    }
+
+    /// Puts back everything that changed since the trail was this long
+    fn undo(&mut self, trail_length: usize) {
+        while self.trail.len() > trail_length {
+            let (cell, tiles) = self.trail.pop().unwrap();
+            self.entropy_hierarchy
+                .set(cell, shannon_entropy(&tiles, &self.weights));
+            self.cells.buf[cell] = tiles;
+        }
+    }
+
+    /// One of the tiles the cell could still be, picked by their weights
+    fn choose(&self, cell: usize, rng: &mut StdRng) -> usize {
+        let tiles = self.cells.buf[cell].iter().collect::<Vec<_>>();
+        tiles
+            .choose_weighted(rng, |&tile| self.weights[tile])
+            .copied()
+            // Unless none of them has any weight at all
+            .unwrap_or_else(|_| *tiles.choose(rng).unwrap())
+    }
+}
+
+/// A cell that was collapsed to a tile, and how long the trail was before it
+struct Decision {
+    cell: usize,
+    tile: usize,
+    trail_length: usize,
+}
+
+/// Why wave function collapse couldn't fill in the output
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WfcError {
+    /// The sample has nothing in it to take patterns from
+    EmptySample,
+    /// Nothing fits at the cell, whichever way every decision before it is made
+    Contradiction { at: V2u },
+    /// Collapsing and backtracking took more steps than allowed,
+    /// with where the last contradiction was if there was one
+    OutOfSteps { steps: usize, at: Option<V2u> },
+    /// Every output fell short of the constraints on the whole of it
+    Unmet(Unmet),
+}
+
+/// A constraint on the whole output that it fell short of
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Unmet {
+    /// The required colour, by where it is in the list, fits nowhere
+    Required(usize),
+    TooLittleWalkable { walkable: usize, cells: usize },
+    /// The walkable cells are split up into this many separate areas
+    Disconnected { areas: usize },
+}
+
+impl Display for Unmet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unmet::Required(i) => write!(f, "required colour {} fits nowhere", i),
+            Unmet::TooLittleWalkable { walkable, cells } => {
+                write!(f, "only {} of {} cells can be walked on", walkable, cells)
+            }
+            Unmet::Disconnected { areas } => {
+                write!(f, "the walkable cells are split into {} areas", areas)
+            }
+        }
+    }
+}
+
+/// What the output has to look like, on top of fitting the sample
+pub struct Constraints<T> {
+    // Cells that have to be a given colour
+    pub fixed: Vec<(V2u, T)>,
+    // The colours the edge of the output can be, any colour at all if there are none
+    pub border: Vec<T>,
+    // Colours that have to turn up at least once
+    pub required: Vec<T>,
+    // The colours that can be walked on, for the rules below
+    pub walkable: Vec<T>,
+    // How much of the output has to be walkable at least, as a fraction of it
+    pub min_walkable: f64,
+    // Whether every walkable cell has to be reachable from every other one
+    pub connected: bool,
+}
+
+impl<T> Default for Constraints<T> {
+    fn default() -> Self {
+        Self {
+            fixed: Vec::new(),
+            border: Vec::new(),
+            required: Vec::new(),
+            walkable: Vec::new(),
+            min_walkable: 0.0,
+            connected: false,
+        }
+    }
+}
+
+impl<T: Copy + Eq> Constraints<T> {
+    /// Pins a picture, like an entrance room, into the output with its corner at the given cell
+    pub fn stamp(mut self, at: V2u, picture: &Grid<T>) -> Self {
+        for y in 0..picture.size.y {
+            for x in 0..picture.size.x {
+                self.fixed
+                    .push((at + V2u::new(x, y), picture[V2u::new(x, y)]));
             }
         }
-        let mut i = 0;
-        for _ in 0..display.size.y {
-            for _ in 0..display.size.x {
-                print!("{}, ", display.buf[i]);
-                i += 1;
+        self
+    }
+
+    /// Which of the rules on the whole output it breaks, if any
+    fn unmet(&self, output: &Grid<T>) -> Option<Unmet> {
+        let walkable =
+            |at: V2i| matches!(output.get(at), Some(colour) if self.walkable.contains(colour));
+        let cells = output.buf.len();
+        let walkable_cells = output
+            .buf
+            .iter()
+            .filter(|colour| self.walkable.contains(colour))
+            .count();
+        if (walkable_cells as f64) < self.min_walkable * cells as f64 {
+            return Some(Unmet::TooLittleWalkable {
+                walkable: walkable_cells,
+                cells,
+            });
+        }
+
+        if self.connected {
+            let mut seen = BitSet::with_capacity(cells);
+            let mut areas = 0;
+            for start in 0..cells {
+                if seen.contains(start) || !self.walkable.contains(&output.buf[start]) {
+                    continue;
+                }
+                areas += 1;
+                let mut next = vec![output.to_2d_index(start as isize).unwrap()];
+                while let Some(at) = next.pop() {
+                    if !walkable(at) || !seen.insert(output.to_1d_index(at).unwrap() as usize) {
+                        continue;
+                    }
+                    next.extend(CROSS_NEIGHBOURHOOD.iter().map(|&offset| at + offset));
+                }
+            }
+            if areas > 1 {
+                return Some(Unmet::Disconnected { areas });
+            }
+        }
+        None
+    }
+}
+
+impl Display for WfcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WfcError::EmptySample => write!(f, "the sample is empty"),
+            WfcError::Contradiction { at } => {
+                write!(f, "nothing fits at ({}, {}) whatever is chosen", at.x, at.y)
+            }
+            WfcError::OutOfSteps { steps, at: Some(at) } => write!(
+                f,
+                "gave up after {} steps, last stuck at ({}, {})",
+                steps, at.x, at.y
+            ),
+            WfcError::OutOfSteps { steps, at: None } => {
+                write!(f, "gave up after {} steps", steps)
             }
-            print!("\n");
+            WfcError::Unmet(unmet) => write!(f, "{}", unmet),
         }
     }
 }
@@ -231,22 +434,120 @@ impl<T> IndexMut<TileIndex> for TileMap<T> {
     fn index_mut(&mut self, index: TileIndex) -> &mut Self::Output { &mut self.0[index.0] }
 }
 
-#[allow(dead_code)]
+// How many steps, collapsing a cell or taking a collapse back, each output cell gets on average
+pub const STEPS_PER_CELL: usize = 16;
+
+/// Something that can be turned and flipped along with the sample it is part of
+pub trait Orient {
+    /// Turned a quarter clockwise, so that whatever faced north faces east
+    fn rotated(&self) -> Self;
+    /// Flipped so that east and west swap places
+    fn mirrored(&self) -> Self;
+}
+
+/// A way the sample is turned, and maybe flipped first, before patterns are taken from it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
 pub enum Orientation {
     Original,
     Clockwise90,
     Clockwise180,
     Clockwise270,
+    Mirrored,
+    MirroredClockwise90,
+    MirroredClockwise180,
+    MirroredClockwise270,
 }
 
-#[allow(unused_variables)]
-pub fn wfc<T: Copy + Eq + Hash + Debug>(
+impl Orientation {
+    /// The sample turned every way, but never flipped
+    pub fn rotations() -> Vec<Orientation> {
+        vec![
+            Orientation::Original,
+            Orientation::Clockwise90,
+            Orientation::Clockwise180,
+            Orientation::Clockwise270,
+        ]
+    }
+
+    /// The sample turned and flipped every way
+    #[allow(dead_code)]
+    pub fn all() -> Vec<Orientation> {
+        let mut all = Orientation::rotations();
+        all.extend(&[
+            Orientation::Mirrored,
+            Orientation::MirroredClockwise90,
+            Orientation::MirroredClockwise180,
+            Orientation::MirroredClockwise270,
+        ]);
+        all
+    }
+
+    fn is_mirrored(self) -> bool {
+        matches!(
+            self,
+            Orientation::Mirrored
+                | Orientation::MirroredClockwise90
+                | Orientation::MirroredClockwise180
+                | Orientation::MirroredClockwise270
+        )
+    }
+
+    /// How many quarter turns clockwise
+    fn turns(self) -> usize {
+        match self {
+            Orientation::Original | Orientation::Mirrored => 0,
+            Orientation::Clockwise90 | Orientation::MirroredClockwise90 => 1,
+            Orientation::Clockwise180 | Orientation::MirroredClockwise180 => 2,
+            Orientation::Clockwise270 | Orientation::MirroredClockwise270 => 3,
+        }
+    }
+
+    /// The grid flipped, and then turned, along with everything in it
+    pub(crate) fn apply_to<T: Orient + Copy>(self, grid: &Grid<T>) -> Grid<T> {
+        let mut result = Grid::with_capacity(grid.size);
+        for y in 0..grid.size.y {
+            for x in 0..grid.size.x {
+                result.buf.push(if self.is_mirrored() {
+                    grid[V2u::new(grid.size.x - 1 - x, y)].mirrored()
+                } else {
+                    grid[V2u::new(x, y)]
+                });
+            }
+        }
+        result.size = grid.size;
+
+        for _ in 0..self.turns() {
+            // North is towards larger y, so a quarter turn clockwise takes (x, y) to (y, -x)
+            let size = result.size;
+            let mut turned = Grid::with_capacity(V2u::new(size.y, size.x));
+            for y in 0..size.x {
+                for x in 0..size.y {
+                    turned
+                        .buf
+                        .push(result[V2u::new(size.x - 1 - y, x)].rotated());
+                }
+            }
+            turned.size = V2u::new(size.y, size.x);
+            result = turned;
+        }
+        result
+    }
+}
+
+/// Fills in an output of the given size so that every part of it looks like some part of the input.
+/// Colours can be given weights to make them more or less common than they are in the input.
+#[allow(clippy::too_many_arguments)]
+pub fn wfc<T: Copy + Eq + Hash + Debug + Orient>(
     input: Grid<T>,
     neighbourhood: &[V2i],
     output_size: V2u,
     orientations: &[Orientation],
-) -> Result<Vec<Grid<Result<T, usize>>>, String> {
-    let mut rng = StdRng::from_entropy();//seed_from_u64(1337);
+    constraints: &Constraints<T>,
+    weights: &[(T, f64)],
+    max_steps: usize,
+    seed: u64,
+) -> Result<Grid<T>, WfcError> {
+    let mut rng = StdRng::seed_from_u64(seed);
 
     let printing = false;
 
@@ -259,15 +560,47 @@ pub fn wfc<T: Copy + Eq + Hash + Debug>(
         println!("{:?}", inverse_neighbourhood);
     }
 
+    // the input turned every way it may be, one after the other
+    let samples: Vec<Grid<T>> = if orientations.is_empty() {
+        vec![input]
+    } else {
+        orientations
+            .iter()
+            .map(|orientation| orientation.apply_to(&input))
+            .collect()
+    };
+    // where each sample starts among the input indices
+    let starts: Vec<usize> = samples
+        .iter()
+        .scan(0, |start, sample| {
+            *start += sample.buf.len();
+            Some(*start - sample.buf.len())
+        })
+        .collect();
+    // the input index at an offset from another, without leaving the sample it is in
+    let input_neighbour = |input_id: usize, offset: V2i| {
+        let sample = starts.iter().rposition(|&start| start <= input_id).unwrap();
+        let index_2d = samples[sample]
+            .to_2d_index((input_id - starts[sample]) as isize)
+            .unwrap();
+        samples[sample]
+            .to_1d_index(index_2d + offset)
+            .map(|i| starts[sample] + i as usize)
+    };
+    // the colour at each point in the input
+    let input_colours: InputMap<T> =
+        InputMap(samples.iter().flat_map(|sample| sample.buf.clone()).collect());
+
     // a tile for each point in the input
     let input_tiles: InputMap<Tile<T>> = InputMap(
-        (0..input.buf.len())
+        (0..input_colours.0.len())
             .map(|i| {
-                let index_2d = input.to_2d_index(i as isize).unwrap();
                 Tile(
                     neighbourhood
                         .iter()
-                        .map(|&offset| input.get(index_2d + offset).map(|c| *c))
+                        .map(|&offset| {
+                            input_neighbour(i, offset).map(|j| input_colours[InputIndex(j)])
+                        })
                         .collect(),
                 )
             })
@@ -348,13 +681,8 @@ pub fn wfc<T: Copy + Eq + Hash + Debug>(
                             .unwrap()
                             .iter()
                             .filter_map(|&InputIndex(input_id)| {
-                                input
-                                    .to_1d_index(
-                                        input.to_2d_index(input_id as isize).unwrap() + offset,
-                                    )
-                                    .map(|input_neighbour| {
-                                        input_to_tiles[InputIndex(input_neighbour as usize)].0
-                                    })
+                                input_neighbour(input_id, offset)
+                                    .map(|neighbour| input_to_tiles[InputIndex(neighbour)].0)
                             }),
                     )
                 }));
@@ -372,13 +700,12 @@ pub fn wfc<T: Copy + Eq + Hash + Debug>(
             .collect();
 
         for i in (0..tiles.0.len()).map(|x| TileIndex(x)) {
+            // A tile can overlap itself too, like floor next to more of the same floor
             for j in (0..tiles.0.len()).map(|x| TileIndex(x)) {
-                if i != j {
-                    let a = &input_tiles[tiles[i]];
-                    let b = &input_tiles[tiles[j]];
-                    if a.is_adjacent(&inverse_neighbourhood, &intersection, *offset, b) {
-                        bit_set[i].insert(j.0);
-                    }
+                let a = &input_tiles[tiles[i]];
+                let b = &input_tiles[tiles[j]];
+                if a.is_adjacent(&inverse_neighbourhood, &intersection, *offset, b) {
+                    bit_set[i].insert(j.0);
                 }
             }
         }
@@ -389,215 +716,263 @@ pub fn wfc<T: Copy + Eq + Hash + Debug>(
 
     let tile_count = tiles.0.len();
 
-    let mut result = Vec::new();
+    if tile_count == 0 {
+        return Err(WfcError::EmptySample);
+    }
 
-    let attempts = 10;
-    for _attempt in 0..attempts {
-        let mut wave_map = Grid::new();
-        wave_map.resize(output_size, BitSet::from_iter(0..tiles.0.len()));
-        if printing {
-            for i in tiles.range() {
-                println!("tile {}", i.0);
-
-                let white = input_tiles[InputIndex(0)].0[0].clone();
-                let black = input_tiles[InputIndex(0)].0[3].clone();
-
-                let boii = move |thing| {
-                    String::from(if thing == white {
-                        "W"
-                    } else if thing == black {
-                        "b"
-                    } else {
-                        "."
-                    })
-                };
+    if printing {
+        for i in tiles.range() {
+            println!("tile {}", i.0);
 
-                input_tiles[tiles[i]].print(&neighbourhood, boii);
-                // println!("{:?}", input_tiles[tiles[i]]);
-            }
-            println!("{:?}, ", neighbourhood);
-            for (offset, _) in adjacencies
+            let white = input_tiles[InputIndex(0)].0[0].clone();
+            let black = input_tiles[InputIndex(0)].0[3].clone();
+
+            let boii = move |thing| {
+                String::from(if thing == white {
+                    "W"
+                } else if thing == black {
+                    "b"
+                } else {
+                    "."
+                })
+            };
+
+            input_tiles[tiles[i]].print(&neighbourhood, boii);
+        }
+        println!("{:?}, ", neighbourhood);
+        for (offset, _) in adjacencies
+            .iter()
+            .sorted_by(|(a, _), (b, _)| a.y.cmp(&b.y).then_with(|| a.x.cmp(&b.x)))
+        {
+            print!("{:?}, ", offset);
+        }
+        println!();
+        for i in tiles.range() {
+            print!("tile: {} -> ", i.0);
+            for (_, bit_set) in adjacencies
                 .iter()
                 .sorted_by(|(a, _), (b, _)| a.y.cmp(&b.y).then_with(|| a.x.cmp(&b.x)))
             {
-                print!("{:?}, ", offset);
+                print!("{:?}, ", bit_set[i]);
             }
             println!();
-            for i in tiles.range() {
-                print!("tile: {} -> ", i.0);
-                for (_, bit_set) in adjacencies
-                    .iter()
-                    .sorted_by(|(a, _), (b, _)| a.y.cmp(&b.y).then_with(|| a.x.cmp(&b.x)))
-                {
-                    print!("{:?}, ", bit_set[i]);
-                }
-                println!();
-            }
         }
+    }
 
-        let mut entropy_hierarchy = EntropyHierarchy::new();
-        for (i, set) in wave_map.buf.iter().enumerate() {
-            entropy_hierarchy.add(i, set.len());
-        }
+    // the colour each tile puts in the output, and the tiles that put one of the given colours
+    let tile_colours: TileMap<T> =
+        TileMap(tiles_to_inputs.0.iter().map(|inputs| input_colours[inputs[0]]).collect());
+    let tiles_of = |colours: &[T]| -> BitSet {
+        tile_colours
+            .range()
+            .filter(|&i| colours.contains(&tile_colours[i]))
+            .map(|i| i.0)
+            .collect()
+    };
 
-        let mut wave_map_backup = wave_map.clone();
-        let mut entropy_hierarchy_backup = entropy_hierarchy.clone();
+    // tiles are as likely as they are common in the sample, times the weight of their colour
+    let weights = tile_colours
+        .range()
+        .map(|i| {
+            let weight = weights
+                .iter()
+                .find(|(colour, _)| *colour == tile_colours[i])
+                .map_or(1.0, |&(_, weight)| weight);
+            tiles_to_inputs[i].len() as f64 * weight.max(0.0)
+        })
+        .collect();
+    let mut wave = Wave::new(output_size, weights);
+    let to_output = |index: V2i| index.map(|e| e as usize);
+    let contradiction = |at: V2i| WfcError::Contradiction { at: to_output(at) };
+    constrain(&adjacencies, &mut wave, tile_count, V2i::new(0, 0)).map_err(contradiction)?;
 
-        if !constrain(
-            &adjacencies,
-            &mut wave_map,
-            &mut entropy_hierarchy,
-            tile_count,
-            V2i::new(0, 0),
-        ) {
-            return Err(String::from(
-                "input can't be used to generate a valid output",
-            ));
-        }
-        if printing {
-            println!("wave map start: {:?}", wave_map);
-        }
-        let (mut backtracks, mut successes) = (0, 0);
-
-        while !entropy_hierarchy.is_converged() {
-            // collapse superposition
-            let (least_entropy, collapsing_output_index) =
-                entropy_hierarchy.get_lowest_entropy(&mut rng);
-            // println!(
-            //     "collapsing: {:?} {:?} with entropy {}",
-            //     to_2d_index(collapse_index as isize, wave_map.size.x),
-            //     collapse_index,
-            //     least_entropy
-            // );
-            if printing {
-                println!("---------- iteration ----------");
-                println!("point: {}", collapsing_output_index);
-                print!("choosing tile: ");
+    // Pinned cells come first, so that everything else is collapsed around them
+    if !constraints.border.is_empty() {
+        let allowed = tiles_of(&constraints.border);
+        for y in 0..output_size.y {
+            for x in 0..output_size.x {
+                if x == 0 || y == 0 || x + 1 == output_size.x || y + 1 == output_size.y {
+                    let at = V2i::new(x as isize, y as isize);
+                    restrict(&adjacencies, &mut wave, tile_count, at, &allowed)
+                        .map_err(contradiction)?;
+                }
             }
-            let chosen_tile = wave_map.buf[collapsing_output_index]
-                .iter()
-                .choose(&mut rng)
-                .map(|x| TileIndex(x))
-                .unwrap();
-            if printing {
-                println!();
+        }
+    }
+    for &(at, colour) in &constraints.fixed {
+        let at = at.map(|e| e as isize);
+        if wave.cells.get(at).is_some() {
+            restrict(&adjacencies, &mut wave, tile_count, at, &tiles_of(&[colour]))
+                .map_err(contradiction)?;
+        }
+    }
+    // Required colours go wherever they still can, trying one cell after another
+    for (i, &colour) in constraints.required.iter().enumerate() {
+        let allowed = tiles_of(&[colour]);
+        let mut candidates = (0..wave.cells.buf.len())
+            .filter(|&cell| !wave.cells.buf[cell].is_disjoint(&allowed))
+            .collect::<Vec<_>>();
+        candidates.shuffle(&mut rng);
+        let placed = candidates.into_iter().any(|cell| {
+            let trail_length = wave.trail.len();
+            let at = wave.cells.to_2d_index(cell as isize).unwrap();
+            let placed = restrict(&adjacencies, &mut wave, tile_count, at, &allowed).is_ok();
+            if !placed {
+                wave.undo(trail_length);
             }
-            entropy_hierarchy.reduce_entropy(collapsing_output_index, least_entropy, 1);
-            wave_map.buf[collapsing_output_index].clear();
-            wave_map.buf[collapsing_output_index].insert(chosen_tile.0);
+            placed
+        });
+        if !placed {
+            return Err(WfcError::Unmet(Unmet::Required(i)));
+        }
+    }
+    let seeded = wave.trail.len();
+    if printing {
+        println!("wave map start: {:?}", wave.cells);
+    }
 
+    // Every collapse is remembered, so that a contradiction can be undone one decision at a time
+    let mut decisions: Vec<Decision> = Vec::new();
+    let (mut steps, mut backtracks) = (0, 0);
+    let mut last_contradiction = None;
+    let mut last_unmet = None;
+    let give_up = |steps, at, unmet: Option<Unmet>| match unmet {
+        Some(unmet) => WfcError::Unmet(unmet),
+        None => WfcError::OutOfSteps { steps, at },
+    };
+    loop {
+        let steps_before = steps;
+        while let Some(cell) = wave.entropy_hierarchy.get_lowest_entropy(&mut rng) {
+            if steps >= max_steps {
+                return Err(give_up(steps, last_contradiction, last_unmet));
+            }
+            steps += 1;
+            let tile = wave.choose(cell, &mut rng);
             if printing {
-                println!("tile: {}", chosen_tile.0);
-                println!("chosen tile: {:?}", input_tiles[tiles[chosen_tile]].0);
-                println!("wave map before constraints: {:?}", wave_map);
-                //entropy_hierarchy.print(&wave_map);
+                println!("---------- iteration ----------");
+                println!("point: {}, tile: {}", cell, tile);
             }
+            decisions.push(Decision {
+                cell,
+                tile,
+                trail_length: wave.trail.len(),
+            });
+            wave.set(cell, BitSet::from_iter(Some(tile)));
+            let index_2d = wave.cells.to_2d_index(cell as isize).unwrap();
+            let mut constrained = constrain(&adjacencies, &mut wave, tile_count, index_2d);
 
-            // reduce entropy
-            let index_2d = wave_map
-                .to_2d_index(collapsing_output_index as isize)
-                .unwrap();
-
-            if !constrain(
-                &adjacencies,
-                &mut wave_map,
-                &mut entropy_hierarchy,
-                tile_count,
-                index_2d,
-            ) {
-                backtracks += 1;
-                wave_map_backup.buf[collapsing_output_index].remove(chosen_tile.0);
-                entropy_hierarchy_backup.reduce_entropy(
-                    collapsing_output_index,
-                    least_entropy,
-                    least_entropy - 1,
-                );
-                wave_map = wave_map_backup.clone();
-                entropy_hierarchy = entropy_hierarchy_backup.clone();
-            } else {
-                successes += 1;
-                if printing {
-                    println!("wave map after constraints: {:?}", wave_map);
+            // Take back the latest decision and rule out what it chose,
+            // and if that leaves nothing either, the one before it
+            while let Err(at) = constrained {
+                let at = to_output(at);
+                last_contradiction = Some(at);
+                let decision = decisions.pop().ok_or(WfcError::Contradiction { at })?;
+                if steps >= max_steps {
+                    return Err(give_up(steps, last_contradiction, last_unmet));
                 }
-                entropy_hierarchy.cleanup();
+                steps += 1;
+                backtracks += 1;
 
-                wave_map_backup = wave_map.clone();
-                entropy_hierarchy_backup = entropy_hierarchy.clone();
+                wave.undo(decision.trail_length);
+                let mut remaining = wave.cells.buf[decision.cell].clone();
+                remaining.remove(decision.tile);
+                let index_2d = wave.cells.to_2d_index(decision.cell as isize).unwrap();
+                constrained = if remaining.is_empty() {
+                    Err(index_2d)
+                } else {
+                    wave.set(decision.cell, remaining);
+                    constrain(&adjacencies, &mut wave, tile_count, index_2d)
+                };
             }
         }
-        println!("backtracks: {}, successes: {}", backtracks, successes);
+        if printing {
+            println!("steps: {}, backtracks: {}", steps, backtracks);
+        }
+
+        let mut image = Grid::with_capacity(output_size);
+        image.buf.extend(
+            wave.cells
+                .buf
+                .iter()
+                .map(|set| tile_colours[TileIndex(set.iter().next().unwrap())]),
+        );
+        image.size = output_size;
 
-        let mut image = unsafe { Grid::uninitialized_with_capacity(output_size) };
-        for (i, set) in wave_map.buf.iter().enumerate() {
-            image.buf[i] = (set.len() == 1)
-                .then(|| input.buf[tiles_to_inputs[TileIndex(set.iter().next().unwrap())][0].0])
-                .ok_or(set.len());
+        // What the whole output has to be like can only be told once it is done,
+        // so an output that falls short is started over from the pinned cells
+        match constraints.unmet(&image) {
+            None => return Ok(image),
+            // There was nothing left to decide, so it would come out the same again
+            Some(unmet) if steps == steps_before => return Err(WfcError::Unmet(unmet)),
+            Some(unmet) => last_unmet = Some(unmet),
         }
-        result.push(image);
-        break;
-        //result.push(to_image(&input, output_size, &wave_map));
+        wave.undo(seeded);
+        decisions.clear();
+    }
+}
+
+/// Narrows a cell down to the allowed tiles, and everything around it to match
+fn restrict(
+    adjacencies: &HashMap<V2i, TileMap<BitSet>>,
+    wave: &mut Wave,
+    tile_count: usize,
+    at: V2i,
+    allowed: &BitSet,
+) -> Result<(), V2i> {
+    let cell = wave.cells.to_1d_index(at).unwrap() as usize;
+    if wave.cells.buf[cell].is_subset(allowed) {
+        return Ok(());
+    }
+    let tiles = wave.cells.buf[cell].intersection(allowed).collect::<BitSet>();
+    if tiles.is_empty() {
+        return Err(at);
     }
-    return Ok(result);
+    wave.set(cell, tiles);
+    constrain(adjacencies, wave, tile_count, at)
 }
 
+/// Rules out whatever no longer fits next to the cell, and next to the cells that changed,
+/// until nothing changes any more. Fails with the cell that was left with nothing at all.
 fn constrain(
     adjacencies: &HashMap<V2i, TileMap<BitSet>>,
-    wave_map: &mut Grid<BitSet>,
-    entropy_hierarchy: &mut EntropyHierarchy,
+    wave: &mut Wave,
     tile_count: usize,
     constrainee: V2i,
-) -> bool {
-    // let mut entropy_map = HashMap::new();
+) -> Result<(), V2i> {
     let mut stek = HashSet::new();
     stek.insert(constrainee);
     while let Some(constrainee) = stek.iter().next().map(|a| *a).and_then(|c| stek.take(&c)) {
-        //while let Some(constrainee) = stack.pop() {
         for (&offset, constraint) in adjacencies.iter() {
             let neighbour = constrainee + offset;
-            if let Some(inner_index) = wave_map.to_1d_index(neighbour) {
-                let reducer = wave_map.get(constrainee).unwrap().iter().fold(
+            if let Some(inner_index) = wave.cells.to_1d_index(neighbour) {
+                let reducer = wave.cells.get(constrainee).unwrap().iter().fold(
                     BitSet::with_capacity(tile_count),
                     |mut result, t| {
                         result.union_with(&constraint[TileIndex(t)]);
                         return result;
                     },
                 );
-                if reducer.is_empty() {
-                    //println!("empty reducer");
-                    return false;
+                let set_to_reduce = &wave.cells.buf[inner_index as usize];
+                if set_to_reduce.is_subset(&reducer) {
+                    continue;
                 }
-                let set_to_reduce = wave_map.get_mut(neighbour).unwrap();
-                let original = set_to_reduce.len();
-                set_to_reduce.intersect_with(&reducer);
-                if original != set_to_reduce.len() {
-                    // entropy_map
-                    //     .entry(inner_index)
-                    //     .or_insert((original, set_to_reduce.len()))
-                    //     .apply(|(_, b)| {
-                    //         *b = set_to_reduce.len();
-                    //     });
-                    entropy_hierarchy.reduce_entropy(
-                        inner_index as usize,
-                        original,
-                        set_to_reduce.len(),
-                    );
-                    // println!("wave map during constraints: {:?}", wave_map);
-                    stek.insert(neighbour);
+                let reduced = set_to_reduce.intersection(&reducer).collect::<BitSet>();
+                if reduced.is_empty() {
+                    return Err(neighbour);
                 }
+                wave.set(inner_index as usize, reduced);
+                stek.insert(neighbour);
             }
         }
     }
-    // entropy_map.iter().for_each(|(&a, &b)| {
-    //     entropy_hierarchy.reduce_entropy(a as usize, b.0, b.1);
-    // });
-    return true;
+    Ok(())
 }
 
 pub fn test() {
     use std::time::Instant;
 
     use cgmath::Array;
-    use image::{Rgb, RgbImage};
+    use image::RgbImage;
     let timer = Instant::now();
     // let pic = image::open("oliprik.png").unwrap();
     let pic = image::open("assets/Images/dungeon_5_separated.png").unwrap();
@@ -607,27 +982,17 @@ pub fn test() {
         &SQUARE_NEIGHBOURHOOD,
         size,
         &[Orientation::Original, Orientation::Clockwise180],
+        &Constraints::default(),
+        &[],
+        size.x * size.y * STEPS_PER_CELL,
+        1337,
     );
     match master {
-        Result::Ok(res) => {
-            for (i, master) in res.iter().enumerate() {
-                let mut other: Grid<Rgb<u8>> = Grid::new();
-                other.size = master.size;
-                other.buf = master
-                    .buf
-                    .iter()
-                    .map(|pix| match pix {
-                        Ok(col) => *col,
-                        Err(0) => Rgb([255, 0, 0]),
-                        Err(n) => Rgb([0, (*n % 256) as u8, (128 + (*n / 256) * 8) as u8]),
-                    })
-                    .collect();
-
-                RgbImage::from(&other)
-                    .save("maps/WFC.png")
-                    .unwrap();
-                println!("saved result to WFC.png");
-            }
+        Result::Ok(master) => {
+            // Not into maps/, where the sample map is read from by other tests at the same time
+            let path = std::env::temp_dir().join("WFC.png");
+            RgbImage::from(&master).save(&path).unwrap();
+            println!("saved result to {}", path.display());
         }
         Result::Err(err) => println!("Error! {}", err),
     }
@@ -636,12 +1001,224 @@ pub fn test() {
 
 #[cfg(test)]
 pub mod tests {
+    use std::iter::FromIterator;
+
+    use bit_set::BitSet;
+    use rand::prelude::*;
 
+    use crate::world_gen::grid::{Grid, V2i, V2u};
     #[allow(unused_imports)]
     use crate::world_gen::wfc::{
         wfc, Orientation, SMALL_SQUARE_NEIGHBOURHOOD, SQUARE_NEIGHBOURHOOD,
     };
+    use crate::world_gen::wfc::{Constraints, Orient, Unmet, Wave, WfcError};
+
+    impl Orient for bool {
+        fn rotated(&self) -> Self { *self }
+        fn mirrored(&self) -> Self { *self }
+    }
+
+    impl Orient for char {
+        fn rotated(&self) -> Self { *self }
+        fn mirrored(&self) -> Self { *self }
+    }
+
+    /// Reads a sample drawn with one character per cell
+    fn sample(rows: &[&str]) -> Grid<char> {
+        let mut grid = Grid::new();
+        grid.resize(V2u::new(rows[0].len(), rows.len()), ' ');
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                grid[V2u::new(x, y)] = c;
+            }
+        }
+        grid
+    }
+
+    fn rooms() -> Grid<char> {
+        sample(&[
+            "##########",
+            "#..#.....#",
+            "#..#.....#",
+            "#........#",
+            "####..####",
+            "#........#",
+            "#..#.....#",
+            "##########",
+        ])
+    }
+
+    fn checkerboard(size: usize) -> Grid<bool> {
+        let mut grid = Grid::new();
+        grid.resize(V2u::new(size, size), false);
+        for (i, cell) in grid.buf.iter_mut().enumerate() {
+            *cell = (i % size + i / size) % 2 == 0;
+        }
+        grid
+    }
 
     #[test]
     pub fn test() { crate::world_gen::wfc::test(); }
+
+    #[test]
+    fn every_cell_fits_its_neighbours() {
+        let output = wfc(
+            checkerboard(4),
+            &SQUARE_NEIGHBOURHOOD,
+            V2u::new(20, 15),
+            &Orientation::all(),
+            &Constraints::default(),
+            &[],
+            10_000,
+            3,
+        )
+        .unwrap();
+        assert_eq!(output.size, V2u::new(20, 15));
+        for y in 0..15 {
+            for x in 1..20 {
+                assert_ne!(output[V2u::new(x, y)], output[V2u::new(x - 1, y)]);
+            }
+        }
+    }
+
+    #[test]
+    fn constraints_are_kept() {
+        let constraints = Constraints {
+            border: vec!['#'],
+            walkable: vec!['.'],
+            min_walkable: 0.3,
+            connected: true,
+            ..Constraints::default()
+        }
+        .stamp(V2u::new(6, 5), &sample(&["...", "..."]));
+        let output = wfc(
+            rooms(),
+            &SQUARE_NEIGHBOURHOOD,
+            V2u::new(18, 14),
+            &Orientation::rotations(),
+            &constraints,
+            &[],
+            100_000,
+            5,
+        )
+        .unwrap();
+
+        for y in 0..14 {
+            for x in 0..18 {
+                if x == 0 || y == 0 || x == 17 || y == 13 {
+                    assert_eq!(output[V2u::new(x, y)], '#');
+                }
+            }
+        }
+        assert_eq!(output[V2u::new(6, 5)], '.');
+        assert_eq!(output[V2u::new(8, 6)], '.');
+        assert!(output.buf.iter().filter(|&&c| c == '.').count() as f64 >= 0.3 * 18.0 * 14.0);
+        assert_eq!(constraints.unmet(&output), None);
+    }
+
+    #[test]
+    fn impossible_constraints_are_errors() {
+        let wfc_with = |constraints: Constraints<char>| {
+            wfc(
+                rooms(),
+                &SQUARE_NEIGHBOURHOOD,
+                V2u::new(10, 10),
+                &[],
+                &constraints,
+                &[],
+                20_000,
+                5,
+            )
+        };
+        assert_eq!(
+            wfc_with(Constraints {
+                required: vec!['#', '>'],
+                ..Constraints::default()
+            })
+            .unwrap_err(),
+            WfcError::Unmet(Unmet::Required(1))
+        );
+        assert!(matches!(
+            wfc_with(Constraints {
+                border: vec!['#'],
+                walkable: vec!['.'],
+                min_walkable: 0.9,
+                ..Constraints::default()
+            }),
+            Err(WfcError::Unmet(Unmet::TooLittleWalkable { cells: 100, .. }))
+        ));
+        assert!(matches!(
+            wfc_with(Constraints::default().stamp(V2u::new(0, 0), &sample(&["#.", ".#"]))),
+            Err(WfcError::Contradiction { .. })
+        ));
+    }
+
+    #[test]
+    fn common_colours_come_out_more_often() {
+        // With nothing but the cell itself in the neighbourhood, cells are picked by weight alone
+        let count = |weights: &[(char, f64)]| {
+            let output = wfc(
+                sample(&["aaaaaaaaab"]),
+                &[V2i::new(0, 0)],
+                V2u::new(20, 20),
+                &[],
+                &Constraints::default(),
+                weights,
+                1_000,
+                9,
+            )
+            .unwrap();
+            output.buf.iter().filter(|&&c| c == 'b').count()
+        };
+        assert!((10..80).contains(&count(&[])));
+        assert!((140..260).contains(&count(&[('b', 9.0)])));
+        assert_eq!(count(&[('b', 0.0)]), 0);
+    }
+
+    #[test]
+    fn undoing_puts_back_what_changed() {
+        let mut wave = Wave::new(V2u::new(3, 1), vec![1.0; 4]);
+        wave.set(0, BitSet::from_iter(Some(2)));
+        let trail_length = wave.trail.len();
+        wave.set(1, BitSet::from_iter(vec![1, 3]));
+        wave.set(1, BitSet::from_iter(Some(3)));
+        wave.set(2, BitSet::from_iter(Some(3)));
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(wave.entropy_hierarchy.get_lowest_entropy(&mut rng), None);
+
+        wave.undo(trail_length);
+        assert_eq!(wave.cells.buf[0], BitSet::from_iter(Some(2)));
+        assert_eq!(wave.cells.buf[1], BitSet::from_iter(0..4));
+        // Only the undecided cells have an entropy
+        let entropy = 4f64.ln().to_bits();
+        assert_eq!(wave.entropy_hierarchy.hierarchy[&entropy], BitSet::from_iter(1..3));
+        assert_eq!(wave.entropy_hierarchy.hierarchy.len(), 1);
+    }
+
+    #[test]
+    fn running_out_of_steps_is_an_error() {
+        let result = wfc(
+            checkerboard(4),
+            &SQUARE_NEIGHBOURHOOD,
+            V2u::new(20, 20),
+            &[],
+            &Constraints::default(),
+            &[],
+            0,
+            3,
+        );
+        assert_eq!(result.unwrap_err(), WfcError::OutOfSteps { steps: 0, at: None });
+
+        let empty = wfc(
+            Grid::<bool>::new(),
+            &SQUARE_NEIGHBOURHOOD,
+            V2u::new(4, 4),
+            &[],
+            &Constraints::default(),
+            &[],
+            100,
+            3,
+        );
+        assert_eq!(empty.unwrap_err(), WfcError::EmptySample);
+    }
 }